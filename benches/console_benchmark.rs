@@ -2,21 +2,19 @@
 extern crate criterion;
 extern crate ludus;
 
-use criterion::Criterion;
 use criterion::black_box;
+use criterion::Criterion;
 use ludus::*;
 
 #[derive(Clone, Copy)]
 pub struct NullDevice;
 
 impl AudioDevice for NullDevice {
-    fn push_sample(&mut self, _sample: f32) {
-    }
+    fn push_sample(&mut self, _sample: f32) {}
 }
 
 impl VideoDevice for NullDevice {
-    fn blit_pixels(&mut self, _pixels: &PixelBuffer) {
-    }
+    fn blit_pixels(&mut self, _pixels: &PixelBuffer) {}
 }
 
 fn step_frame(console: &mut Console) {
@@ -33,4 +31,4 @@ fn criterion_benchmark(c: &mut Criterion) {
 }
 
 criterion_group!(benches, criterion_benchmark);
-criterion_main!(benches);
\ No newline at end of file
+criterion_main!(benches);
@@ -1,7 +1,51 @@
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
 use super::memory::MemoryBus;
 
-use core::f32::consts::PI;
 use crate::ports::AudioDevice;
+use crate::region::Region;
+use crate::state::{StateError, StateReader, StateWriter};
+use core::f32::consts::PI;
+
+/// A write to one of the APU registers, at $4000-$4013, $4015, or $4017.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ApuWrite {
+    /// The number of CPU cycles run since power on, before the instruction
+    /// doing the write
+    pub cycle: u64,
+    /// The register written to
+    pub address: u16,
+    /// The value written
+    pub value: u8,
+}
+
+/// Keeps the last few writes to APU registers, dropping older ones.
+pub(crate) struct ApuLog {
+    writes: VecDeque<ApuWrite>,
+    capacity: usize,
+}
+
+impl ApuLog {
+    pub fn new(capacity: usize) -> Self {
+        ApuLog {
+            writes: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, write: ApuWrite) {
+        if self.writes.len() >= self.capacity {
+            self.writes.pop_front();
+        }
+        self.writes.push_back(write);
+    }
+
+    /// Removes the writes logged so far, returning them from oldest to newest
+    pub fn take(&mut self) -> Vec<ApuWrite> {
+        self.writes.drain(..).collect()
+    }
+}
 
 const LENGTH_TABLE: [u8; 32] = [
     10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
@@ -28,22 +72,27 @@ const DMC_TABLE: [u8; 16] = [
     214, 190, 170, 160, 143, 127, 113, 107, 95, 80, 71, 64, 53, 42, 36, 27,
 ];
 
-/// Constructs a new tnd table
-fn make_pulse_table() -> [f32; 31] {
-    let mut arr = [0.0; 31];
-    for (i, item) in arr.iter_mut().enumerate() {
-        *item = 95.52 / (8128.0 / (i as f32) + 100.0);
-    }
-    arr
-}
-
-/// Constructs a new pulse table
-fn make_tnd_table() -> [f32; 203] {
-    let mut arr = [0.0; 203];
-    for (i, item) in arr.iter_mut().enumerate() {
-        *item = 163.67 / (24329.0 / (i as f32) + 100.0);
-    }
-    arr
+/// Mixes the output levels of each channel together.
+///
+/// The channels aren't mixed linearly: the two pulse channels are mixed
+/// together, and so are the triangle, noise, and DMC channels, with each
+/// group getting louder more slowly as its channels add up. The output
+/// is between 0 and 1, and gets centered by the filters afterwards.
+/// See: https://wiki.nesdev.com/w/index.php/APU_Mixer
+pub(crate) fn mix(p1: u8, p2: u8, t: u8, n: u8, d: u8) -> f32 {
+    let pulse = f32::from(p1 + p2);
+    let pulse_out = if pulse == 0.0 {
+        0.0
+    } else {
+        95.88 / (8128.0 / pulse + 100.0)
+    };
+    let tnd = f32::from(t) / 8227.0 + f32::from(n) / 12241.0 + f32::from(d) / 22638.0;
+    let tnd_out = if tnd == 0.0 {
+        0.0
+    } else {
+        159.79 / (1.0 / tnd + 100.0)
+    };
+    pulse_out + tnd_out
 }
 
 /// Represents a first order filter, implementing the following formula:
@@ -95,29 +144,77 @@ impl Filter {
         self.prev_x = x;
         y
     }
+
+    fn save(&self, w: &mut StateWriter) {
+        w.f32(self.prev_x);
+        w.f32(self.prev_y);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.prev_x = r.f32()?;
+        self.prev_y = r.f32()?;
+        Ok(())
+    }
+}
+
+/// The cutoff frequencies, in Hz, of the filters applied to the audio.
+///
+/// The defaults are those of the filters on the NES itself: two
+/// first-order high pass filters, followed by a first-order low pass.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AudioFilter {
+    /// The cutoff of the first high pass filter
+    pub high_pass1: f32,
+    /// The cutoff of the second high pass filter
+    pub high_pass2: f32,
+    /// The cutoff of the low pass filter
+    pub low_pass: f32,
+}
+
+impl Default for AudioFilter {
+    fn default() -> Self {
+        AudioFilter {
+            high_pass1: 90.0,
+            high_pass2: 440.0,
+            low_pass: 14000.0,
+        }
+    }
 }
 
 /// Represents the collection of filters applied to the output of the APU
-struct FilterChain {
+pub(crate) struct FilterChain {
     high1: Filter,
     high2: Filter,
     low: Filter,
 }
 
 impl FilterChain {
-    fn new(sample_rate: u32) -> Self {
+    pub fn new(sample_rate: u32, cutoffs: AudioFilter) -> Self {
         FilterChain {
-            high1: Filter::high_pass(sample_rate, 90.0),
-            high2: Filter::high_pass(sample_rate, 440.0),
-            low: Filter::low_pass(sample_rate, 14000.0),
+            high1: Filter::high_pass(sample_rate, cutoffs.high_pass1),
+            high2: Filter::high_pass(sample_rate, cutoffs.high_pass2),
+            low: Filter::low_pass(sample_rate, cutoffs.low_pass),
         }
     }
 
-    fn step(&mut self, x: f32) -> f32 {
+    pub fn step(&mut self, x: f32) -> f32 {
         let x1 = self.high1.step(x);
         let x2 = self.high2.step(x1);
         self.low.step(x2)
     }
+
+    fn save(&self, w: &mut StateWriter) {
+        self.high1.save(w);
+        self.high2.save(w);
+        self.low.save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.high1.load(r)?;
+        self.high2.load(r)?;
+        self.low.load(r)?;
+        Ok(())
+    }
 }
 
 /// Represents the Square signal generator of the APU
@@ -203,7 +300,7 @@ impl Square {
 
     fn write_sweep(&mut self, value: u8) {
         self.sweep_enabled = (value >> 7) & 1 == 1;
-        self.sweep_period = ((value >> 4) & 7) + 1;
+        self.sweep_period = (value >> 4) & 7;
         self.sweep_negate = (value >> 3) & 1 == 1;
         self.sweep_shift = value & 7;
         self.sweep_reload = true;
@@ -214,7 +311,10 @@ impl Square {
     }
 
     fn write_high_timer(&mut self, value: u8) {
-        self.length_value = LENGTH_TABLE[(value >> 3) as usize];
+        // Disabled channels keep their length counter at 0
+        if self.enabled {
+            self.length_value = LENGTH_TABLE[(value >> 3) as usize];
+        }
         let shifted = u16::from(value & 7) << 8;
         self.timer_period = (self.timer_period & 0xFF) | shifted;
         self.envelope_start = true;
@@ -247,32 +347,36 @@ impl Square {
         }
     }
 
-    fn sweep(&mut self) {
+    /// Returns the period the sweep unit is moving towards.
+    ///
+    /// The first channel negates with one's complement, subtracting one more.
+    fn sweep_target(&self) -> u16 {
         let delta = self.timer_period >> self.sweep_shift;
         if self.sweep_negate {
-            self.timer_period -= delta;
-            if self.first_channel {
-                self.timer_period -= 1;
-            }
+            let extra = u16::from(self.first_channel);
+            self.timer_period.saturating_sub(delta + extra)
         } else {
-            self.timer_period += delta;
+            self.timer_period + delta
         }
     }
 
+    /// Returns true if the sweep unit silences the channel.
+    ///
+    /// This happens even when sweeps are disabled.
+    fn sweep_muted(&self) -> bool {
+        self.timer_period < 8 || self.sweep_target() > 0x7FF
+    }
+
     fn step_sweep(&mut self) {
-        if self.sweep_reload {
-            if self.sweep_enabled && self.sweep_value == 0 {
-                self.sweep();
-            }
+        let shifting = self.sweep_enabled && self.sweep_shift > 0;
+        if self.sweep_value == 0 && shifting && !self.sweep_muted() {
+            self.timer_period = self.sweep_target();
+        }
+        if self.sweep_value == 0 || self.sweep_reload {
             self.sweep_value = self.sweep_period;
             self.sweep_reload = false;
-        } else if self.sweep_value > 0 {
-            self.sweep_value -= 1;
         } else {
-            if self.sweep_enabled {
-                self.sweep();
-            }
-            self.sweep_value = self.sweep_period;
+            self.sweep_value -= 1;
         }
     }
 
@@ -293,7 +397,7 @@ impl Square {
         if DUTY_TABLE[i1][i2] == 0 {
             return 0;
         }
-        if self.timer_period < 8 || self.timer_period > 0x7FF {
+        if self.sweep_muted() {
             return 0;
         }
         if self.envelope_enabled {
@@ -302,6 +406,53 @@ impl Square {
             self.constant_volume
         }
     }
+
+    fn save(&self, w: &mut StateWriter) {
+        w.bool(self.enabled);
+        w.bool(self.length_enabled);
+        w.u8(self.length_value);
+        w.u16(self.timer_period);
+        w.u16(self.timer_value);
+        w.u8(self.duty_mode);
+        w.u8(self.duty_value);
+        w.bool(self.sweep_reload);
+        w.bool(self.sweep_enabled);
+        w.bool(self.sweep_negate);
+        w.u8(self.sweep_shift);
+        w.u8(self.sweep_period);
+        w.u8(self.sweep_value);
+        w.bool(self.envelope_enabled);
+        w.bool(self.envelope_loop);
+        w.bool(self.envelope_start);
+        w.u8(self.envelope_period);
+        w.u8(self.envelope_value);
+        w.u8(self.envelope_volume);
+        w.u8(self.constant_volume);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.enabled = r.bool()?;
+        self.length_enabled = r.bool()?;
+        self.length_value = r.u8()?;
+        self.timer_period = r.u16()? & 0x7FF;
+        self.timer_value = r.u16()? & 0x7FF;
+        self.duty_mode = r.u8()? & 3;
+        self.duty_value = r.u8()? % 8;
+        self.sweep_reload = r.bool()?;
+        self.sweep_enabled = r.bool()?;
+        self.sweep_negate = r.bool()?;
+        self.sweep_shift = r.u8()? & 7;
+        self.sweep_period = r.u8()? & 7;
+        self.sweep_value = r.u8()? & 7;
+        self.envelope_enabled = r.bool()?;
+        self.envelope_loop = r.bool()?;
+        self.envelope_start = r.bool()?;
+        self.envelope_period = r.u8()? & 0xF;
+        self.envelope_value = r.u8()? & 0xF;
+        self.envelope_volume = r.u8()? & 0xF;
+        self.constant_volume = r.u8()? & 0xF;
+        Ok(())
+    }
 }
 
 /// Represents the triangle signal simulator
@@ -351,7 +502,9 @@ impl Triangle {
     }
 
     fn write_high_timer(&mut self, value: u8) {
-        self.length_value = LENGTH_TABLE[(value >> 3) as usize];
+        if self.enabled {
+            self.length_value = LENGTH_TABLE[(value >> 3) as usize];
+        }
         let high = u16::from(value & 7) << 8;
         self.timer_period = (self.timer_period & 0xFF) | high;
         self.timer_value = self.timer_period;
@@ -393,6 +546,31 @@ impl Triangle {
             TRIANGLE_TABLE[self.duty_value as usize]
         }
     }
+
+    fn save(&self, w: &mut StateWriter) {
+        w.bool(self.enabled);
+        w.bool(self.length_enabled);
+        w.u8(self.length_value);
+        w.u16(self.timer_period);
+        w.u16(self.timer_value);
+        w.u8(self.duty_value);
+        w.u8(self.counter_period);
+        w.u8(self.counter_value);
+        w.bool(self.counter_reload);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.enabled = r.bool()?;
+        self.length_enabled = r.bool()?;
+        self.length_value = r.u8()?;
+        self.timer_period = r.u16()? & 0x7FF;
+        self.timer_value = r.u16()? & 0x7FF;
+        self.duty_value = r.u8()? % 32;
+        self.counter_period = r.u8()? & 0x7F;
+        self.counter_value = r.u8()? & 0x7F;
+        self.counter_reload = r.bool()?;
+        Ok(())
+    }
 }
 
 /// Represents the noise signal generator
@@ -461,7 +639,9 @@ impl Noise {
     }
 
     fn write_length(&mut self, value: u8) {
-        self.length_value = LENGTH_TABLE[(value >> 3) as usize];
+        if self.enabled {
+            self.length_value = LENGTH_TABLE[(value >> 3) as usize];
+        }
         self.envelope_start = true;
     }
 
@@ -510,13 +690,51 @@ impl Noise {
             self.constant_volume
         }
     }
+
+    fn save(&self, w: &mut StateWriter) {
+        w.bool(self.enabled);
+        w.bool(self.mode);
+        w.u16(self.shift_register);
+        w.bool(self.length_enabled);
+        w.u8(self.length_value);
+        w.u16(self.timer_period);
+        w.u16(self.timer_value);
+        w.bool(self.envelope_enabled);
+        w.bool(self.envelope_loop);
+        w.bool(self.envelope_start);
+        w.u8(self.envelope_period);
+        w.u8(self.envelope_value);
+        w.u8(self.envelope_volume);
+        w.u8(self.constant_volume);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.enabled = r.bool()?;
+        self.mode = r.bool()?;
+        self.shift_register = r.u16()?;
+        self.length_enabled = r.bool()?;
+        self.length_value = r.u8()?;
+        self.timer_period = r.u16()?;
+        self.timer_value = r.u16()?;
+        self.envelope_enabled = r.bool()?;
+        self.envelope_loop = r.bool()?;
+        self.envelope_start = r.bool()?;
+        self.envelope_period = r.u8()? & 0xF;
+        self.envelope_value = r.u8()? & 0xF;
+        self.envelope_volume = r.u8()? & 0xF;
+        self.constant_volume = r.u8()? & 0xF;
+        Ok(())
+    }
 }
 
 /// Generator for DMC Samples
+///
+/// Samples are read from CPU memory a byte at a time, and each bit
+/// moves the output level up or down by 2.
+///
+/// More info: https://wiki.nesdev.com/w/index.php/APU_DMC
 struct DMC {
-    /// Whether or not output is enabled for this generator
-    enabled: bool,
-    /// Current output value
+    /// Current output value, between 0 and 127
     value: u8,
     /// The address of the current sample
     sample_address: u16,
@@ -526,39 +744,52 @@ struct DMC {
     current_address: u16,
     /// The current length left to read
     current_length: u16,
-    /// Contains the value of shifts for effects
+    /// The byte read ahead of the output, if any
+    buffer: Option<u8>,
+    /// The bits being output, starting from the lowest
     shift_register: u8,
+    /// The number of bits left to output from the shift register
     bit_count: u8,
+    /// Whether or not the current byte is silent, because the buffer
+    /// was empty when it started
+    silence: bool,
     /// The point at which the tick resets
     tick_period: u8,
     /// The current value of the tick
     tick_value: u8,
     /// Whether or not to loop back at the end of a sound cycle
     do_loop: bool,
-    /// Whether or not an irq ocurred
-    irq: bool,
+    /// Whether or not to raise an IRQ at the end of a sample
+    irq_enabled: bool,
+    /// Whether or not the IRQ was raised, and hasn't been acknowledged
+    interrupt: bool,
 }
 
 impl DMC {
     fn new() -> Self {
         DMC {
-            enabled: false,
             value: 0,
-            sample_address: 0,
-            sample_length: 0,
+            sample_address: 0xC000,
+            sample_length: 1,
             current_address: 0,
             current_length: 0,
+            buffer: None,
             shift_register: 0,
-            bit_count: 0,
-            tick_period: 0,
+            bit_count: 8,
+            silence: true,
+            tick_period: DMC_TABLE[0],
             tick_value: 0,
             do_loop: false,
-            irq: false,
+            irq_enabled: false,
+            interrupt: false,
         }
     }
 
     fn write_control(&mut self, value: u8) {
-        self.irq = value & 0x80 == 0x80;
+        self.irq_enabled = value & 0x80 == 0x80;
+        if !self.irq_enabled {
+            self.interrupt = false;
+        }
         self.do_loop = value & 0x40 == 0x40;
         self.tick_period = DMC_TABLE[(value & 0xF) as usize];
     }
@@ -575,47 +806,60 @@ impl DMC {
         self.sample_length = (u16::from(value) << 4) | 1;
     }
 
+    /// Starts or stops playing the sample, through $4015.
+    ///
+    /// This acknowledges the IRQ, and only restarts the sample if
+    /// it was done playing.
+    fn set_enabled(&mut self, enabled: bool) {
+        self.interrupt = false;
+        if !enabled {
+            self.current_length = 0;
+        } else if self.current_length == 0 {
+            self.restart();
+        }
+    }
+
     fn restart(&mut self) {
         self.current_address = self.sample_address;
         self.current_length = self.sample_length;
     }
 
-    fn step_timer(&mut self, read: u8) -> bool {
-        if self.enabled {
-            let stall = self.step_reader(read);
-            if self.tick_value == 0 {
-                self.tick_value = self.tick_period;
-                self.step_shifter();
-            } else {
-                self.tick_value -= 1;
-            }
-            stall
-        } else {
-            false
-        }
+    /// Whether or not the next byte of the sample needs to be read
+    fn needs_read(&self) -> bool {
+        self.buffer.is_none() && self.current_length > 0
     }
 
-    // returns whether or not to stall
-    fn step_reader(&mut self, read: u8) -> bool {
-        if self.current_length > 0 && self.bit_count == 0 {
-            self.shift_register = read;
-            self.bit_count = 8;
-            self.current_address = self.current_address.wrapping_add(1);
-            if self.current_address == 0 {
-                self.current_address = 0x8000;
-            }
-            self.current_length -= 1;
-            if self.current_length == 0 && self.do_loop {
+    /// Fills the buffer with the byte at `current_address`.
+    ///
+    /// This sets the interrupt flag if the sample ended, with IRQs enabled.
+    fn fill_buffer(&mut self, read: u8) {
+        self.buffer = Some(read);
+        self.current_address = self.current_address.wrapping_add(1);
+        if self.current_address == 0 {
+            self.current_address = 0x8000;
+        }
+        self.current_length -= 1;
+        if self.current_length == 0 {
+            if self.do_loop {
                 self.restart();
+            } else if self.irq_enabled {
+                self.interrupt = true;
             }
-            true
+        }
+    }
+
+    fn step_timer(&mut self) {
+        if self.tick_value == 0 {
+            // The timer runs for a total of period + 1 steps
+            self.tick_value = self.tick_period - 1;
+            self.step_shifter();
         } else {
-            false
+            self.tick_value -= 1;
         }
     }
 
     fn step_shifter(&mut self) {
-        if self.bit_count != 0 {
+        if !self.silence {
             if self.shift_register & 1 == 1 {
                 if self.value <= 125 {
                     self.value += 2;
@@ -623,14 +867,62 @@ impl DMC {
             } else if self.value >= 2 {
                 self.value -= 2;
             }
-            self.shift_register >>= 1;
-            self.bit_count -= 1;
+        }
+        self.shift_register >>= 1;
+        self.bit_count -= 1;
+        if self.bit_count == 0 {
+            self.bit_count = 8;
+            match self.buffer.take() {
+                None => self.silence = true,
+                Some(byte) => {
+                    self.silence = false;
+                    self.shift_register = byte;
+                }
+            }
         }
     }
 
     fn output(&self) -> u8 {
         self.value
     }
+
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.value);
+        w.u16(self.sample_address);
+        w.u16(self.sample_length);
+        w.u16(self.current_address);
+        w.u16(self.current_length);
+        w.bool(self.buffer.is_some());
+        w.u8(self.buffer.unwrap_or(0));
+        w.u8(self.shift_register);
+        w.u8(self.bit_count);
+        w.bool(self.silence);
+        w.u8(self.tick_period);
+        w.u8(self.tick_value);
+        w.bool(self.do_loop);
+        w.bool(self.irq_enabled);
+        w.bool(self.interrupt);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.value = r.u8()? & 0x7F;
+        self.sample_address = r.u16()?;
+        self.sample_length = r.u16()?;
+        self.current_address = r.u16()?;
+        self.current_length = r.u16()?;
+        let full = r.bool()?;
+        let buffer = r.u8()?;
+        self.buffer = if full { Some(buffer) } else { None };
+        self.shift_register = r.u8()?;
+        self.bit_count = r.u8()?.clamp(1, 8);
+        self.silence = r.bool()?;
+        self.tick_period = r.u8()?.max(1);
+        self.tick_value = r.u8()?;
+        self.do_loop = r.bool()?;
+        self.irq_enabled = r.bool()?;
+        self.interrupt = r.bool()?;
+        Ok(())
+    }
 }
 
 /// Contains registers that are written to across the memory bus
@@ -645,10 +937,16 @@ pub struct APUState {
     noise: Noise,
     /// The DMC sample generator
     dmc: DMC,
-    /// The current frame period
+    /// The number of steps in the frame sequence, either 4 or 5
     frame_period: u8,
     /// Whether or not to trigger IRQs
     frame_irq: bool,
+    /// Whether the frame counter is holding the IRQ line, until $4015 is read
+    frame_interrupt: bool,
+    /// Set when $4017 is written, to restart the frame sequence
+    frame_reset: bool,
+    /// Whether or not the APU is being stepped at all
+    enabled: bool,
 }
 
 impl Default for APUState {
@@ -659,8 +957,12 @@ impl Default for APUState {
             triangle: Triangle::new(),
             noise: Noise::new(1),
             dmc: DMC::new(),
-            frame_period: 0,
-            frame_irq: false,
+            // The console starts in the 4 step mode, with IRQs enabled
+            frame_period: 4,
+            frame_irq: true,
+            frame_interrupt: false,
+            frame_reset: false,
+            enabled: true,
         }
     }
 }
@@ -670,16 +972,62 @@ impl APUState {
         APUState::default()
     }
 
-    pub fn read_register(&self, address: u16) -> u8 {
+    /// Returns the current output level of the DMC
+    #[cfg(test)]
+    pub fn dmc_output(&self) -> u8 {
+        self.dmc.output()
+    }
+
+    /// Returns the timer period of the first square channel
+    #[cfg(test)]
+    pub fn square1_period(&self) -> u16 {
+        self.square1.timer_period
+    }
+
+    /// Reads a register, clearing the frame interrupt when reading $4015
+    pub fn read_register(&mut self, address: u16) -> u8 {
         match address {
-            0x4015 => self.read_status(),
+            0x4015 => {
+                let status = self.read_status();
+                self.frame_interrupt = false;
+                status
+            }
             // Some addresses may be read by bad games
             _ => 0,
         }
     }
 
+    /// Returns what reading a register would, keeping the frame interrupt
+    pub fn peek_register(&self, address: u16) -> u8 {
+        match address {
+            0x4015 => self.read_status(),
+            _ => 0,
+        }
+    }
+
+    /// Brings back every channel to its state on power up.
+    ///
+    /// Whether or not the APU is enabled is a setting, and is kept.
+    pub fn power_cycle(&mut self) {
+        *self = APUState {
+            enabled: self.enabled,
+            ..APUState::default()
+        };
+    }
+
+    /// Stop reporting channels as active, since the APU won't be stepped.
+    ///
+    /// Otherwise, length counters would never run out, and games
+    /// waiting on them would hang.
+    pub fn disable(&mut self) {
+        self.enabled = false;
+    }
+
     fn read_status(&self) -> u8 {
         let mut result = 0;
+        if !self.enabled {
+            return result;
+        }
         if self.square1.length_value > 0 {
             result |= 1;
         }
@@ -695,6 +1043,12 @@ impl APUState {
         if self.dmc.current_length > 0 {
             result |= 16;
         }
+        if self.frame_interrupt {
+            result |= 0x40;
+        }
+        if self.dmc.interrupt {
+            result |= 0x80;
+        }
         result
     }
 
@@ -732,7 +1086,6 @@ impl APUState {
         self.square2.enabled = value & 2 == 2;
         self.triangle.enabled = value & 4 == 4;
         self.noise.enabled = value & 8 == 8;
-        self.dmc.enabled = value & 16 == 16;
         if !self.square1.enabled {
             self.square1.length_value = 0;
         }
@@ -745,17 +1098,20 @@ impl APUState {
         if !self.noise.enabled {
             self.noise.length_value = 0;
         }
-        if !self.dmc.enabled {
-            self.dmc.current_length = 0;
-        } else if self.dmc.current_length == 0 {
-            self.dmc.restart();
-        }
+        self.dmc.set_enabled(value & 16 == 16);
     }
 
+    /// Sets the mode of the frame counter, restarting its sequence.
+    ///
+    /// Inhibiting IRQs also clears a pending frame interrupt.
     fn write_frame_counter(&mut self, value: u8) {
         self.frame_period = 4 + ((value >> 7) & 1);
         self.frame_irq = (value >> 6) & 1 == 0;
-        // Catching up with the frame period
+        if !self.frame_irq {
+            self.frame_interrupt = false;
+        }
+        self.frame_reset = true;
+        // The 5 step mode clocks every unit as soon as it's set
         if self.frame_period == 5 {
             self.step_envelope();
             self.step_sweep();
@@ -781,40 +1137,136 @@ impl APUState {
         self.triangle.step_length();
         self.noise.step_length();
     }
+
+    pub fn save(&self, w: &mut StateWriter) {
+        self.square1.save(w);
+        self.square2.save(w);
+        self.triangle.save(w);
+        self.noise.save(w);
+        self.dmc.save(w);
+        w.u8(self.frame_period);
+        w.bool(self.frame_irq);
+        w.bool(self.frame_interrupt);
+        w.bool(self.frame_reset);
+    }
+
+    pub fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.square1.load(r)?;
+        self.square2.load(r)?;
+        self.triangle.load(r)?;
+        self.noise.load(r)?;
+        self.dmc.load(r)?;
+        self.frame_period = r.u8()?;
+        if !matches!(self.frame_period, 4 | 5) {
+            return Err(StateError::InvalidValue);
+        }
+        self.frame_irq = r.bool()?;
+        self.frame_interrupt = r.bool()?;
+        self.frame_reset = r.bool()?;
+        Ok(())
+    }
 }
 
 /// Represents the audio processing unit
 pub(crate) struct APU {
     /// The chain of filters used on the output of the generators
     filter: FilterChain,
-    // The 2 tables used to find the height of the wave output
-    pulse_table: [f32; 31],
-    tnd_table: [f32; 203],
+    /// The cutoffs of the filters, or None to leave the output as is
+    cutoffs: Option<AudioFilter>,
     /// Used to time frame ticks
     frame_tick: u16,
-    /// Used to time sample ticks
-    sample_tick: u16,
-    /// The number of ticks after which to reset the sample tick.
-    /// This is determined from the sample rate at runtime
-    sample_cap: u16,
+    /// The number of ticks after which the frame counter steps.
+    /// This is determined by the region.
+    frame_period: u16,
+    /// Used to time sample ticks.
+    ///
+    /// This grows by the sample rate every tick, and a sample is sent
+    /// once it reaches the CPU frequency, which keeps the exact rate.
+    sample_tick: u32,
+    /// The frequency of the CPU, determined by the region
+    cpu_frequency: u32,
+    /// How fast the console runs, compared to the region's CPU frequency
+    speed: f32,
+    /// The value `sample_tick` reaches when a sample is sent.
+    ///
+    /// This is the CPU frequency scaled by the speed, so that samples are
+    /// still sent at the sample rate, with the pitch following the speed.
+    sample_period: u32,
+    /// The sum of the output on every tick since the last sample
+    sample_sum: f32,
+    /// The number of ticks added to the sum
+    sample_count: u32,
+    /// The last sample sent, held while paused so the output doesn't jump
+    last_sample: f32,
     /// The current frame value
     frame_value: u8,
+    /// The sample rate of the audio device
+    sample_rate: u32,
 }
 
 impl APU {
     pub fn new(sample_rate: u32) -> Self {
-        // We need to round up, otherwise we'll slowly add latency to the music
-        let sample_cap = (1_790_000 / sample_rate) as u16 + 1;
-        let tnd_table = make_tnd_table();
-        let pulse_table = make_pulse_table();
-        APU {
-            filter: FilterChain::new(sample_rate),
-            tnd_table,
-            pulse_table,
+        let mut apu = APU {
+            filter: FilterChain::new(sample_rate, AudioFilter::default()),
+            cutoffs: Some(AudioFilter::default()),
             frame_tick: 0,
+            frame_period: 0,
             sample_tick: 0,
-            sample_cap,
+            cpu_frequency: 0,
+            speed: 1.0,
+            sample_period: 0,
+            sample_sum: 0.0,
+            sample_count: 0,
+            last_sample: 0.0,
             frame_value: 0,
+            sample_rate,
+        };
+        apu.set_region(Region::default());
+        apu
+    }
+
+    /// Adjusts the timing of the APU to match a region
+    pub fn set_region(&mut self, region: Region) {
+        self.cpu_frequency = region.cpu_frequency();
+        self.frame_period = region.frame_counter_period();
+        self.frame_tick = 0;
+        self.set_speed(self.speed);
+    }
+
+    /// Scales the rate samples are sent at, when the console runs at another speed.
+    ///
+    /// Samples keep coming at the sample rate in real time, so the pitch
+    /// goes up and down with the speed.
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed;
+        self.sample_period = (self.cpu_frequency as f32 * speed) as u32;
+        self.sample_tick %= self.sample_period;
+    }
+
+    /// Changes the rate samples are sent at, like when the audio device changes.
+    ///
+    /// The filters are made again for the new rate.
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        self.sample_tick = 0;
+        self.set_filter(self.cutoffs);
+    }
+
+    /// Changes the filters applied to the output, or removes them with None
+    pub fn set_filter(&mut self, cutoffs: Option<AudioFilter>) {
+        self.cutoffs = cutoffs;
+        let cutoffs = cutoffs.unwrap_or_default();
+        self.filter = FilterChain::new(self.sample_rate, cutoffs);
+    }
+
+    /// Sends the samples making up some micro seconds of silence.
+    ///
+    /// The last sample sent is repeated, instead of dropping to 0, which
+    /// would click when the output isn't centered by the filters.
+    pub fn push_silence(&self, audio: &mut impl AudioDevice, micros: u32) {
+        let samples = u64::from(micros) * u64::from(self.sample_rate) / 1_000_000;
+        for _ in 0..samples {
+            audio.push_sample(self.last_sample);
         }
     }
 
@@ -825,22 +1277,45 @@ impl APU {
         // we can use the first bit of the frame_tick as an even odd flag
         let toggle = self.frame_tick & 1 == 0;
         self.step_timer(m, toggle);
-        // This is equivalent to firing at roughly 240 hz
-        if self.frame_tick >= 7458 {
+        if m.apu.frame_reset {
+            m.apu.frame_reset = false;
+            // Keep the parity of the tick, since timers depend on it
+            self.frame_tick &= 1;
+            self.frame_value = 0;
+        }
+        // This is equivalent to firing at roughly 240 hz on NTSC
+        if self.frame_tick >= self.frame_period {
             self.frame_tick = 0;
             self.step_framecounter(m);
         }
-        self.sample_tick += 1;
-        if self.sample_tick >= self.sample_cap {
-            self.sample_tick = 0;
-            self.send_sample(m, audio);
+        self.sample_sum += self.output(m);
+        self.sample_count += 1;
+        self.sample_tick += self.sample_rate;
+        if self.sample_tick >= self.sample_period {
+            self.sample_tick -= self.sample_period;
+            self.send_sample(audio);
+        }
+        // Like mapper IRQs, the line stays held until acknowledged
+        if m.apu.frame_interrupt || m.apu.dmc.interrupt {
+            m.cpu.set_irq();
         }
     }
 
-    fn send_sample(&mut self, m: &mut MemoryBus, audio: &mut impl AudioDevice) {
-        let output = self.output(m);
-        let filtered = self.filter.step(output);
-        audio.push_sample(filtered)
+    /// Sends the average output since the last sample.
+    ///
+    /// Averaging acts as a low pass filter, getting rid of the frequencies
+    /// too high for the sample rate, which would otherwise alias.
+    fn send_sample(&mut self, audio: &mut impl AudioDevice) {
+        let output = self.sample_sum / self.sample_count as f32;
+        self.sample_sum = 0.0;
+        self.sample_count = 0;
+        let sample = if self.cutoffs.is_some() {
+            self.filter.step(output)
+        } else {
+            output
+        };
+        self.last_sample = sample;
+        audio.push_sample(sample);
     }
 
     fn output(&mut self, m: &mut MemoryBus) -> f32 {
@@ -849,10 +1324,7 @@ impl APU {
         let t = m.apu.triangle.output();
         let n = m.apu.noise.output();
         let d = m.apu.dmc.output();
-        // TODO: figure out if these bound checks are a bug somewhere else
-        let pulse_out = self.pulse_table[(p1 + p2) as usize];
-        let tnd_out = self.tnd_table[(3 * t + 2 * n + d) as usize];
-        pulse_out + tnd_out
+        mix(p1, p2, t, n, d) + m.mapper.expansion_audio()
     }
 
     fn step_timer(&mut self, m: &mut MemoryBus, toggle: bool) {
@@ -860,27 +1332,34 @@ impl APU {
             m.apu.square1.step_timer();
             m.apu.square2.step_timer();
             m.apu.noise.step_timer();
-            let address = m.apu.dmc.current_address;
-            let read = m.cpu_read(address);
-            if m.apu.dmc.step_timer(read) {
-                m.cpu.add_stall(4);
+            // Memory is only read when the DMC needs another byte,
+            // which stalls the CPU while the byte is fetched
+            if m.apu.dmc.needs_read() {
+                let read = m.dmc_fetch(m.apu.dmc.current_address);
+                m.apu.dmc.fill_buffer(read);
             }
+            m.apu.dmc.step_timer();
         }
         m.apu.triangle.step_timer();
     }
 
+    /// Steps the frame sequence, clocking the units of each channel.
+    ///
+    /// Envelopes and the linear counter are clocked on every step but the
+    /// 4th of the 5 step mode, and sweeps and length counters on every
+    /// other step, ending on the last one.
     fn step_framecounter(&mut self, m: &mut MemoryBus) {
         match m.apu.frame_period {
             4 => {
                 self.frame_value = (self.frame_value + 1) % 4;
                 match self.frame_value {
-                    0 | 2 => m.apu.step_envelope(),
-                    1 => {
+                    1 | 3 => m.apu.step_envelope(),
+                    2 => {
                         m.apu.step_envelope();
                         m.apu.step_sweep();
                         m.apu.step_length();
                     }
-                    3 => {
+                    0 => {
                         m.apu.step_envelope();
                         m.apu.step_sweep();
                         m.apu.step_length();
@@ -909,7 +1388,29 @@ impl APU {
 
     fn fire_irq(&self, m: &mut MemoryBus) {
         if m.apu.frame_irq {
-            m.cpu.set_irq();
+            m.apu.frame_interrupt = true;
         }
     }
+
+    /// Saves the state of the frame counter and filters.
+    ///
+    /// The timing determined by the region and sample rate isn't saved.
+    pub fn save(&self, w: &mut StateWriter) {
+        self.filter.save(w);
+        w.u16(self.frame_tick);
+        w.u32(self.sample_tick);
+        w.f32(self.sample_sum);
+        w.u32(self.sample_count);
+        w.u8(self.frame_value);
+    }
+
+    pub fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.filter.load(r)?;
+        self.frame_tick = r.u16()?;
+        self.sample_tick = r.u32()?;
+        self.sample_sum = r.f32()?;
+        self.sample_count = r.u32()?;
+        self.frame_value = r.u8()?;
+        Ok(())
+    }
 }
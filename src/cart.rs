@@ -1,7 +1,12 @@
 use core::convert::TryFrom;
 
+use alloc::string::String;
 use alloc::vec::Vec;
 
+use crate::hash::{crc32, md5, sha1};
+use crate::region::Region;
+use crate::state::{StateError, StateReader, StateWriter};
+
 /// Represents the possible errors when decoding a Cart
 #[derive(Clone, Copy, Debug)]
 pub enum CartReadingError {
@@ -20,6 +25,8 @@ pub enum Mirroring {
     SingleLower,
     /// Every mirror points to the second table
     SingleUpper,
+    /// Each table is separate, using extra RAM on the cart
+    FourScreen,
 }
 
 impl From<u8> for Mirroring {
@@ -54,6 +61,7 @@ impl Mirroring {
             (Mirroring::Vertical, 3) => 1,
             (Mirroring::SingleLower, _) => 0,
             (Mirroring::SingleUpper, _) => 1,
+            (Mirroring::FourScreen, table) => table,
             _ => 0,
         };
         0x2000 + table * 0x400 + (address % 0x400)
@@ -64,12 +72,28 @@ impl Mirroring {
 ///
 /// In theory, the mapper id in a cart could be any byte, but only a small subset
 /// of mappers were actually used.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum MapperID {
     /// The mapper used for 0x0 and 0x2
     M2,
     /// iNES mapper 0x1
     M1,
+    /// iNES mapper 0x3, CNROM
+    M3,
+    /// iNES mapper 0x7, AxROM
+    M7,
+    /// iNES mapper 0x9, MMC2
+    M9,
+    /// iNES mapper 0xB, Color Dreams
+    M11,
+    /// iNES mapper 0x18, VRC6a
+    M24,
+    /// iNES mapper 0x1A, VRC6b
+    M26,
+    /// iNES mapper 0x42, GxROM
+    M66,
+    /// The bank switching of NSF music files, which isn't used by carts
+    Nsf,
 }
 
 impl TryFrom<u8> for MapperID {
@@ -80,11 +104,79 @@ impl TryFrom<u8> for MapperID {
             0 => Ok(MapperID::M2),
             1 => Ok(MapperID::M1),
             2 => Ok(MapperID::M2),
+            3 => Ok(MapperID::M3),
+            7 => Ok(MapperID::M7),
+            9 => Ok(MapperID::M9),
+            11 => Ok(MapperID::M11),
+            24 => Ok(MapperID::M24),
+            26 => Ok(MapperID::M26),
+            66 => Ok(MapperID::M66),
             _ => Err(CartReadingError::UnknownMapper(byte)),
         }
     }
 }
 
+/// What the header of a ROM says about its cart.
+///
+/// This is meant to be shown to users, in a window title for example.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CartInfo {
+    /// The iNES mapper number, before being matched with a `MapperID`
+    pub mapper: u8,
+    /// The size of PRG ROM, in bytes
+    pub prg_size: usize,
+    /// The size of CHR ROM, in bytes, which is 0 for carts with CHR RAM
+    pub chr_size: usize,
+    /// The mirroring given by the header, which some mappers change later
+    pub mirroring: Mirroring,
+    /// Whether or not the header says the cart has a battery
+    pub has_battery: bool,
+    /// The title some dumps append after the ROM data, if present
+    pub title: Option<String>,
+    /// The CRC32 of the PRG and CHR ROM, the same as `Cart::hash`
+    pub crc32: u32,
+    /// The SHA-1 digest of the PRG and CHR ROM, without the header or trainer.
+    ///
+    /// Along with the CRC32, this matches ROMs against databases.
+    pub sha1: [u8; 20],
+}
+
+impl CartInfo {
+    /// Returns a caption for the window running a ROM, from the name of its file.
+    ///
+    /// The title stored in the ROM is included too, if there's one.
+    pub fn caption(&self, file_name: &str) -> String {
+        match &self.title {
+            Some(title) => format!("Ludus - {} ({})", title, file_name),
+            None => format!("Ludus - {}", file_name),
+        }
+    }
+}
+
+/// Reads the title at the end of a ROM, made of up to 128 bytes of ASCII.
+///
+/// This isn't part of the iNES format, but some tools append it anyways,
+/// padded with zeroes. Anything else after the ROM data is ignored.
+fn read_title(trailer: &[u8]) -> Option<String> {
+    if trailer.is_empty() || trailer.len() > 128 {
+        return None;
+    }
+    let end = trailer
+        .iter()
+        .position(|&b| b == 0)
+        .unwrap_or(trailer.len());
+    let text = &trailer[..end];
+    if !text.iter().all(|b| b.is_ascii_graphic() || *b == b' ') {
+        return None;
+    }
+    let title = core::str::from_utf8(text).ok()?.trim();
+    if title.is_empty() {
+        None
+    } else {
+        Some(title.into())
+    }
+}
+
 /// Represents an NES Cartridge
 /// The PRG and CHR roms vary in sizes between carts,
 /// which is why they're stored in Vecs.
@@ -101,31 +193,96 @@ pub struct Cart {
     pub mirroring: Mirroring,
     /// Indicates whether or not a battery backed RAM is present
     pub has_battery: bool,
+    /// Whether or not CHR is RAM, because the cart has no CHR ROM
+    pub chr_ram: bool,
+    /// Whether or not writes to bank registers in ROM have bus conflicts.
+    ///
+    /// On boards made of discrete logic, ROM drives the data bus during
+    /// writes too, so the value seen by the mapper is ANDed with the byte
+    /// of ROM at that address. This starts enabled for the boards most
+    /// common with mappers 2, 3, and 66, which have conflicts, and is only
+    /// used by those mappers along with 7 and 11.
+    pub bus_conflicts: bool,
+    /// The region the cart is made for, if the header says so.
+    ///
+    /// Most headers leave this out, even for PAL games.
+    pub region: Option<Region>,
+    /// The CRC32 of the PRG and CHR ROM, without the header or trainer.
+    ///
+    /// This identifies the game the same way ROM databases do.
+    pub hash: u32,
+    /// What the header says about the cart, which doesn't change while playing
+    info: CartInfo,
 }
 
 impl Cart {
     /// Reads a buffer of bytes into a Cart,
     /// detecting and parsing the format automatically.
+    ///
+    /// Buffers cut short, missing part of the header or data, are rejected,
+    /// along with headers without any PRG ROM.
     pub fn from_bytes(buffer: &[u8]) -> Result<Cart, CartReadingError> {
-        if buffer[0..4] == [0x4E, 0x45, 0x53, 0x1A] {
+        if buffer.len() >= 16 && buffer[0..4] == [0x4E, 0x45, 0x53, 0x1A] {
             Cart::from_ines(buffer)
         } else {
             Err(CartReadingError::UnrecognisedFormat)
         }
     }
 
+    /// Makes a cart with only PRG ROM, for formats other than iNES.
+    ///
+    /// The cart has 8KB of CHR RAM, and the mapper number in its info is 0.
+    pub(crate) fn with_prg(prg: Vec<u8>, mapper: MapperID, title: Option<String>) -> Cart {
+        let hash = crc32(&prg);
+        let info = CartInfo {
+            mapper: 0,
+            prg_size: prg.len(),
+            chr_size: 0,
+            mirroring: Mirroring::Horizontal,
+            has_battery: false,
+            title,
+            crc32: hash,
+            sha1: sha1(&prg),
+        };
+        Cart {
+            prg,
+            chr: vec![0; 0x2000],
+            sram: [0; 0x2000],
+            mapper,
+            mirroring: Mirroring::Horizontal,
+            has_battery: false,
+            chr_ram: true,
+            bus_conflicts: false,
+            region: None,
+            hash,
+            info,
+        }
+    }
+
     /// Reads an INES formatted buffer, including the header
     fn from_ines(buffer: &[u8]) -> Result<Cart, CartReadingError> {
         let prg_chunks = buffer[4] as usize;
         let chr_chunks = buffer[5] as usize;
         let flag6 = buffer[6];
         let flag7 = buffer[7];
+        // Only PAL is flagged, since NTSC is the same as no information
+        let region = if buffer[9] & 1 != 0 {
+            Some(Region::PAL)
+        } else {
+            None
+        };
         let trainer_offset = if flag6 & 0b100 > 0 { 512 } else { 0 };
         let prg_start = 16 + trainer_offset;
         let prg_end = prg_start + 0x4000 * prg_chunks;
         let chr_end = prg_end + 0x2000 * chr_chunks;
-        let mapper = MapperID::try_from((flag6 >> 4) | (flag7 & 0xF0))?;
-        let mirroring = if flag6 & 1 != 0 {
+        if prg_chunks == 0 || buffer.len() < chr_end {
+            return Err(CartReadingError::UnrecognisedFormat);
+        }
+        let mapper_number = (flag6 >> 4) | (flag7 & 0xF0);
+        let mapper = MapperID::try_from(mapper_number)?;
+        let mirroring = if flag6 & 0b1000 != 0 {
+            Mirroring::FourScreen
+        } else if flag6 & 1 != 0 {
             Mirroring::Vertical
         } else {
             Mirroring::Horizontal
@@ -135,13 +292,91 @@ impl Cart {
         } else {
             buffer[prg_end..chr_end].to_vec()
         };
+        // The trainer is loaded at $7000, where games expect to find it
+        let mut sram = [0; 0x2000];
+        sram[0x1000..0x1000 + trainer_offset].copy_from_slice(&buffer[16..prg_start]);
+        let rom = &buffer[prg_start..chr_end];
+        let hash = crc32(rom);
+        let info = CartInfo {
+            mapper: mapper_number,
+            prg_size: prg_end - prg_start,
+            chr_size: chr_end - prg_end,
+            mirroring,
+            has_battery: flag6 & 0b10 > 0,
+            title: buffer.get(chr_end..).and_then(read_title),
+            crc32: hash,
+            sha1: sha1(rom),
+        };
         Ok(Cart {
             prg: buffer[prg_start..prg_end].to_vec(),
             chr,
             mapper,
-            sram: [0; 0x2000],
+            sram,
             mirroring,
             has_battery: flag6 & 0b10 > 0,
+            chr_ram: chr_chunks == 0,
+            bus_conflicts: matches!(mapper_number, 2 | 3 | 66),
+            region,
+            hash,
+            info,
         })
     }
+
+    /// Returns what the header says about the cart, like its mapper and size
+    pub fn info(&self) -> &CartInfo {
+        &self.info
+    }
+
+    /// Computes the MD5 digest of the PRG and CHR ROM.
+    ///
+    /// This is the checksum FCEUX uses to identify games, in movies for
+    /// example, and is computed when needed since it's rarely used.
+    pub fn md5(&self) -> [u8; 16] {
+        let mut rom = self.prg.clone();
+        if !self.chr_ram {
+            rom.extend_from_slice(&self.chr);
+        }
+        md5(&rom)
+    }
+
+    /// Writes to CHR, ignoring the write if CHR is ROM.
+    pub(crate) fn write_chr(&mut self, index: usize, value: u8) {
+        if self.chr_ram {
+            self.chr[index] = value;
+        }
+    }
+
+    /// Saves the parts of the cart that can change while playing.
+    ///
+    /// This is the SRAM, the mirroring, and CHR if it's RAM.
+    pub(crate) fn save(&self, w: &mut StateWriter) {
+        w.bytes(&self.sram);
+        let mirroring = match self.mirroring {
+            Mirroring::Horizontal => 0,
+            Mirroring::Vertical => 1,
+            Mirroring::SingleLower => 2,
+            Mirroring::SingleUpper => 3,
+            Mirroring::FourScreen => 4,
+        };
+        w.u8(mirroring);
+        if self.chr_ram {
+            w.bytes(&self.chr);
+        }
+    }
+
+    pub(crate) fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        r.bytes(&mut self.sram)?;
+        self.mirroring = match r.u8()? {
+            0 => Mirroring::Horizontal,
+            1 => Mirroring::Vertical,
+            2 => Mirroring::SingleLower,
+            3 => Mirroring::SingleUpper,
+            4 => Mirroring::FourScreen,
+            _ => return Err(StateError::InvalidValue),
+        };
+        if self.chr_ram {
+            r.bytes(&mut self.chr)?;
+        }
+        Ok(())
+    }
 }
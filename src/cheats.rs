@@ -0,0 +1,86 @@
+/// The letters used by Game Genie codes, in the order of the values they encode
+const LETTERS: &[u8; 16] = b"APZLGITYEOXUKSVN";
+
+/// Represents the possible errors when decoding a cheat code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheatError {
+    /// The code didn't have 6 or 8 letters, containing the length it had
+    BadLength(usize),
+    /// The code contained a letter Game Genie codes don't use
+    BadLetter(char),
+}
+
+/// Represents a cheat, replacing the value the CPU reads at an address.
+///
+/// Like the Game Genie, cheats only apply to reads of PRG, from $8000 up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cheat {
+    /// The address the cheat patches
+    pub address: u16,
+    /// The value read instead of the original one
+    pub value: u8,
+    /// If present, the cheat only applies when the original value matches.
+    ///
+    /// This lets the cheat target a single bank, when the address is
+    /// shared by multiple banks.
+    pub compare: Option<u8>,
+}
+
+impl Cheat {
+    /// Decodes a 6 or 8 letter Game Genie code, like `SXIOPO`.
+    ///
+    /// The letters can be upper or lower case.
+    /// See: https://wiki.nesdev.com/w/index.php/Game_Genie
+    pub fn from_game_genie(code: &str) -> Result<Self, CheatError> {
+        let mut n = [0u8; 8];
+        let mut len = 0;
+        for c in code.chars() {
+            let upper = c.to_ascii_uppercase();
+            let value = LETTERS
+                .iter()
+                .position(|&letter| char::from(letter) == upper)
+                .ok_or(CheatError::BadLetter(c))?;
+            if len < n.len() {
+                n[len] = value as u8;
+            }
+            len += 1;
+        }
+        if len != 6 && len != 8 {
+            return Err(CheatError::BadLength(len));
+        }
+        let address = 0x8000
+            | (u16::from(n[3] & 7) << 12)
+            | (u16::from(n[5] & 7) << 8)
+            | (u16::from(n[4] & 8) << 8)
+            | (u16::from(n[2] & 7) << 4)
+            | (u16::from(n[1] & 8) << 4)
+            | u16::from(n[4] & 7)
+            | u16::from(n[3] & 8);
+        let value = ((n[1] & 7) << 4) | ((n[0] & 8) << 4) | (n[0] & 7);
+        if len == 6 {
+            Ok(Cheat {
+                address,
+                value: value | (n[5] & 8),
+                compare: None,
+            })
+        } else {
+            let compare = ((n[7] & 7) << 4) | ((n[6] & 8) << 4) | (n[6] & 7) | (n[5] & 8);
+            Ok(Cheat {
+                address,
+                value: value | (n[7] & 8),
+                compare: Some(compare),
+            })
+        }
+    }
+
+    /// Returns the value the CPU reads with this cheat, given the original value
+    pub(crate) fn apply(&self, address: u16, original: u8) -> u8 {
+        if address != self.address {
+            return original;
+        }
+        match self.compare {
+            Some(compare) if compare != original => original,
+            _ => self.value,
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use crate::palette::Palette;
+use crate::region::Region;
+
+/// Represents how closely the console follows the quirks of the hardware.
+///
+/// Most games run fine without these quirks, and emulating them costs time,
+/// so `Fast` is the default. `Accurate` enables all of them at once:
+///
+/// - palette ram and OAM get the values found on hardware at power up,
+///   like `ConsoleBuilder::accurate_power_up`.
+/// - the sprite overflow flag follows the bug of the hardware,
+///   like `ConsoleBuilder::sprite_overflow_bug`.
+///
+/// Behavior that's always emulated, like skipping a dot on odd frames,
+/// isn't affected by this setting.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Accuracy {
+    /// Only emulate what games need to run correctly
+    #[default]
+    Fast,
+    /// Emulate every quirk supported, at the cost of speed
+    Accurate,
+}
+
+/// Represents what RAM contains when the console is powered on.
+///
+/// On hardware, RAM starts out in a semi random state, which depends on
+/// the console. Some games read it before writing to it, and expect some
+/// values not to be zero, while test ROMs often expect a specific fill.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RamFill {
+    /// Every byte is 0x00
+    #[default]
+    Zeros,
+    /// Every byte is 0xFF
+    Ones,
+    /// Groups of 4 bytes alternate between 0x00 and 0xFF, starting with 0x00.
+    ///
+    /// This is the pattern FCEUX uses, and is close to what many consoles have.
+    Striped,
+}
+
+impl RamFill {
+    /// Returns the value of a byte of RAM
+    pub(crate) fn byte(self, address: usize) -> u8 {
+        match self {
+            RamFill::Zeros => 0x00,
+            RamFill::Ones => 0xFF,
+            RamFill::Striped if address & 4 == 0 => 0x00,
+            RamFill::Striped => 0xFF,
+        }
+    }
+}
+
+/// Represents settings to apply to a specific game.
+///
+/// Each setting left as `None` keeps whatever the console would use otherwise.
+/// Configs are passed to `ConsoleBuilder::game_configs`, which picks the right
+/// one by looking at the hash of the cart being loaded.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GameConfig {
+    /// The region to run the game in
+    pub region: Option<Region>,
+    /// The colors output by the PPU, see `Console::set_palette`
+    pub palette: Option<Palette>,
+    /// Whether or not the sprite overflow flag follows the hardware bug,
+    /// see `ConsoleBuilder::sprite_overflow_bug`
+    pub sprite_overflow_bug: Option<bool>,
+    /// Whether or not writes to bank registers have bus conflicts.
+    ///
+    /// This is for games on a different board than the usual one for
+    /// their mapper, see `Cart::bus_conflicts`.
+    pub bus_conflicts: Option<bool>,
+}
@@ -1,10 +1,260 @@
-use crate::apu::APU;
-use crate::cart::Cart;
-use crate::controller::ButtonState;
-use crate::cpu::CPU;
-use crate::memory::MemoryBus;
-use crate::ports::{AudioDevice, VideoDevice};
-use crate::ppu::PPU;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec::Vec;
+
+use crate::apu::{ApuLog, ApuWrite, AudioFilter, APU};
+use crate::cart::{Cart, CartReadingError};
+use crate::cheats::{Cheat, CheatError};
+use crate::config::{Accuracy, GameConfig, RamFill};
+use crate::controller::{ButtonState, Turbo, Zapper};
+use crate::cpu::{Registers, TraceEntry, CPU};
+use crate::hud::{Hud, HudDevice};
+use crate::input::{FrameInput, InputQueue, InputScript};
+use crate::memory::{EmulationError, MemoryBus, PowerOn, Watch, WatchHit};
+use crate::movie::{Movie, MovieError, MovieFrame};
+use crate::nsf::{Nsf, NsfPlayer, IDLE_LOOP};
+use crate::palette::Palette;
+use crate::ports::{
+    AudioDevice, NullAudio, NullVideo, PixelBuffer, VideoDevice, NAMETABLES_HEIGHT,
+    NAMETABLES_WIDTH, NES_HEIGHT, NES_WIDTH,
+};
+use crate::ppu::{PPUState, SpriteEntry, SpriteInfo, PPU};
+use crate::region::Region;
+use crate::state::{StateError, StateReader, StateWriter};
+
+/// The header identifying saved PPU state
+const PPU_STATE_MAGIC: &[u8; 4] = b"LDPU";
+/// The header identifying a saved state of the whole console
+const STATE_MAGIC: &[u8; 4] = b"LDST";
+
+/// Used to configure a console before creating it.
+///
+/// The defaults used by the builder are the same as those of `Console::new`.
+pub struct ConsoleBuilder {
+    cart: Cart,
+    sample_rate: u32,
+    power_on: PowerOn,
+    overflow_bug: bool,
+    audio: bool,
+    region: Option<Region>,
+    game_configs: BTreeMap<u32, GameConfig>,
+}
+
+impl ConsoleBuilder {
+    /// Start configuring a console playing a cart, with an audio sample rate.
+    pub fn new(cart: Cart, sample_rate: u32) -> Self {
+        ConsoleBuilder {
+            cart,
+            sample_rate,
+            power_on: PowerOn::default(),
+            overflow_bug: false,
+            audio: true,
+            region: None,
+            game_configs: BTreeMap::new(),
+        }
+    }
+
+    /// Set whether or not to emulate the contents of PPU memory on power up.
+    ///
+    /// When enabled, palette ram gets the values it has on hardware, and OAM
+    /// is filled with 0xFF. Otherwise, both are filled with zeros.
+    /// This is useful to match reference emulators in tests.
+    ///
+    /// This is disabled by default.
+    pub fn accurate_power_up(mut self, accurate: bool) -> Self {
+        self.power_on.accurate_ppu = accurate;
+        self
+    }
+
+    /// Set how closely the console follows the quirks of the hardware.
+    ///
+    /// This enables or disables every quirk listed in `Accuracy` at once,
+    /// overriding the individual options set before calling this.
+    ///
+    /// This is `Accuracy::Fast` by default.
+    pub fn accuracy(mut self, accuracy: Accuracy) -> Self {
+        let accurate = accuracy == Accuracy::Accurate;
+        self.power_on.accurate_ppu = accurate;
+        self.overflow_bug = accurate;
+        self
+    }
+
+    /// Set whether or not the sprite overflow flag follows the hardware bug.
+    ///
+    /// The PPU has a bug when looking for more than 8 sprites on a line,
+    /// which can miss some of them, or flag lines without that many.
+    /// A few games rely on the flag for timing. Otherwise, the flag is set
+    /// whenever there are more than 8 sprites on a line.
+    ///
+    /// This is disabled by default.
+    pub fn sprite_overflow_bug(mut self, enabled: bool) -> Self {
+        self.overflow_bug = enabled;
+        self
+    }
+
+    /// Set what ram is filled with on power up, and on every power cycle.
+    ///
+    /// Resetting the console keeps ram as it is, like on hardware.
+    /// A `seed` takes precedence over this.
+    ///
+    /// This is `RamFill::Zeros` by default.
+    pub fn ram_fill(mut self, fill: RamFill) -> Self {
+        self.power_on.ram = fill;
+        self
+    }
+
+    /// Fill ram, palette ram and OAM with random values on power up.
+    ///
+    /// The values are generated from a seed, so that two consoles built
+    /// with the same seed and cart behave exactly the same. This is useful
+    /// to find bugs depending on uninitialized memory, in games or in the
+    /// emulator itself, and to reproduce them. Resetting the console fills
+    /// memory with the same values again.
+    ///
+    /// This takes precedence over `accurate_power_up`.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.power_on.seed = Some(seed);
+        self
+    }
+
+    /// Set whether or not to emulate the APU.
+    ///
+    /// Without audio, the APU is never stepped, and no samples are sent to
+    /// the audio device. The CPU and PPU stay as accurate as before, and
+    /// reading the APU status always returns 0. This saves time in
+    /// benchmarks or tests that only care about video.
+    ///
+    /// To run without sound while keeping the APU accurate, keep this
+    /// enabled and step the console with `NullAudio` instead.
+    ///
+    /// This is enabled by default.
+    pub fn audio(mut self, enabled: bool) -> Self {
+        self.audio = enabled;
+        self
+    }
+
+    /// Set the region the console emulates, overriding the one in the header.
+    ///
+    /// By default, the region comes from the header of the cart, falling
+    /// back to NTSC, since most headers don't specify it. A config
+    /// for the game set with `game_configs` takes precedence over this.
+    pub fn region(mut self, region: Region) -> Self {
+        self.region = Some(region);
+        self
+    }
+
+    /// Set the configs to use for specific games, indexed by `Cart::hash`.
+    ///
+    /// If the cart being loaded has a config, its settings override the ones
+    /// of this builder. This lets frontends keep a single table of per game
+    /// overrides, instead of looking them up by hand.
+    pub fn game_configs(mut self, configs: BTreeMap<u32, GameConfig>) -> Self {
+        self.game_configs = configs;
+        self
+    }
+
+    /// Create the console with this configuration.
+    pub fn build(self) -> Console {
+        let config = self.game_configs.get(&self.cart.hash).cloned();
+        let config = config.unwrap_or_default();
+        let hash = self.cart.hash;
+        let region = config.region.or(self.region).or(self.cart.region);
+        let mut cart = self.cart;
+        if let Some(bus_conflicts) = config.bus_conflicts {
+            cart.bus_conflicts = bus_conflicts;
+        }
+        let mut memory = MemoryBus::with_cart(cart, self.power_on);
+        if !self.audio {
+            memory.apu.disable();
+        }
+        let mut ppu = PPU::new(&mut memory);
+        ppu.set_overflow_bug(config.sprite_overflow_bug.unwrap_or(self.overflow_bug));
+        let cpu = CPU::new(memory);
+        let mut console = Console {
+            apu: APU::new(self.sample_rate),
+            cpu,
+            ppu,
+            audio: self.audio,
+            region: Region::default(),
+            dot_remainder: 0,
+            frame: 0,
+            frame_budget: 0,
+            inputs: InputQueue::new(),
+            hud: None,
+            reset_held: false,
+            freezes: Vec::new(),
+            breakpoints: Vec::new(),
+            held: [ButtonState::default(); 2],
+            turbo: Turbo::default(),
+            fast_forward: 1,
+            frame_skip: 0,
+            speed: 1.0,
+            paused: false,
+            rewind: None,
+            movie: None,
+            recording: None,
+            nsf: None,
+            hash,
+        };
+        if let Some(region) = region {
+            console.set_region(region);
+        }
+        if let Some(palette) = config.palette {
+            console.set_palette(palette);
+        }
+        console
+    }
+}
+
+/// Describes how far each processor advanced during a step.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StepResult {
+    /// The number of CPU cycles passed
+    pub cpu_cycles: i32,
+    /// The number of dots the PPU advanced
+    pub ppu_dots: i32,
+    /// The number of times the APU was stepped
+    pub apu_ticks: i32,
+    /// Whether or not the PPU completed a frame during this step
+    pub frame_completed: bool,
+}
+
+/// A movie being played back
+struct Playback {
+    /// The input for each frame of the movie
+    frames: Vec<MovieFrame>,
+    /// The frame of the console the movie started on
+    start: u64,
+}
+
+/// The inputs recorded so far, to make a movie
+#[derive(Default)]
+struct Recording {
+    /// The input of each frame recorded
+    frames: Vec<MovieFrame>,
+    /// The reset and power cycle to record with the current frame
+    commands: MovieFrame,
+}
+
+/// The recent states kept to rewind the game
+struct Rewind {
+    /// The saved states, from oldest to newest
+    snapshots: VecDeque<Vec<u8>>,
+    /// The number of frames between snapshots
+    interval: u32,
+    /// The most snapshots kept, dropping the oldest past that
+    capacity: usize,
+}
+
+/// Describes why `step_until_breakpoint` stopped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BreakReason {
+    /// The CPU is about to execute the instruction at a breakpoint
+    Breakpoint(u16),
+    /// An instruction accessed a watched address
+    Watch(WatchHit),
+    /// The number of cycles allowed ran out first
+    CyclesElapsed,
+}
 
 /// Used to act as an owner of everything needed to run a game
 /// Is also responsible for holding ram,
@@ -13,55 +263,302 @@ pub struct Console {
     apu: APU,
     cpu: CPU,
     ppu: PPU,
+    /// Whether or not the APU gets stepped
+    audio: bool,
+    /// The region determining the timing of each processor
+    region: Region,
+    /// The fraction of a PPU dot left over from the previous step.
+    ///
+    /// This is needed for regions without a whole number of dots per cycle.
+    dot_remainder: i32,
+    /// The number of frames the PPU has completed
+    frame: u64,
+    /// The half cycles left to run by `step_frame_exact`.
+    ///
+    /// This carries the half cycle, and the cycles run past the end of
+    /// a frame by the last instruction, over to the next frame.
+    frame_budget: i32,
+    /// Inputs to apply when their frame starts
+    inputs: InputQueue,
+    /// The HUD drawn over every frame, if enabled
+    hud: Option<Hud>,
+    /// Whether or not the reset button is being held
+    reset_held: bool,
+    /// The addresses frozen to a value, written again every frame
+    freezes: Vec<(u16, u8)>,
+    /// The addresses `step_until_breakpoint` stops at
+    breakpoints: Vec<u16>,
+    /// The buttons held on each controller, before applying turbo
+    held: [ButtonState; 2],
+    /// The rapid fire settings used for both controllers
+    turbo: Turbo,
+    /// How many times faster than normal the console runs
+    fast_forward: u32,
+    /// How many frames are left out after each one sent to the video device
+    frame_skip: u32,
+    /// The speed multiplier applied to the timing based step methods
+    speed: f32,
+    /// Whether or not the timing based step methods are stopped
+    paused: bool,
+    /// The snapshots to rewind to, if enabled
+    rewind: Option<Rewind>,
+    /// The movie feeding inputs, instead of the controllers
+    movie: Option<Playback>,
+    /// The inputs being recorded, if any
+    recording: Option<Recording>,
+    /// The music being played, when playing an NSF instead of a cart
+    nsf: Option<NsfPlayer>,
+    /// The hash of the cart being played, used to check saved states
+    hash: u32,
 }
 
 impl Console {
+    /// Create a new console, with the default configuration.
+    ///
+    /// See `ConsoleBuilder` for more options.
     pub fn new(cart: Cart, sample_rate: u32) -> Self {
-        let mut memory = MemoryBus::with_cart(cart);
-        let ppu = PPU::new(&mut memory);
-        let cpu = CPU::new(memory);
-        Console {
-            apu: APU::new(sample_rate),
-            cpu,
-            ppu,
+        ConsoleBuilder::new(cart, sample_rate).build()
+    }
+
+    /// Create a new console from the bytes of a ROM, with the default configuration.
+    ///
+    /// This doesn't need a filesystem, so the ROM can come from an archive,
+    /// the network, or be embedded with `include_bytes!`.
+    pub fn from_rom_bytes(rom: &[u8], sample_rate: u32) -> Result<Self, CartReadingError> {
+        Ok(Console::new(Cart::from_bytes(rom)?, sample_rate))
+    }
+
+    /// Create a new console playing an NSF music file.
+    ///
+    /// The console runs without the PPU, so no frames are sent to the
+    /// video device. Instead, the CPU calls INIT to start a song, then PLAY
+    /// at the rate given by the header, and each call to PLAY counts as
+    /// a frame for `step_frame`. The region comes from the header, and the
+    /// starting song is played right away. See `play_track` to change it.
+    pub fn from_nsf(nsf: &Nsf, sample_rate: u32) -> Self {
+        let mut console = ConsoleBuilder::new(nsf.cart(), sample_rate)
+            .region(nsf.region())
+            .build();
+        let player = NsfPlayer::new(nsf);
+        let track = player.track;
+        console.nsf = Some(player);
+        console.play_track(track);
+        console
+    }
+
+    /// Starts playing a song of the NSF being played, starting at 0.
+    ///
+    /// Memory and the APU are cleared, then INIT is called with the song
+    /// number. Songs past the last one wrap around to the first. This does
+    /// nothing when playing a cart.
+    pub fn play_track(&mut self, track: u8) {
+        let pal = u8::from(self.region == Region::PAL);
+        let Some(player) = &mut self.nsf else {
+            return;
+        };
+        let (init, banks) = player.start(track, self.region);
+        let track = player.track;
+        let m = &mut self.cpu.mem;
+        for address in (0..0x800).chain(0x6000..0x8000) {
+            m.cpu_write(address, 0);
+        }
+        for address in 0x4000..0x4014 {
+            m.cpu_write(address, 0);
         }
+        m.cpu_write(0x4015, 0);
+        m.cpu_write(0x4015, 0x0F);
+        m.cpu_write(0x4017, 0x40);
+        for (address, bank) in (0x5FF8..).zip(banks) {
+            m.cpu_write(address, bank);
+        }
+        m.cpu.clear_interrupt();
+        self.cpu.power_up();
+        self.cpu.set_arguments(track, pal);
+        self.cpu.call(init, IDLE_LOOP);
     }
 
-    /// Advance the console by a single CPU cycle.
-    /// 
+    /// Returns the song being played from an NSF, starting at 0
+    pub fn track(&self) -> Option<u8> {
+        self.nsf.as_ref().map(|player| player.track)
+    }
+
+    /// Returns the number of songs in the NSF being played
+    pub fn track_count(&self) -> Option<u8> {
+        self.nsf.as_ref().map(|player| player.song_count)
+    }
+
+    /// Advance the console by a single CPU instruction.
+    ///
     /// This needs access to the audio and video devices, because the APU
     /// may generate audio samples, and the PPU may generate a frame.
-    pub fn step<A, V>(&mut self, audio: &mut A, video: &mut V) -> i32
+    ///
+    /// Once this returns, the other processors have caught up with the CPU:
+    /// the APU and mapper have been stepped once per CPU cycle, and the PPU
+    /// 3 times per cycle, or 3.2 times on PAL, where the fraction of a dot
+    /// left over is carried to the next step. This makes it possible to
+    /// look at the state of the console after any instruction, with
+    /// `registers` and `peek`.
+    ///
+    /// An interrupt being handled counts as part of the next instruction.
+    ///
+    /// This returns the number of CPU cycles passed, or the first error
+    /// the console ran into during the instruction. The console can keep
+    /// running after an error, or be reset to recover from it.
+    pub fn step<A, V>(&mut self, audio: &mut A, video: &mut V) -> Result<i32, EmulationError>
     where
         A: AudioDevice,
         V: VideoDevice,
     {
-        let cpucycles = self.cpu.step();
-        let m = &mut self.cpu.mem;
-        for _ in 0..cpucycles * 3 {
-            self.ppu.step(m, video);
+        self.cpu.mem.error = None;
+        let cycles = self.step_unchecked(audio, video);
+        match self.cpu.mem.error.take() {
+            None => Ok(cycles),
+            Some(error) => Err(error),
         }
+    }
+
+    /// Advance the console by a single CPU instruction, ignoring errors.
+    ///
+    /// This is what the other step methods use. Errors don't stop
+    /// the console, so this never panics either, but the game might
+    /// misbehave without any way to tell why.
+    pub fn step_unchecked<A, V>(&mut self, audio: &mut A, video: &mut V) -> i32
+    where
+        A: AudioDevice,
+        V: VideoDevice,
+    {
+        self.step_detailed(audio, video).cpu_cycles
+    }
+
+    /// Advance the console by a single CPU instruction, like `step_unchecked`.
+    ///
+    /// This returns how far each processor advanced, instead of
+    /// only the number of CPU cycles.
+    pub fn step_detailed<A, V>(&mut self, audio: &mut A, video: &mut V) -> StepResult
+    where
+        A: AudioDevice,
+        V: VideoDevice,
+    {
+        // The CPU doesn't run while reset is held, but time still passes
+        let instruction = !self.reset_held && !self.cpu.mem.cpu.stalled();
+        let cpucycles = if self.reset_held { 1 } else { self.cpu.step() };
+        let pc = self.cpu.pc();
+        let m = &mut self.cpu.mem;
         for _ in 0..cpucycles {
-            self.apu.step(m, audio);
+            m.step_mapper();
+        }
+        // Music doesn't need the PPU, so PLAY is called on a timer instead
+        let play_due = match &mut self.nsf {
+            Some(player) => Some(player.tick(cpucycles, self.region)),
+            None => None,
+        };
+        let (numerator, denominator) = self.region.ppu_dots_per_cycle();
+        let dots = cpucycles * numerator + self.dot_remainder;
+        self.dot_remainder = dots % denominator;
+        let ppu_dots = if play_due.is_some() {
+            0
+        } else {
+            dots / denominator
+        };
+        // The frame being drawn is frame + 1, once completed
+        let skip_period = u64::from(self.frame_skip) + 1;
+        let skipped = !(self.frame + 1).is_multiple_of(skip_period);
+        let mut frame_happened = play_due.unwrap_or(false);
+        for _ in 0..ppu_dots {
+            let happened = match &mut self.hud {
+                _ if skipped => self.ppu.step(m, &mut NullVideo),
+                None => self.ppu.step(m, video),
+                Some(hud) => {
+                    let mut device = HudDevice {
+                        video: &mut *video,
+                        hud,
+                        frame: self.frame,
+                        pc,
+                    };
+                    self.ppu.step(m, &mut device)
+                }
+            };
+            frame_happened = happened || frame_happened;
+        }
+        if let Some(hud) = &mut self.hud {
+            hud.add_cycles(cpucycles, self.region.cpu_frequency());
+        }
+        let mut apu_ticks = 0;
+        if self.audio {
+            for cycle in 0..cpucycles {
+                m.on_last_access = instruction && cycle == cpucycles - 1;
+                self.apu.step(m, audio);
+            }
+            m.on_last_access = false;
+            apu_ticks = cpucycles;
+        }
+        // PLAY is skipped if the previous call or INIT is still running
+        let idle = (IDLE_LOOP..IDLE_LOOP + 3).contains(&pc);
+        if let (Some(true), Some(player), true) = (play_due, &self.nsf, idle) {
+            let play = player.play;
+            self.cpu.call(play, IDLE_LOOP);
+        }
+        if frame_happened {
+            self.start_frame();
+        }
+        StepResult {
+            cpu_cycles: cpucycles,
+            ppu_dots,
+            apu_ticks,
+            frame_completed: frame_happened,
         }
-        cpucycles
     }
 
     /// Advance the console by a certain number of micro seconds.
+    ///
+    /// While paused, this only sends silence to the audio device.
     pub fn step_micros<A, V>(&mut self, audio: &mut A, video: &mut V, micros: u32)
     where
         A: AudioDevice,
         V: VideoDevice,
     {
-        // This emulates 1.79 cpu cycles per microsecond
-        let mut cpu_cycles = ((micros * 179) / 100) as i32;
+        if self.paused {
+            self.apu.push_silence(audio, micros);
+            return;
+        }
+        let emulated = (micros as f32 * self.speed) as u32;
+        for _ in 1..self.fast_forward {
+            self.run_micros(&mut NullAudio, &mut NullVideo, emulated);
+        }
+        if self.fast_forward > 1 {
+            self.run_shown_micros(&mut NullAudio, video, emulated, micros);
+        } else {
+            self.run_shown_micros(audio, video, emulated, micros);
+        }
+    }
+
+    /// Runs for some emulated time, only showing the frames of the last
+    /// `shown` micro seconds, so that running above normal speed doesn't
+    /// send frames faster than they can be displayed.
+    fn run_shown_micros<A, V>(&mut self, audio: &mut A, video: &mut V, micros: u32, shown: u32)
+    where
+        A: AudioDevice,
+        V: VideoDevice,
+    {
+        let hidden = micros.saturating_sub(shown);
+        self.run_micros(audio, &mut NullVideo, hidden);
+        self.run_micros(audio, video, micros - hidden);
+    }
+
+    fn run_micros<A, V>(&mut self, audio: &mut A, video: &mut V, micros: u32)
+    where
+        A: AudioDevice,
+        V: VideoDevice,
+    {
+        let frequency = u64::from(self.region.cpu_frequency());
+        let mut cpu_cycles = (u64::from(micros) * frequency / 1_000_000) as i32;
         while cpu_cycles > 0 {
-            cpu_cycles -= self.step(audio, video);
+            cpu_cycles -= self.step_unchecked(audio, video);
         }
     }
 
     /// Advance the console until the next frame.
-    /// 
+    ///
     /// Unlike the other step methods, this is not based on timing, but
     /// based on waiting until the ppu actually generates a video frame.
     /// This is more useful for applications that want to do something
@@ -72,28 +569,1020 @@ impl Console {
         A: AudioDevice,
         V: VideoDevice,
     {
-        let mut frame_happened = false;
-        while !frame_happened {
-            let cpucycles = self.cpu.step();
-            let m = &mut self.cpu.mem;
-            for _ in 0..cpucycles * 3 {
-                frame_happened = self.ppu.step(m, video) || frame_happened;
+        for _ in 1..self.fast_forward {
+            while !self
+                .step_detailed(&mut NullAudio, &mut NullVideo)
+                .frame_completed
+            {}
+        }
+        if self.fast_forward > 1 {
+            while !self.step_detailed(&mut NullAudio, video).frame_completed {}
+        } else {
+            while !self.step_detailed(audio, video).frame_completed {}
+        }
+    }
+
+    /// Advance the console by exactly one frame worth of CPU cycles.
+    ///
+    /// Unlike `step_micros`, this doesn't depend on how much time passed
+    /// on the host, which makes it suitable for a fixed timestep loop, and
+    /// for deterministic playback. A frame is 29780.5 cycles on NTSC, and
+    /// 33247.5 on PAL, so frames alternate between running one cycle more
+    /// and one cycle less. Since instructions can't be interrupted, the last
+    /// one might run past the budget, in which case the next frame runs
+    /// that much less. This returns the number of cycles actually run.
+    ///
+    /// Unlike `step_frame`, this isn't aligned with the frames the PPU
+    /// generates, but it keeps exactly the same pace over time.
+    ///
+    /// While paused, this runs nothing and sends a frame of silence to
+    /// the audio device, returning 0.
+    pub fn step_frame_exact<A, V>(&mut self, audio: &mut A, video: &mut V) -> i32
+    where
+        A: AudioDevice,
+        V: VideoDevice,
+    {
+        if self.paused {
+            let half_cycles = f64::from(self.region.frame_half_cycles());
+            let micros = half_cycles * 500_000.0 / f64::from(self.region.cpu_frequency());
+            self.apu.push_silence(audio, micros as u32);
+            return 0;
+        }
+        let mut cpu_cycles = 0;
+        for _ in 1..self.fast_forward {
+            cpu_cycles += self.run_frame_exact(&mut NullAudio, &mut NullVideo);
+        }
+        if self.fast_forward > 1 {
+            cpu_cycles + self.run_frame_exact(&mut NullAudio, video)
+        } else {
+            cpu_cycles + self.run_frame_exact(audio, video)
+        }
+    }
+
+    fn run_frame_exact<A, V>(&mut self, audio: &mut A, video: &mut V) -> i32
+    where
+        A: AudioDevice,
+        V: VideoDevice,
+    {
+        let frame = self.region.frame_half_cycles();
+        self.frame_budget += (f64::from(frame) * f64::from(self.speed)) as i32;
+        let mut cpu_cycles = 0;
+        // Above normal speed, only the frames of the last frame worth of
+        // cycles are shown
+        while self.frame_budget >= frame + 2 {
+            let cycles = self.step_unchecked(audio, &mut NullVideo);
+            self.frame_budget -= 2 * cycles;
+            cpu_cycles += cycles;
+        }
+        while self.frame_budget >= 2 {
+            let cycles = self.step_unchecked(audio, video);
+            self.frame_budget -= 2 * cycles;
+            cpu_cycles += cycles;
+        }
+        cpu_cycles
+    }
+
+    /// Advance the console until the next frame, without any devices.
+    ///
+    /// Audio is thrown away, and the completed frame is returned instead
+    /// of being sent to a video device. This is meant for running games
+    /// headlessly, like taking screenshots, or checking that the frames
+    /// of a game don't change between versions.
+    pub fn run_frame(&mut self) -> &PixelBuffer {
+        self.step_frame(&mut NullAudio, &mut NullVideo);
+        self.ppu.front_buffer()
+    }
+
+    /// Called whenever the PPU completes a frame, to start the next one.
+    fn start_frame(&mut self) {
+        if let Some(recording) = &mut self.recording {
+            let [player1, player2] = self.held;
+            recording.frames.push(MovieFrame {
+                player1,
+                player2,
+                ..recording.commands
+            });
+            recording.commands = MovieFrame::default();
+        }
+        self.frame += 1;
+        for &(address, value) in &self.freezes {
+            self.cpu.mem.cpu_write(address, value);
+        }
+        if let Some(input) = self.inputs.get(self.frame) {
+            self.apply_input(input);
+        }
+        self.apply_movie_frame();
+        self.apply_buttons();
+        let frame = self.frame;
+        let due = matches!(&self.rewind, Some(rewind) if frame.is_multiple_of(u64::from(rewind.interval)));
+        if due {
+            let state = self.save_state();
+            if let Some(rewind) = &mut self.rewind {
+                if rewind.snapshots.len() >= rewind.capacity {
+                    rewind.snapshots.pop_front();
+                }
+                rewind.snapshots.push_back(state);
             }
-            for _ in 0..cpucycles {
-                self.apu.step(m, audio);
+        }
+    }
+
+    fn apply_input(&mut self, input: FrameInput) {
+        self.held = [input.player1, input.player2];
+        self.set_microphone(input.microphone);
+    }
+
+    /// Applies the input of the movie for the current frame, if one is playing.
+    ///
+    /// Once the movie runs out, the buttons are left as they were on the last
+    /// frame, and the controllers can be used again.
+    fn apply_movie_frame(&mut self) {
+        let frame = match &self.movie {
+            None => return,
+            // Going back before the movie started, with a saved state or
+            // by rewinding, stops it like running out of frames does
+            Some(playback) => self
+                .frame
+                .checked_sub(playback.start)
+                .and_then(|index| playback.frames.get(index as usize))
+                .copied(),
+        };
+        let frame = match frame {
+            None => {
+                self.movie = None;
+                return;
             }
+            Some(frame) => frame,
+        };
+        if frame.power {
+            self.power_cycle();
+        } else if frame.reset {
+            self.reset();
         }
+        self.held = [frame.player1, frame.player2];
+    }
+
+    /// Passes the buttons held to the controllers, with turbo applied
+    fn apply_buttons(&mut self) {
+        let [player1, player2] = self.held;
+        self.cpu.set_buttons(self.turbo.apply(player1, self.frame));
+        let player2 = self.turbo.apply(player2, self.frame);
+        self.cpu.mem.controller2.set_buttons(player2);
+    }
+
+    /// Set whether or not the microphone on the second controller picks up sound.
+    ///
+    /// This is only present on the Famicom, and is read through bit 2 of $4016.
+    /// Frontends can drive it with a key, or with the volume of a real microphone.
+    pub fn set_microphone(&mut self, active: bool) {
+        self.cpu.mem.microphone = active;
+    }
+
+    /// Signal an NMI to the CPU, as if the PPU had entered vblank.
+    ///
+    /// The CPU handles the interrupt before its next instruction.
+    /// This is meant for testing interrupt handlers in isolation.
+    pub fn trigger_nmi(&mut self) {
+        self.cpu.mem.cpu.set_nmi();
+    }
+
+    /// Signal an IRQ to the CPU, as if the APU or the cart had requested one.
+    ///
+    /// Like a real IRQ, this is ignored if the interrupt disable flag is set
+    /// when the CPU executes its next instruction. An NMI that's already
+    /// pending takes priority over this.
+    pub fn trigger_irq(&mut self) {
+        self.cpu.mem.cpu.set_irq();
     }
 
     pub fn update_controller(&mut self, buttons: ButtonState) {
-        self.cpu.set_buttons(buttons);
+        if self.movie.is_none() {
+            self.held[0] = buttons;
+            self.apply_buttons();
+        }
+    }
+
+    /// Update the buttons held on the second controller, read through $4017.
+    pub fn update_controller_p2(&mut self, buttons: ButtonState) {
+        if self.movie.is_none() {
+            self.held[1] = buttons;
+            self.apply_buttons();
+        }
+    }
+
+    /// Start playing back a movie, feeding its inputs to the controllers.
+    ///
+    /// The console is power cycled first, since movies start from power on,
+    /// and switched to the region the movie was recorded in. Each frame then
+    /// uses the next input of the movie, and `update_controller` is ignored
+    /// until the movie runs out. Turbo should be off, since it would change
+    /// the inputs recorded. Loading a state from before the movie started
+    /// stops it.
+    ///
+    /// This fails without changing anything if the movie records the
+    /// checksum of a different ROM.
+    pub fn play_movie(&mut self, movie: &Movie) -> Result<(), MovieError> {
+        if let Some(checksum) = movie.checksum() {
+            if checksum != self.cpu.mem.mapper.cart().md5() {
+                return Err(MovieError::WrongRom);
+            }
+        }
+        let region = if movie.pal() {
+            Region::PAL
+        } else {
+            Region::NTSC
+        };
+        self.set_region(region);
+        self.power_cycle();
+        self.movie = Some(Playback {
+            frames: movie.frames().to_vec(),
+            start: self.frame,
+        });
+        self.apply_movie_frame();
+        self.apply_buttons();
+        Ok(())
+    }
+
+    /// Start recording the inputs of each frame, to make a movie.
+    ///
+    /// The console is power cycled first, like when playing a movie back.
+    /// At the end of each frame, the buttons held on both controllers are
+    /// recorded, along with any reset or power cycle since the last frame.
+    /// Buttons should be updated before running a frame, rather than
+    /// in the middle of one, for the movie to play back the same way.
+    pub fn start_recording(&mut self) {
+        self.power_cycle();
+        self.recording = Some(Recording::default());
+    }
+
+    /// Stop recording inputs, returning the movie recorded.
+    ///
+    /// The movie is empty if nothing was being recorded.
+    pub fn stop_recording(&mut self) -> Movie {
+        let frames = match self.recording.take() {
+            None => return Movie::default(),
+            Some(recording) => recording.frames,
+        };
+        let checksum = self.cpu.mem.mapper.cart().md5();
+        Movie::new(Some(checksum), self.region == Region::PAL, frames)
     }
 
-    /// Resets everything to it's initial state
+    /// Returns true if a movie is still feeding inputs to the controllers.
+    pub fn movie_playing(&self) -> bool {
+        self.movie.is_some()
+    }
+
+    /// Stop playing a movie, giving control back to `update_controller`.
+    pub fn stop_movie(&mut self) {
+        self.movie = None;
+    }
+
+    /// Pause the console, stopping `step_micros` and `step_frame_exact`.
+    ///
+    /// The state of the console is kept as is, and those methods send
+    /// silence to the audio device instead, so that an audio buffer being
+    /// played doesn't run dry and repeat stale samples. The silence holds
+    /// the last sample sent, so pausing doesn't click. The frontend can
+    /// keep calling them as usual, with the time passed while paused being
+    /// dropped, so resuming doesn't catch up on it. The other step methods
+    /// still run, which lets a frontend advance frame by frame while paused.
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    /// Resume running after `pause`, from where the console stopped.
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    /// Returns true if the console was paused with `pause`
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Run several frames for every frame of `step_micros`, `step_frame`,
+    /// and `step_frame_exact`, to speed through slow parts of a game.
+    ///
+    /// A factor of 1, or 0, runs at normal speed. While fast forwarding,
+    /// audio is thrown away, since it would pile up faster than it can be
+    /// played, and only the last of the frames run is sent to the video
+    /// device. Going back to normal speed doesn't disturb timing.
+    pub fn set_fast_forward(&mut self, factor: u32) {
+        self.fast_forward = factor.max(1);
+    }
+
+    /// Leave out some frames from the video device, for hosts too slow to
+    /// display every frame.
+    ///
+    /// After each frame sent to the video device, the next `skip` frames
+    /// aren't, so 1 sends every other frame. These frames are still fully
+    /// rendered, keeping sprite 0 hits and timing the same, and audio keeps
+    /// playing as usual. `framebuffer` keeps returning the last frame
+    /// rendered, shown or not. A skip of 0, the default, sends every frame.
+    pub fn set_frame_skip(&mut self, skip: u32) {
+        self.frame_skip = skip;
+    }
+
+    /// Run the console faster or slower than normal, like at half speed to
+    /// study fast action, or at twice the speed to get through slow parts.
+    ///
+    /// This scales the time emulated by `step_micros` and `step_frame_exact`,
+    /// while `step_frame` still runs a single frame. The speed is kept between
+    /// 0.125 and 8, and 1 runs at normal speed. Samples are still sent at the
+    /// same rate, so the pitch of the audio follows the speed. Below normal
+    /// speed every frame is sent to the video device, but above it, only the
+    /// frames run during the last frame worth of time are, so the video device
+    /// isn't flooded. Unlike `set_fast_forward`, audio keeps playing.
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = if speed.is_nan() {
+            1.0
+        } else {
+            speed.clamp(0.125, 8.0)
+        };
+        self.apu.set_speed(self.speed);
+    }
+
+    /// Set the colors output by the PPU, like those read from a `.pal` file.
+    ///
+    /// This takes effect from the next pixel drawn, and stays across resets
+    /// and loaded states. Use `Palette::default()` to go back to the colors
+    /// built into the crate.
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.cpu.mem.ppu.colors = palette;
+    }
+
+    /// Plug a Zapper in the second port, aimed at a pixel of the screen.
+    ///
+    /// This should be called every frame with the position of the mouse,
+    /// or whatever the Zapper is emulated with, and whether the trigger
+    /// is pulled. Like on hardware, light is sensed when the PPU draws
+    /// a bright pixel where the Zapper is aimed, and for a few scanlines
+    /// after that. Aiming outside of the screen never senses light.
+    pub fn set_zapper(&mut self, x: u16, y: u16, trigger: bool) {
+        match &mut self.cpu.mem.zapper {
+            Some(zapper) => {
+                zapper.x = x;
+                zapper.y = y;
+                zapper.trigger = trigger;
+            }
+            None => self.cpu.mem.zapper = Some(Zapper::new(x, y, trigger)),
+        }
+    }
+
+    /// Unplug the Zapper, putting the second controller back in its port.
+    pub fn remove_zapper(&mut self) {
+        self.cpu.mem.zapper = None;
+    }
+
+    /// Set which buttons fire rapidly while held, on both controllers.
+    ///
+    /// Turbo buttons alternate between pressed and released every
+    /// `period` frames, for games expecting the button to be mashed.
+    pub fn set_turbo(&mut self, turbo: Turbo) {
+        self.turbo = turbo;
+        self.apply_buttons();
+    }
+
+    /// Set whether or not to draw a HUD over every frame.
+    ///
+    /// The HUD shows the number of frames per second, the current frame
+    /// number, and the program counter of the CPU. It's drawn over the frame
+    /// before it's sent to the video device, but not over the frame returned
+    /// by `capture_frame`. Since the console has no clock of its own, the
+    /// frames per second are measured against emulated time, every 60 frames,
+    /// so they only drop below the rate of the region when frames are skipped.
+    pub fn set_hud(&mut self, enabled: bool) {
+        if !enabled {
+            self.hud = None;
+        } else if self.hud.is_none() {
+            self.hud = Some(Hud::new());
+        }
+    }
+
+    /// Returns the region the console is currently emulating.
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// Change the region the console emulates, while it's running.
+    ///
+    /// This changes the speed of the CPU, the number of scanlines per frame,
+    /// and the timing of the APU. Since the PPU might be on a scanline
+    /// that doesn't exist in the new region, it gets moved back to the end
+    /// of the visible part of the frame.
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.dot_remainder = 0;
+        self.ppu.set_region(region);
+        self.apu.set_region(region);
+        self.cpu.mem.ppu.swap_emphasis = region.swaps_emphasis();
+    }
+
+    /// Change the rate of the samples sent to the audio device.
+    ///
+    /// This can be used when the audio device is set up again, with a
+    /// different rate than the one given when making the console.
+    /// The samples are resampled from the CPU rate, so the pitch stays
+    /// the same whatever the region or sample rate.
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.apu.set_sample_rate(sample_rate);
+    }
+
+    /// Change the filters applied to the audio, or remove them with None.
+    ///
+    /// By default, the audio goes through the same filters as on the NES,
+    /// which center it and soften harsh edges. Without them, the output
+    /// is the raw mix of the channels, between 0 and 1.
+    pub fn set_audio_filter(&mut self, filter: Option<AudioFilter>) {
+        self.apu.set_filter(filter);
+    }
+
+    /// Returns the scanline the PPU is on, and the dot on that scanline.
+    ///
+    /// Visible scanlines go from 0 to 239, and the pre-render line is the
+    /// last one, 261 on NTSC. Each has 341 dots, from 0 to 340. Checked
+    /// after each `step`, this tells which dot a write happened around,
+    /// to look into mid frame changes of palettes or scrolling for example.
+    /// Since the PPU runs after each instruction, this is the position at
+    /// the end of the last one.
+    pub fn ppu_position(&self) -> (i32, i32) {
+        self.ppu.position()
+    }
+
+    /// Returns the number of frames the console has completed.
+    ///
+    /// This is the frame number used to index queued inputs.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Queue up the input to use for a given frame.
+    ///
+    /// The input is applied as soon as that frame starts, or immediately
+    /// if it's the current frame. Queuing input for a frame that already
+    /// has some will overwrite it, including for frames that have already
+    /// been played.
+    ///
+    /// This is the building block for rollback netplay: local inputs are
+    /// queued a few frames ahead of time, and remote inputs are queued as
+    /// they arrive. When a remote input arrives for a frame that has already
+    /// been played with a predicted input, the console is restored to its
+    /// state before that frame, the correct input is queued, and
+    /// `step_frame` is called until the console is back to the present.
+    /// Since queued inputs are kept around, the frames being simulated again
+    /// will use the same inputs as the first time, except for the corrected
+    /// ones.
+    pub fn queue_input(&mut self, frame: u64, input: FrameInput) {
+        self.inputs.insert(frame, input);
+        if frame == self.frame {
+            self.apply_input(input);
+        }
+    }
+
+    /// Queue up every input in a script, relative to the current frame.
+    ///
+    /// Frame 0 in the script is the current frame, so a script can be
+    /// queued at any point. Frames later in the script are queued as if
+    /// each of them was passed to `queue_input`.
+    pub fn queue_script(&mut self, script: &InputScript) {
+        let start = self.frame;
+        for (frame, input) in script.inputs() {
+            self.queue_input(start + frame, input);
+        }
+    }
+
+    /// Forget about every input queued for a frame before the one given.
+    ///
+    /// Inputs are kept around after being played, in order to allow rolling
+    /// back, so this should be called once it's no longer possible to roll
+    /// back to a frame.
+    pub fn discard_inputs_before(&mut self, frame: u64) {
+        self.inputs.discard_before(frame);
+    }
+
+    /// Returns the sprites in range of a scanline, in the order of OAM.
+    ///
+    /// This uses the same selection as the PPU, taking the current sprite
+    /// size into account, but doesn't change any state. Only the first 8
+    /// sprites are drawn, and are marked as such. If more than 8 are returned,
+    /// the sprite overflow flag would get set on that line.
+    ///
+    /// Since the PPU selects sprites on the line before they're displayed,
+    /// a sprite appears one line below the Y position stored in OAM.
+    /// The line given here is the line the sprites are displayed on.
+    pub fn sprites_on_scanline(&self, line: i32) -> Vec<SpriteEntry> {
+        self.cpu.mem.ppu.sprites_on_scanline(line)
+    }
+
+    /// Returns the 64 sprites in OAM, in order, with their attributes decoded.
+    ///
+    /// This shows every sprite, including those hidden off screen.
+    /// `SpriteInfo::on_scanline` tells whether one is in range of a line.
+    pub fn sprites(&self) -> [SpriteInfo; 64] {
+        self.cpu.mem.ppu.sprites()
+    }
+
+    /// Returns the last frame the PPU completed.
+    ///
+    /// Like `capture_frame`, this is the frame most recently sent to the
+    /// video device, but without copying it. Pass it to `encode_png` to
+    /// take a screenshot.
+    pub fn framebuffer(&self) -> &PixelBuffer {
+        self.ppu.front_buffer()
+    }
+
+    /// Returns the index in the system palette of each pixel of the last frame.
+    ///
+    /// The pixels are in the same order as `framebuffer`, with indices from
+    /// 0 to 63. Grayscale is applied to them, but not emphasis, which is
+    /// given by the mask register. This is meant for tools that need the
+    /// exact color the NES used, instead of guessing it from RGB values.
+    pub fn index_buffer(&self) -> &[u8; NES_WIDTH * NES_HEIGHT] {
+        self.ppu.index_buffer()
+    }
+
+    /// Returns a copy of the last frame the PPU completed.
+    ///
+    /// This is the frame most recently sent to the video device, regardless
+    /// of how far the PPU is into rendering the next one, which makes it
+    /// suitable for screenshots. The buffer is rather large, so it
+    /// should be boxed if it needs to be kept around.
+    pub fn capture_frame(&self) -> PixelBuffer {
+        self.ppu.front_buffer().clone()
+    }
+
+    /// Draws the background of the four nametables, as arranged in PPU memory.
+    ///
+    /// This returns `NAMETABLES_WIDTH` by `NAMETABLES_HEIGHT` pixels in
+    /// row order, using the same format as `PixelBuffer`. Every tile is
+    /// drawn with the current CHR, attributes and palettes, including the
+    /// parts of the nametables scrolled off screen, which helps with
+    /// debugging scrolling and mirroring.
+    pub fn render_nametables(&self) -> Vec<u32> {
+        let mut pixels = vec![0; NAMETABLES_WIDTH * NAMETABLES_HEIGHT];
+        self.ppu.render_nametables(&self.cpu.mem, &mut pixels);
+        pixels
+    }
+
+    /// Draws both pattern tables side by side, using a background palette.
+    ///
+    /// The table at $0000 is on the left, and the one at $1000 on the right,
+    /// each as a 128x128 sheet of tiles, in the top half of the buffer.
+    /// CHR is read through the mapper, so this shows the banks currently
+    /// selected. `palette` picks one of the 4 background palettes.
+    pub fn render_pattern_tables(&self, palette: u8) -> PixelBuffer {
+        let mut pixels = PixelBuffer::default();
+        self.ppu
+            .render_pattern_tables(&self.cpu.mem, palette, &mut pixels);
+        pixels
+    }
+
+    /// Saves the state of the PPU visible to the rest of the console.
+    ///
+    /// This contains palette ram, nametables, OAM, the scroll registers,
+    /// and the flags set through PPU registers, but nothing about the
+    /// CPU, APU, or the cart. It's meant for debugging graphics, by
+    /// freezing the visual state, or comparing two states.
+    pub fn save_ppu_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::new(PPU_STATE_MAGIC);
+        self.cpu.mem.ppu.save(&mut w);
+        w.finish()
+    }
+
+    /// Restores the state of the PPU saved by `save_ppu_state`.
+    ///
+    /// The PPU is left untouched if the state can't be read.
+    pub fn load_ppu_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let mut r = StateReader::new(state, PPU_STATE_MAGIC)?;
+        self.cpu.mem.ppu.replace(PPUState::load(&mut r)?);
+        Ok(())
+    }
+
+    /// Saves the state of the whole console.
+    ///
+    /// This contains everything needed to resume the game later on, from
+    /// the exact same cycle: the state of the CPU, PPU, APU, ram, and the
+    /// registers of the mapper, along with save ram. Queued inputs, the HUD,
+    /// and trace settings aren't part of the state.
+    ///
+    /// The state starts with a header and a version, so that states saved
+    /// by incompatible versions of this crate are rejected when loading.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::new(STATE_MAGIC);
+        w.u32(self.hash);
+        w.u8(match self.region {
+            Region::NTSC => 0,
+            Region::PAL => 1,
+        });
+        w.u64(self.frame);
+        w.i32(self.dot_remainder);
+        w.i32(self.frame_budget);
+        w.bool(self.reset_held);
+        self.cpu.save(&mut w);
+        self.ppu.save(&mut w);
+        self.apu.save(&mut w);
+        w.finish()
+    }
+
+    /// Start keeping snapshots of the console, to go back in time with `rewind_step`.
+    ///
+    /// A snapshot is taken every `interval` frames, and only the last
+    /// `capacity` are kept, which bounds the memory used. Each snapshot
+    /// is a full saved state, about half a megabyte, so keeping 10 seconds
+    /// with a snapshot every 6 frames uses about 50MB. Calling this again
+    /// drops the snapshots taken so far, and a capacity of 0 stops taking them.
+    pub fn enable_rewind(&mut self, interval: u32, capacity: usize) {
+        self.rewind = if capacity == 0 {
+            None
+        } else {
+            Some(Rewind {
+                snapshots: VecDeque::with_capacity(capacity),
+                interval: interval.max(1),
+                capacity,
+            })
+        };
+    }
+
+    /// Go back to the most recent snapshot, removing it.
+    ///
+    /// Calling this every frame while a key is held plays the game backwards,
+    /// one snapshot at a time. This returns false, without changing anything,
+    /// once there are no snapshots left.
+    pub fn rewind_step(&mut self) -> bool {
+        let state = match self.rewind.as_mut().and_then(|r| r.snapshots.pop_back()) {
+            Some(state) => state,
+            None => return false,
+        };
+        // The snapshot was saved by this console, so it always loads
+        self.load_state(&state).is_ok()
+    }
+
+    /// Restores a state saved by `save_state`.
+    ///
+    /// The console continues from where the state was saved, without being
+    /// reset. States saved while playing a different cart are rejected.
+    /// If the state can't be loaded, the console is left untouched.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let mut r = StateReader::new(state, STATE_MAGIC)?;
+        if r.u32()? != self.hash {
+            return Err(StateError::WrongCart);
+        }
+        let backup = self.save_state();
+        let result = self.read_state(&mut r);
+        if result.is_err() {
+            let mut r = StateReader::new(&backup, STATE_MAGIC)?;
+            r.u32()?;
+            self.read_state(&mut r)?;
+        }
+        result
+    }
+
+    /// Reads the state following the header and hash
+    fn read_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        let region = if r.u8()? == 0 {
+            Region::NTSC
+        } else {
+            Region::PAL
+        };
+        // This needs to come first, since it resets part of the timing
+        self.set_region(region);
+        self.frame = r.u64()?;
+        self.dot_remainder = r.i32()?;
+        self.frame_budget = r.i32()?;
+        self.reset_held = r.bool()?;
+        self.cpu.load(r)?;
+        self.ppu.load(r)?;
+        self.apu.load(r)
+    }
+
+    /// Add a Game Genie code, patching the values the CPU reads from PRG.
+    ///
+    /// The code takes effect immediately, and stays active until removed,
+    /// even after a reset. Adding the same code twice has no effect.
+    pub fn add_cheat(&mut self, code: &str) -> Result<(), CheatError> {
+        let cheat = Cheat::from_game_genie(code)?;
+        let cheats = &mut self.cpu.mem.cheats;
+        if !cheats.contains(&cheat) {
+            cheats.push(cheat);
+        }
+        Ok(())
+    }
+
+    /// Remove a Game Genie code added with `add_cheat`.
+    ///
+    /// Nothing happens if the code wasn't active, but it still needs to
+    /// be valid.
+    pub fn remove_cheat(&mut self, code: &str) -> Result<(), CheatError> {
+        let cheat = Cheat::from_game_genie(code)?;
+        self.cpu.mem.cheats.retain(|&active| active != cheat);
+        Ok(())
+    }
+
+    /// Write a value to memory, as if the CPU had stored it.
+    ///
+    /// This goes through the same path as CPU writes, so writing to
+    /// a register has the same effect as when a game does it.
+    pub fn poke(&mut self, address: u16, value: u8) {
+        self.cpu.mem.cpu_write(address, value);
+    }
+
+    /// Read a value from memory, as if the CPU had loaded it.
+    ///
+    /// Like `poke`, this goes through the same path as the CPU, so reading
+    /// a register has the same side effects as when a game does it.
+    /// Use `peek` to look at memory without changing it.
+    pub fn read(&mut self, address: u16) -> u8 {
+        self.cpu.mem.cpu_read(address)
+    }
+
+    /// Read a value from memory, without disturbing the console.
+    ///
+    /// This is meant for debuggers, which shouldn't change what they look at.
+    /// Unlike `read`, reading a register has no side effects, so PPUSTATUS
+    /// keeps its vblank flag, and $4015 its frame interrupt. Some registers
+    /// return approximate values this way: PPUDATA returns what a read would,
+    /// without moving to the next address, OAMDATA ignores its glitches
+    /// during rendering, and controllers return their next bit.
+    pub fn peek(&self, address: u16) -> u8 {
+        self.cpu.mem.peek(address)
+    }
+
+    /// Read a range of memory, without disturbing the console.
+    ///
+    /// This is meant for memory viewers, to dump the zero page or the stack
+    /// for example. RAM mirrors and cheats are taken into account, and
+    /// registers are read like `peek` does, without side effects.
+    /// Addresses past $FFFF wrap around to $0000.
+    pub fn read_range(&self, start: u16, len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|offset| self.peek(start.wrapping_add(offset as u16)))
+            .collect()
+    }
+
+    /// Keep a value in RAM, writing it again at the start of every frame.
+    ///
+    /// This is how most trainers work, like keeping the number of lives
+    /// from going down. The value is written right away, then every time
+    /// the PPU completes a frame, whichever method is used to step the
+    /// console. Freezing an address again replaces its value.
+    ///
+    /// Only RAM and cart RAM, in $0000-$1FFF and $6000-$7FFF, can be frozen,
+    /// since writing registers every frame would disrupt the game.
+    /// This returns false, without freezing anything, for other addresses.
+    pub fn freeze(&mut self, address: u16, value: u8) -> bool {
+        if !(address < 0x2000 || (0x6000..0x8000).contains(&address)) {
+            return false;
+        }
+        self.unfreeze(address);
+        self.freezes.push((address, value));
+        self.poke(address, value);
+        true
+    }
+
+    /// Stop writing a value to an address frozen with `freeze`.
+    pub fn unfreeze(&mut self, address: u16) {
+        self.freezes.retain(|&(frozen, _)| frozen != address);
+    }
+
+    /// Returns the contents of battery backed RAM, if the cart has any.
+    ///
+    /// This is the 8KB of RAM at $6000-$7FFF, which games use to keep
+    /// saves between sessions. Frontends should write this to disk when
+    /// the game is closed, and pass it to `load_sram` on the next start.
+    /// Carts without a battery return `None`, since nothing they keep in
+    /// that RAM is meant to last.
+    pub fn sram(&self) -> Option<&[u8]> {
+        let cart = self.cpu.mem.mapper.cart();
+        if cart.has_battery {
+            Some(&cart.sram)
+        } else {
+            None
+        }
+    }
+
+    /// Restores battery backed RAM, as returned by `sram`.
+    ///
+    /// This should be called before running the game, since it might read
+    /// its saves right away. Only the first 8KB are used, and a shorter
+    /// save only overwrites the start of the RAM.
+    pub fn load_sram(&mut self, sram: &[u8]) {
+        let cart = self.cpu.mem.mapper.cart_mut();
+        let len = sram.len().min(cart.sram.len());
+        cart.sram[..len].copy_from_slice(&sram[..len]);
+    }
+
+    /// Returns the registers of the CPU, as left by the last instruction.
+    pub fn registers(&self) -> Registers {
+        self.cpu.registers()
+    }
+
+    /// Start keeping track of the last `capacity` instructions executed.
+    ///
+    /// This is useful to figure out how the console ended up in a given
+    /// state, without the cost of logging every single instruction.
+    /// Calling this again clears the instructions tracked so far, and
+    /// a capacity of 0 stops tracking entirely.
+    pub fn enable_trace_ring(&mut self, capacity: usize) {
+        self.cpu.enable_trace(capacity);
+    }
+
+    /// Returns the last instructions executed, from oldest to newest.
+    ///
+    /// This will be empty unless `enable_trace_ring` was called.
+    pub fn trace_dump(&self) -> Vec<TraceEntry> {
+        self.cpu.trace()
+    }
+
+    /// Start logging the last `capacity` writes to APU registers.
+    ///
+    /// Each write comes with the CPU cycle of the instruction doing it,
+    /// which along with `frame`, is enough to reconstruct the music of a
+    /// game, like a music ripper would. This doesn't change how the console
+    /// runs. Calling this again clears the writes logged so far, and a
+    /// capacity of 0 stops logging entirely.
+    pub fn enable_apu_log(&mut self, capacity: usize) {
+        self.cpu.mem.apu_log = if capacity == 0 {
+            None
+        } else {
+            Some(ApuLog::new(capacity))
+        };
+    }
+
+    /// Removes the APU writes logged so far, returning them from oldest to newest.
+    ///
+    /// This will be empty unless `enable_apu_log` was called. Calling this
+    /// every frame keeps the log from dropping writes.
+    pub fn take_apu_log(&mut self) -> Vec<ApuWrite> {
+        match &mut self.cpu.mem.apu_log {
+            None => Vec::new(),
+            Some(log) => log.take(),
+        }
+    }
+
+    /// Stop `step_until_breakpoint` before executing the instruction at an address.
+    ///
+    /// Adding the same breakpoint twice has no effect.
+    pub fn add_breakpoint(&mut self, address: u16) {
+        if !self.breakpoints.contains(&address) {
+            self.breakpoints.push(address);
+        }
+    }
+
+    /// Remove a breakpoint added with `add_breakpoint`.
+    pub fn remove_breakpoint(&mut self, address: u16) {
+        self.breakpoints.retain(|&breakpoint| breakpoint != address);
+    }
+
+    /// Stop `step_until_breakpoint` after an instruction reads or writes an address.
+    ///
+    /// Mirrors of RAM count as the same address, so watching $0010 also
+    /// catches accesses to $0810. Watching an address again replaces the
+    /// previous watch, and watching neither reads nor writes removes it.
+    pub fn add_watch(&mut self, address: u16, on_read: bool, on_write: bool) {
+        let watches = &mut self.cpu.mem.watches;
+        watches.retain(|watch| watch.address != address);
+        if on_read || on_write {
+            watches.push(Watch {
+                address,
+                on_read,
+                on_write,
+            });
+        }
+    }
+
+    /// Run instructions until one hits a watch, or the CPU is about
+    /// to execute one at a breakpoint, for at most `max_cycles` CPU cycles.
+    ///
+    /// At least one instruction is always executed, so calling this again
+    /// after stopping continues past the breakpoint or watch. Cycles where
+    /// the CPU is stalled by DMA, or held in reset, don't count as an
+    /// instruction. This returns `BreakReason::CyclesElapsed` if nothing was
+    /// hit within `max_cycles`, and `None` right away if there are no
+    /// breakpoints or watches.
+    ///
+    /// Breakpoints are checked between instructions, so the first instruction
+    /// of an interrupt handler can't be stopped at.
+    pub fn step_until_breakpoint<A, V>(
+        &mut self,
+        audio: &mut A,
+        video: &mut V,
+        max_cycles: u64,
+    ) -> Option<BreakReason>
+    where
+        A: AudioDevice,
+        V: VideoDevice,
+    {
+        if self.breakpoints.is_empty() && self.cpu.mem.watches.is_empty() {
+            return None;
+        }
+        let mut cycles = 0;
+        while cycles < max_cycles {
+            let start = self.cpu.pc();
+            let instruction = !self.reset_held && !self.cpu.mem.cpu.stalled();
+            self.cpu.mem.watch_hit = None;
+            cycles += u64::from(self.step_unchecked(audio, video).unsigned_abs());
+            if let Some(hit) = self.cpu.mem.watch_hit.take() {
+                return Some(BreakReason::Watch(WatchHit { pc: start, ..hit }));
+            }
+            let pc = self.cpu.pc();
+            if instruction && self.breakpoints.contains(&pc) {
+                return Some(BreakReason::Breakpoint(pc));
+            }
+        }
+        Some(BreakReason::CyclesElapsed)
+    }
+
+    /// Set whether or not the reset button is held down.
+    ///
+    /// Like on hardware, the console is reset when the button is pressed,
+    /// and stays in reset until it's released, at which point the game
+    /// starts running again. While held, the PPU keeps producing blank
+    /// frames, so `step_frame` still returns.
+    ///
+    /// This can be called every frame with the state of a key, since
+    /// only pressing the button again resets the console.
+    pub fn set_reset_button(&mut self, held: bool) {
+        if held && !self.reset_held {
+            self.reset();
+        }
+        self.reset_held = held;
+    }
+
+    /// Resets the console, like pressing the reset button.
+    ///
+    /// The game restarts from its reset vector, but memory is kept:
+    /// ram, SRAM, CHR RAM, as well as PPU memory, like nametables,
+    /// palettes and OAM. The CPU registers are kept too, other than the
+    /// stack pointer and interrupt flag. Games can use this to tell
+    /// a reset apart from turning the console on.
+    ///
+    /// The APU channels are silenced, and the PPU control and mask
+    /// registers are cleared, which turns off rendering and NMIs.
+    /// The mapper keeps its registers, like on most carts.
     pub fn reset(&mut self) {
-        self.cpu.reset();
+        if let Some(recording) = &mut self.recording {
+            recording.commands.reset = true;
+        }
         self.cpu.mem.reset();
+        self.cpu.reset();
+        self.ppu.reset(&mut self.cpu.mem);
+        if let Some(track) = self.track() {
+            self.play_track(track);
+        }
+    }
+
+    /// Turns the console off and on again.
+    ///
+    /// Ram and PPU memory go back to their contents on power up, following
+    /// the options given to `ConsoleBuilder`, and every processor loses its
+    /// state, with the screen being cleared. The mapper starts over too,
+    /// with its banks, IRQ counter and mirroring as they are on power up.
+    /// SRAM and CHR RAM are kept, since they belong to the cart.
+    pub fn power_cycle(&mut self) {
+        if let Some(recording) = &mut self.recording {
+            recording.commands.power = true;
+        }
+        self.cpu.mem.rebuild_mapper();
+        self.cpu.mem.power_cycle();
+        self.cpu.power_up();
         self.ppu.reset(&mut self.cpu.mem);
+        self.apu.set_region(self.region);
         self.ppu.clear_vbuffers();
+        if let Some(track) = self.track() {
+            self.play_track(track);
+        }
+    }
+
+    /// Swaps the cart being played for another ROM, and powers the console on again.
+    ///
+    /// This keeps the settings of the console, like its sample rate, speed,
+    /// and turbo, so a frontend can switch games without setting up its
+    /// audio and video again. Everything tied to the previous game is
+    /// dropped: cheats, frozen addresses, queued inputs, rewind snapshots,
+    /// any movie being played or recorded, and the reset button being held.
+    /// The region is taken from the header of the new ROM, like
+    /// `Console::new` does, and can be changed afterwards with `set_region`.
+    ///
+    /// If the ROM isn't valid, the previous cart keeps running untouched.
+    pub fn load_cart(&mut self, rom: &[u8]) -> Result<(), CartReadingError> {
+        let cart = Cart::from_bytes(rom)?;
+        let region = cart.region.unwrap_or_default();
+        self.hash = cart.hash;
+        self.cpu.mem.insert_cart(cart);
+        self.cpu.power_up();
+        self.ppu.reset(&mut self.cpu.mem);
+        self.ppu.clear_vbuffers();
+        self.set_region(region);
+        self.reset_held = false;
+        self.frame = 0;
+        self.frame_budget = 0;
+        self.inputs = InputQueue::new();
+        self.freezes.clear();
+        if let Some(rewind) = &mut self.rewind {
+            rewind.snapshots.clear();
+        }
+        self.movie = None;
+        self.recording = None;
+        self.nsf = None;
+        Ok(())
     }
 }
@@ -1,4 +1,6 @@
-#[derive(Clone, Copy, Default)]
+use crate::state::{StateError, StateReader, StateWriter};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub struct ButtonState {
     pub a: bool,
     pub b: bool,
@@ -10,14 +12,156 @@ pub struct ButtonState {
     pub right: bool,
 }
 
-/// Represents a controller
+impl ButtonState {
+    /// Turns the position of an analog stick into D-pad directions.
+    ///
+    /// Both axes go from -1.0 to 1.0, with up and right being positive.
+    /// Nothing is held while the stick is within `deadzone` of the center.
+    /// Otherwise, the stick is split into 8 directions, so that diagonals
+    /// hold two buttons. The result can be merged with other inputs.
+    pub fn from_stick(x: f32, y: f32, deadzone: f32) -> ButtonState {
+        let length = x * x + y * y;
+        if length <= deadzone * deadzone {
+            return ButtonState::default();
+        }
+        // An axis counts when the stick is within 67.5 degrees of it,
+        // and sin(22.5 degrees) squared is about 0.1464
+        let threshold = length * 0.1464;
+        ButtonState {
+            up: y > 0.0 && y * y > threshold,
+            down: y < 0.0 && y * y > threshold,
+            left: x < 0.0 && x * x > threshold,
+            right: x > 0.0 && x * x > threshold,
+            ..ButtonState::default()
+        }
+    }
+
+    /// Combines two button states, with a button being held if it's held in either
+    pub fn merge(self, other: ButtonState) -> ButtonState {
+        ButtonState {
+            a: self.a || other.a,
+            b: self.b || other.b,
+            select: self.select || other.select,
+            start: self.start || other.start,
+            up: self.up || other.up,
+            down: self.down || other.down,
+            left: self.left || other.left,
+            right: self.right || other.right,
+        }
+    }
+}
+
+/// Rapid fire settings, making A or B alternate between pressed and released.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Turbo {
+    /// Whether or not A fires rapidly while held
+    pub a: bool,
+    /// Whether or not B fires rapidly while held
+    pub b: bool,
+    /// The number of frames buttons stay pressed, then released.
+    ///
+    /// With 1, buttons alternate every frame, which is as fast as
+    /// most games can read them. A period of 0 is treated as 1.
+    pub period: u32,
+}
+
+impl Default for Turbo {
+    /// Returns settings with rapid fire disabled.
+    fn default() -> Self {
+        Turbo {
+            a: false,
+            b: false,
+            period: 1,
+        }
+    }
+}
+
+impl Turbo {
+    /// Returns the buttons seen by the console on a frame, from those held
+    pub fn apply(&self, buttons: ButtonState, frame: u64) -> ButtonState {
+        let period = u64::from(self.period.max(1));
+        if (frame / period).is_multiple_of(2) {
+            return buttons;
+        }
+        ButtonState {
+            a: buttons.a && !self.a,
+            b: buttons.b && !self.b,
+            ..buttons
+        }
+    }
+}
+
+/// Maps each button of a controller, along with reset and exit, to a key.
+///
+/// The type of key is left to the frontend, so this works with any
+/// windowing or input library. Frontends can then build a `ButtonState`
+/// from whichever keys are held, letting users remap the controls.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyBindings<K> {
+    pub a: K,
+    pub b: K,
+    pub select: K,
+    pub start: K,
+    pub up: K,
+    pub down: K,
+    pub left: K,
+    pub right: K,
+    /// The key resetting the console
+    pub reset: K,
+    /// The key closing the emulator
+    pub exit: K,
+}
+
+impl Default for KeyBindings<char> {
+    /// Uses WASD for the D-pad, K and J for A and B, G and H for select and
+    /// start, along with Enter to reset and Escape to exit.
+    fn default() -> Self {
+        KeyBindings {
+            a: 'k',
+            b: 'j',
+            select: 'g',
+            start: 'h',
+            up: 'w',
+            down: 's',
+            left: 'a',
+            right: 'd',
+            reset: '\r',
+            exit: '\u{1b}',
+        }
+    }
+}
+
+impl<K> KeyBindings<K> {
+    /// Returns the buttons held, using a function telling if a key is down
+    pub fn button_state(&self, mut is_down: impl FnMut(&K) -> bool) -> ButtonState {
+        ButtonState {
+            a: is_down(&self.a),
+            b: is_down(&self.b),
+            select: is_down(&self.select),
+            start: is_down(&self.start),
+            up: is_down(&self.up),
+            down: is_down(&self.down),
+            left: is_down(&self.left),
+            right: is_down(&self.right),
+        }
+    }
+}
+
+/// Represents a standard controller.
+///
+/// Writing 1 to $4016 holds the strobe, continuously loading the buttons
+/// into a shift register, so reads keep returning A. Once the strobe is
+/// released, each read returns the next button, in the order of `buttons`,
+/// shifting in a 1. After all 8 buttons, reads return 1 until the next strobe.
+///
+/// See: https://wiki.nesdev.com/w/index.php/Standard_controller
 #[derive(Default)]
 pub(crate) struct Controller {
     /// A bitfield of the buttons, in the following order:
     /// A, B, Select, Start, Up, Down, Left, Right
     buttons: [bool; 8],
-    /// What button is currently being read
-    index: u8,
+    /// The buttons latched by the last strobe, with the next one read in bit 0
+    shift: u8,
     strobe: bool,
 }
 
@@ -37,18 +181,33 @@ impl Controller {
             buttons.left,
             buttons.right,
         ];
+        if self.strobe {
+            self.latch();
+        }
     }
 
-    pub fn read(&mut self) -> u8 {
-        let index = self.index as usize;
-        let res = if *self.buttons.get(index).unwrap_or(&false) {
-            1
-        } else {
-            0
-        };
-        self.index += 1;
+    /// Loads the buttons held into the shift register
+    fn latch(&mut self) {
+        self.shift = self
+            .buttons
+            .iter()
+            .rev()
+            .fold(0, |bits, &held| (bits << 1) | u8::from(held));
+    }
+
+    /// Returns the bit the next read would return, without shifting
+    pub fn peek(&self) -> u8 {
         if self.strobe {
-            self.index = 0;
+            u8::from(self.buttons[0])
+        } else {
+            self.shift & 1
+        }
+    }
+
+    pub fn read(&mut self) -> u8 {
+        let res = self.peek();
+        if !self.strobe {
+            self.shift = (self.shift >> 1) | 0x80;
         }
         res
     }
@@ -56,7 +215,78 @@ impl Controller {
     pub fn write(&mut self, value: u8) {
         self.strobe = value & 1 == 1;
         if self.strobe {
-            self.index = 0;
+            self.latch();
+        }
+    }
+
+    pub fn save(&self, w: &mut StateWriter) {
+        for &button in &self.buttons {
+            w.bool(button);
+        }
+        w.u8(self.shift);
+        w.bool(self.strobe);
+    }
+
+    pub fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        for button in self.buttons.iter_mut() {
+            *button = r.bool()?;
+        }
+        self.shift = r.u8()?;
+        self.strobe = r.bool()?;
+        Ok(())
+    }
+}
+
+/// How many scanlines the Zapper keeps sensing light after seeing a bright pixel.
+///
+/// The photodiode stays on for a while after the beam passes, which is
+/// between 19 and 26 scanlines on hardware.
+/// See: https://wiki.nesdev.com/w/index.php/Zapper
+const LIGHT_LINES: u8 = 24;
+
+/// Represents a Zapper light gun, plugged in the second port
+#[derive(Clone, Copy, Default)]
+pub(crate) struct Zapper {
+    /// The pixel the Zapper is aimed at
+    pub x: u16,
+    pub y: u16,
+    pub trigger: bool,
+    /// The number of scanlines left during which light is sensed
+    light_lines: u8,
+}
+
+impl Zapper {
+    pub fn new(x: u16, y: u16, trigger: bool) -> Self {
+        Zapper {
+            x,
+            y,
+            trigger,
+            light_lines: 0,
+        }
+    }
+
+    /// Returns the bits of $4017, with bit 3 cleared when light is sensed,
+    /// and bit 4 set while the trigger is pulled.
+    pub fn read(&self) -> u8 {
+        let dark = u8::from(self.light_lines == 0) << 3;
+        let trigger = u8::from(self.trigger) << 4;
+        dark | trigger
+    }
+
+    /// Called by the PPU after drawing a pixel, to check if it's aimed at
+    pub fn sense(&mut self, x: usize, y: usize, argb: u32) {
+        if x != usize::from(self.x) || y != usize::from(self.y) {
+            return;
         }
+        let r = (argb >> 16) & 0xFF;
+        let g = (argb >> 8) & 0xFF;
+        let b = argb & 0xFF;
+        let luma = (r * 299 + g * 587 + b * 114) / 1000;
+        self.light_lines = if luma >= 0x80 { LIGHT_LINES } else { 0 };
+    }
+
+    /// Called by the PPU at the end of every scanline
+    pub fn end_line(&mut self) {
+        self.light_lines = self.light_lines.saturating_sub(1);
     }
 }
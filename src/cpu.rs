@@ -1,5 +1,12 @@
-use super::memory::MemoryBus;
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+use super::memory::{EmulationError, MemoryBus};
+use crate::cart::{Cart, CartReadingError};
 use crate::controller::ButtonState;
+use crate::state::{StateError, StateReader, StateWriter};
 
 // The various addressing modes of each opcode
 const OP_MODES: [u8; 256] = [
@@ -17,14 +24,14 @@ const OP_MODES: [u8; 256] = [
 // The size of each instruction in bytes
 // we sacrifice space to avoid casting
 const OP_SIZES: [u16; 256] = [
-    2, 2, 0, 0, 2, 2, 2, 0, 1, 2, 1, 0, 3, 3, 3, 0, 2, 2, 0, 0, 2, 2, 2, 0, 1, 3, 1, 0, 3, 3, 3, 0,
-    3, 2, 0, 0, 2, 2, 2, 0, 1, 2, 1, 0, 3, 3, 3, 0, 2, 2, 0, 0, 2, 2, 2, 0, 1, 3, 1, 0, 3, 3, 3, 0,
-    1, 2, 0, 0, 2, 2, 2, 0, 1, 2, 1, 0, 3, 3, 3, 0, 2, 2, 0, 0, 2, 2, 2, 0, 1, 3, 1, 0, 3, 3, 3, 0,
-    1, 2, 0, 0, 2, 2, 2, 0, 1, 2, 1, 0, 3, 3, 3, 0, 2, 2, 0, 0, 2, 2, 2, 0, 1, 3, 1, 0, 3, 3, 3, 0,
-    2, 2, 0, 0, 2, 2, 2, 0, 1, 0, 1, 0, 3, 3, 3, 0, 2, 2, 0, 0, 2, 2, 2, 0, 1, 3, 1, 0, 0, 3, 0, 0,
-    2, 2, 2, 0, 2, 2, 2, 0, 1, 2, 1, 0, 3, 3, 3, 0, 2, 2, 0, 0, 2, 2, 2, 0, 1, 3, 1, 0, 3, 3, 3, 0,
-    2, 2, 0, 0, 2, 2, 2, 0, 1, 2, 1, 0, 3, 3, 3, 0, 2, 2, 0, 0, 2, 2, 2, 0, 1, 3, 1, 0, 3, 3, 3, 0,
-    2, 2, 0, 0, 2, 2, 2, 0, 1, 2, 1, 0, 3, 3, 3, 0, 2, 2, 0, 0, 2, 2, 2, 0, 1, 3, 1, 0, 3, 3, 3, 0,
+    2, 2, 0, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3, 2, 2, 0, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3,
+    3, 2, 0, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3, 2, 2, 0, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3,
+    1, 2, 0, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3, 2, 2, 0, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3,
+    1, 2, 0, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3, 2, 2, 0, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3,
+    2, 2, 2, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3, 2, 2, 0, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3,
+    2, 2, 2, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3, 2, 2, 0, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3,
+    2, 2, 2, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3, 2, 2, 0, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3,
+    2, 2, 2, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3, 2, 2, 0, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3,
 ];
 
 // How many cycles each instruction takes
@@ -42,9 +49,33 @@ const OP_CYCLES: [i32; 256] = [
 // The op codes which add a cycle when crossing pages accessing memory
 // doesn't include branch instructions, since the page crossing check
 // happens when the branch is known to be successful or not
-const EXTRA_PAGECYCLE_OPS: [u8; 23] = [
+const EXTRA_PAGECYCLE_OPS: [u8; 32] = [
     0x7D, 0x79, 0x71, 0x3D, 0x39, 0x31, 0xDD, 0xD9, 0xD1, 0x5D, 0x59, 0x51, 0xBD, 0xB9, 0xB1, 0xBE,
-    0xBC, 0x1D, 0x19, 0x11, 0xFD, 0xF9, 0xF1,
+    0xBC, 0x1D, 0x19, 0x11, 0xFD, 0xF9, 0xF1, 0x1C, 0x3C, 0x5C, 0x7C, 0xDC, 0xFC, 0xB3, 0xBB, 0xBF,
+];
+
+// The mnemonic of each opcode, used when disassembling
+const OP_NAMES: [&str; 256] = [
+    "BRK", "ORA", "KIL", "SLO", "NOP", "ORA", "ASL", "SLO", "PHP", "ORA", "ASL", "ANC", "NOP",
+    "ORA", "ASL", "SLO", "BPL", "ORA", "KIL", "SLO", "NOP", "ORA", "ASL", "SLO", "CLC", "ORA",
+    "NOP", "SLO", "NOP", "ORA", "ASL", "SLO", "JSR", "AND", "KIL", "RLA", "BIT", "AND", "ROL",
+    "RLA", "PLP", "AND", "ROL", "ANC", "BIT", "AND", "ROL", "RLA", "BMI", "AND", "KIL", "RLA",
+    "NOP", "AND", "ROL", "RLA", "SEC", "AND", "NOP", "RLA", "NOP", "AND", "ROL", "RLA", "RTI",
+    "EOR", "KIL", "SRE", "NOP", "EOR", "LSR", "SRE", "PHA", "EOR", "LSR", "ALR", "JMP", "EOR",
+    "LSR", "SRE", "BVC", "EOR", "KIL", "SRE", "NOP", "EOR", "LSR", "SRE", "CLI", "EOR", "NOP",
+    "SRE", "NOP", "EOR", "LSR", "SRE", "RTS", "ADC", "KIL", "RRA", "NOP", "ADC", "ROR", "RRA",
+    "PLA", "ADC", "ROR", "ARR", "JMP", "ADC", "ROR", "RRA", "BVS", "ADC", "KIL", "RRA", "NOP",
+    "ADC", "ROR", "RRA", "SEI", "ADC", "NOP", "RRA", "NOP", "ADC", "ROR", "RRA", "NOP", "STA",
+    "NOP", "SAX", "STY", "STA", "STX", "SAX", "DEY", "NOP", "TXA", "XAA", "STY", "STA", "STX",
+    "SAX", "BCC", "STA", "KIL", "AHX", "STY", "STA", "STX", "SAX", "TYA", "STA", "TXS", "TAS",
+    "SHY", "STA", "SHX", "AHX", "LDY", "LDA", "LDX", "LAX", "LDY", "LDA", "LDX", "LAX", "TAY",
+    "LDA", "TAX", "LAX", "LDY", "LDA", "LDX", "LAX", "BCS", "LDA", "KIL", "LAX", "LDY", "LDA",
+    "LDX", "LAX", "CLV", "LDA", "TSX", "LAS", "LDY", "LDA", "LDX", "LAX", "CPY", "CMP", "NOP",
+    "DCP", "CPY", "CMP", "DEC", "DCP", "INY", "CMP", "DEX", "AXS", "CPY", "CMP", "DEC", "DCP",
+    "BNE", "CMP", "KIL", "DCP", "NOP", "CMP", "DEC", "DCP", "CLD", "CMP", "NOP", "DCP", "NOP",
+    "CMP", "DEC", "DCP", "CPX", "SBC", "NOP", "ISC", "CPX", "SBC", "INC", "ISC", "INX", "SBC",
+    "NOP", "SBC", "CPX", "SBC", "INC", "ISC", "BEQ", "SBC", "KIL", "ISC", "NOP", "SBC", "INC",
+    "ISC", "SED", "SBC", "NOP", "ISC", "NOP", "SBC", "INC", "ISC",
 ];
 
 /// Represents the type of addressing an op uses
@@ -89,6 +120,71 @@ impl Addressing {
     }
 }
 
+/// Disassembles PRG data, returning the address and text of each instruction.
+///
+/// The data is assumed to be mapped at $8000. Bytes at the end that
+/// don't form a complete instruction are listed as raw data.
+pub fn disassemble(prg: &[u8]) -> Vec<(u16, String)> {
+    let mut instructions = Vec::new();
+    let mut offset = 0;
+    while offset < prg.len() {
+        let address = 0x8000u16.wrapping_add(offset as u16);
+        let opcode = prg[offset];
+        // KIL doesn't advance on its own, but still takes a byte
+        let size = OP_SIZES[opcode as usize].max(1) as usize;
+        let operands = match prg.get(offset + 1..offset + size) {
+            Some(operands) => operands,
+            None => {
+                instructions.push((address, format!(".byte ${:02X}", opcode)));
+                offset += 1;
+                continue;
+            }
+        };
+        let byte = operands.first().copied().unwrap_or(0);
+        let word = u16::from(byte) | (u16::from(operands.get(1).copied().unwrap_or(0)) << 8);
+        let name = OP_NAMES[opcode as usize];
+        let text = match Addressing::from_byte(OP_MODES[opcode as usize]) {
+            Addressing::Absolute => format!("{} ${:04X}", name, word),
+            Addressing::AbsoluteX => format!("{} ${:04X},X", name, word),
+            Addressing::AbsoluteY => format!("{} ${:04X},Y", name, word),
+            Addressing::Accumulator => format!("{} A", name),
+            Addressing::Immediate => format!("{} #${:02X}", name, byte),
+            Addressing::Implied => String::from(name),
+            Addressing::IndexedIndirect => format!("{} (${:02X},X)", name, byte),
+            Addressing::Indirect => format!("{} (${:04X})", name, word),
+            Addressing::IndirectIndexed => format!("{} (${:02X}),Y", name, byte),
+            Addressing::Relative => {
+                let next = address.wrapping_add(2);
+                let target = next.wrapping_add(byte as i8 as u16);
+                format!("{} ${:04X}", name, target)
+            }
+            Addressing::ZeroPage => format!("{} ${:02X}", name, byte),
+            Addressing::ZeroPageX => format!("{} ${:02X},X", name, byte),
+            Addressing::ZeroPageY => format!("{} ${:02X},Y", name, byte),
+        };
+        instructions.push((address, text));
+        offset += size;
+    }
+    instructions
+}
+
+/// Disassembles PRG data into a listing, with an instruction per line.
+pub fn disassembly_listing(prg: &[u8]) -> String {
+    let mut listing = String::new();
+    for (address, text) in disassemble(prg) {
+        listing.push_str(&format!("${:04X}  {}\n", address, text));
+    }
+    listing
+}
+
+/// Disassembles the PRG data of a ROM into a listing, like `disassembly_listing`.
+///
+/// This takes the bytes of a whole ROM, header included.
+pub fn disassemble_rom(rom: &[u8]) -> Result<String, CartReadingError> {
+    let cart = Cart::from_bytes(rom)?;
+    Ok(disassembly_listing(&cart.prg))
+}
+
 /// Represents the different types of Interrupts the CPU might deal with
 #[derive(Clone)]
 enum Interrupt {
@@ -98,7 +194,7 @@ enum Interrupt {
 
 /// Returns true if two addresses return different pages
 fn pages_differ(a: u16, b: u16) -> bool {
-    a & 0xFF00 != b & 0xFF
+    a & 0xFF00 != b & 0xFF00
 }
 
 /// Returns the number of extra cycles used by a branch instruction
@@ -117,6 +213,10 @@ pub struct CPUState {
     interrupt: Option<Interrupt>,
     /// Used to add stalls to cpu cycles
     stall: i32,
+    /// Set when an OAM DMA was started by the current instruction
+    dma: bool,
+    /// The number of cycles run since power on, to align DMA with
+    cycles: u64,
 }
 
 impl CPUState {
@@ -128,17 +228,181 @@ impl CPUState {
         self.interrupt = Some(Interrupt::NMI);
     }
 
+    /// Requests an IRQ, unless an NMI is already pending
     pub fn set_irq(&mut self) {
-        self.interrupt = Some(Interrupt::IRQ);
+        if self.interrupt.is_none() {
+            self.interrupt = Some(Interrupt::IRQ);
+        }
+    }
+
+    /// Returns true if an NMI is waiting to be handled
+    #[cfg(test)]
+    pub fn nmi_pending(&self) -> bool {
+        matches!(self.interrupt, Some(Interrupt::NMI))
     }
 
     pub fn clear_interrupt(&mut self) {
         self.interrupt = None;
     }
 
+    /// Returns true if the next step only stalls, without running an instruction
+    pub fn stalled(&self) -> bool {
+        self.stall > 0
+    }
+
     pub fn add_stall(&mut self, amount: i32) {
         self.stall += amount;
     }
+
+    /// Returns the number of cycles run since power on, up to the current instruction
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Starts an OAM DMA, halting the CPU once the current instruction is done
+    pub fn start_dma(&mut self) {
+        self.dma = true;
+    }
+
+    /// Counts the cycles of an instruction, along with the DMA it started.
+    ///
+    /// The DMA waits a cycle for the write to finish, then another one if
+    /// that lands on an odd cycle, before alternating between reading and
+    /// writing for 512 cycles, for a total of 513 or 514 cycles.
+    fn finish_instruction(&mut self, cycles: i32) -> i32 {
+        let mut total = cycles;
+        if self.dma {
+            self.dma = false;
+            let odd = (self.cycles + cycles as u64) % 2 == 1;
+            total += 513 + i32::from(odd);
+        }
+        self.cycles += total as u64;
+        total
+    }
+
+    pub fn save(&self, w: &mut StateWriter) {
+        let interrupt = match self.interrupt {
+            None => 0,
+            Some(Interrupt::NMI) => 1,
+            Some(Interrupt::IRQ) => 2,
+        };
+        w.u8(interrupt);
+        w.i32(self.stall);
+        w.u64(self.cycles);
+    }
+
+    pub fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.interrupt = match r.u8()? {
+            1 => Some(Interrupt::NMI),
+            2 => Some(Interrupt::IRQ),
+            _ => None,
+        };
+        self.stall = r.i32()?;
+        self.cycles = r.u64()?;
+        Ok(())
+    }
+}
+
+/// Represents the state of the CPU right before it executed an instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TraceEntry {
+    /// The address of the instruction
+    pub pc: u16,
+    /// The opcode of the instruction
+    pub opcode: u8,
+    /// The accumulator
+    pub a: u8,
+    /// The X register
+    pub x: u8,
+    /// The Y register
+    pub y: u8,
+    /// The status flags, packed into a byte
+    pub p: u8,
+    /// The stack pointer
+    pub sp: u8,
+}
+
+/// Represents the registers of the CPU, between two instructions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Registers {
+    /// The address of the next instruction
+    pub pc: u16,
+    /// The accumulator
+    pub a: u8,
+    /// The X register
+    pub x: u8,
+    /// The Y register
+    pub y: u8,
+    /// The status flags, packed into a byte
+    pub p: u8,
+    /// The stack pointer
+    pub sp: u8,
+}
+
+impl Registers {
+    /// Returns true if the carry flag is set
+    pub fn carry(&self) -> bool {
+        self.p & 0x01 != 0
+    }
+
+    /// Returns true if the zero flag is set
+    pub fn zero(&self) -> bool {
+        self.p & 0x02 != 0
+    }
+
+    /// Returns true if IRQs are disabled
+    pub fn interrupt_disable(&self) -> bool {
+        self.p & 0x04 != 0
+    }
+
+    /// Returns true if the decimal flag is set, which the NES ignores
+    pub fn decimal(&self) -> bool {
+        self.p & 0x08 != 0
+    }
+
+    /// Returns true if the overflow flag is set
+    pub fn overflow(&self) -> bool {
+        self.p & 0x40 != 0
+    }
+
+    /// Returns true if the negative flag is set
+    pub fn negative(&self) -> bool {
+        self.p & 0x80 != 0
+    }
+}
+
+/// Formats the registers like the logs of nestest, as in
+/// `A:05 X:04 Y:80 P:A4 SP:FD`, followed by the PC.
+impl fmt::Display for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PC:{:04X}",
+            self.a, self.x, self.y, self.p, self.sp, self.pc
+        )
+    }
+}
+
+/// Keeps the last few instructions executed, dropping older ones.
+struct TraceRing {
+    entries: VecDeque<TraceEntry>,
+    capacity: usize,
+}
+
+impl TraceRing {
+    fn new(capacity: usize) -> Self {
+        TraceRing {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    fn push(&mut self, entry: TraceEntry) {
+        if self.entries.len() >= self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
 }
 
 /// Represents possible CPU interrupts
@@ -174,6 +438,8 @@ pub(crate) struct CPU {
     n: u8,
     /// Shared acess to the memory bus along with the ppu,
     pub mem: MemoryBus,
+    /// Keeps track of recent instructions, if enabled
+    trace: Option<TraceRing>,
 }
 
 impl CPU {
@@ -194,18 +460,106 @@ impl CPU {
             v: 0,
             n: 0,
             mem,
+            trace: None,
         };
-        cpu.reset();
+        cpu.power_up();
         cpu
     }
 
-    /// Resets the CPU to its initial powerup state.
-    pub fn reset(&mut self) {
+    /// Puts the CPU in its initial powerup state, clearing every register.
+    pub fn power_up(&mut self) {
+        self.a = 0;
+        self.x = 0;
+        self.y = 0;
         self.pc = self.read16(0xFFFC);
         self.sp = 0xFD;
         self.set_flags(0x24);
     }
 
+    /// Jumps through the reset vector, like the reset line does.
+    ///
+    /// The registers are kept, except for the stack pointer, which moves
+    /// down by 3 like an interrupt that doesn't write, and interrupts
+    /// being disabled.
+    pub fn reset(&mut self) {
+        self.pc = self.read16(0xFFFC);
+        self.sp = self.sp.wrapping_sub(3);
+        self.i = 1;
+    }
+
+    /// Calls a subroutine from outside of the program, like a `JSR` would.
+    ///
+    /// The return address is pushed so that the `RTS` ending the
+    /// subroutine lands on `return_to`.
+    pub fn call(&mut self, address: u16, return_to: u16) {
+        self.push16(return_to.wrapping_sub(1));
+        self.pc = address;
+    }
+
+    /// Sets the accumulator and X, which some routines take arguments in
+    pub fn set_arguments(&mut self, a: u8, x: u8) {
+        self.a = a;
+        self.x = x;
+    }
+
+    /// Saves the registers of the CPU, along with the whole memory bus
+    pub fn save(&self, w: &mut StateWriter) {
+        w.u16(self.pc);
+        w.u8(self.sp);
+        w.u8(self.a);
+        w.u8(self.x);
+        w.u8(self.y);
+        w.u8(self.get_flags());
+        self.mem.save(w);
+    }
+
+    pub fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.pc = r.u16()?;
+        self.sp = r.u8()?;
+        self.a = r.u8()?;
+        self.x = r.u8()?;
+        self.y = r.u8()?;
+        let flags = r.u8()?;
+        self.set_flags(flags);
+        self.mem.load(r)
+    }
+
+    /// Returns the address of the next instruction
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    /// Returns the current value of every register
+    pub fn registers(&self) -> Registers {
+        Registers {
+            pc: self.pc,
+            a: self.a,
+            x: self.x,
+            y: self.y,
+            p: self.get_flags(),
+            sp: self.sp,
+        }
+    }
+
+    /// Start keeping track of the last `capacity` instructions executed.
+    ///
+    /// A capacity of 0 stops keeping track of instructions.
+    pub fn enable_trace(&mut self, capacity: usize) {
+        self.trace = if capacity == 0 {
+            None
+        } else {
+            Some(TraceRing::new(capacity))
+        };
+    }
+
+    /// Returns the instructions tracked so far, from oldest to newest
+    pub fn trace(&self) -> Vec<TraceEntry> {
+        match &self.trace {
+            None => Vec::new(),
+            Some(ring) => ring.entries.iter().copied().collect(),
+        }
+    }
+
     /// Sets the buttons for controller 1
     pub fn set_buttons(&mut self, buttons: ButtonState) {
         self.mem.controller1.set_buttons(buttons);
@@ -241,13 +595,13 @@ impl CPU {
 
     fn read16(&mut self, address: u16) -> u16 {
         let lo = self.read(address);
-        let hi = self.read(address + 1);
+        let hi = self.read(address.wrapping_add(1));
         u16::from_be_bytes([hi, lo])
     }
 
     /// Emulates a software bug where only the lower bit wraps around
     fn read16bug(&mut self, a: u16) -> u16 {
-        let b = (a & 0xFF00) | ((a + 1) & 0xFF);
+        let b = (a & 0xFF00) | (a.wrapping_add(1) & 0xFF);
         let lo = self.read(a);
         let hi = self.read(b);
         u16::from_be_bytes([hi, lo])
@@ -257,6 +611,16 @@ impl CPU {
         self.mem.cpu_write(address, value);
     }
 
+    /// Writes the result of a read-modify-write instruction.
+    ///
+    /// Like on hardware, the value read is written back unchanged on the
+    /// cycle before the result. Some registers, like the serial port
+    /// of MMC1, react to this extra write.
+    fn write_modified(&mut self, address: u16, original: u8, value: u8) {
+        self.write(address, original);
+        self.write(address, value);
+    }
+
     fn push(&mut self, value: u8) {
         let sp = u16::from(self.sp);
         self.write(0x100 | sp, value);
@@ -315,6 +679,61 @@ impl CPU {
         };
     }
 
+    /// Adds a value to the accumulator, along with the carry
+    fn adc(&mut self, b: u8) {
+        let a = self.a;
+        let c = self.c;
+        let a2 = a.wrapping_add(b).wrapping_add(c);
+        self.a = a2;
+        self.set_zn(a2);
+        if u32::from(a) + u32::from(b) + u32::from(c) > 0xFF {
+            self.c = 1;
+        } else {
+            self.c = 0;
+        }
+        if (a ^ b) & 0x80 == 0 && (a ^ a2) & 0x80 != 0 {
+            self.v = 1;
+        } else {
+            self.v = 0;
+        }
+    }
+
+    /// Subtracts a value from the accumulator, borrowing from the carry
+    fn sbc(&mut self, b: u8) {
+        let a = self.a;
+        let c = self.c;
+        let a2 = a.wrapping_sub(b).wrapping_sub(1 - c);
+        self.a = a2;
+        self.set_zn(a2);
+        if i32::from(a) - i32::from(b) - (1 - i32::from(c)) >= 0 {
+            self.c = 1;
+        } else {
+            self.c = 0;
+        }
+        if (a ^ b) & 0x80 != 0 && (a ^ a2) & 0x80 != 0 {
+            self.v = 1;
+        } else {
+            self.v = 0;
+        }
+    }
+
+    /// Stores a register masked with the high byte of the address plus one,
+    /// like SHX, SHY, AHX and TAS do.
+    ///
+    /// When indexing crosses a page, the value replaces the high byte
+    /// of the address instead of the carry.
+    fn store_high(&mut self, address: u16, index: u8, value: u8) {
+        let base = address.wrapping_sub(u16::from(index));
+        let high = ((base >> 8) as u8).wrapping_add(1);
+        let value = value & high;
+        let address = if pages_differ(base, address) {
+            (u16::from(value) << 8) | (address & 0xFF)
+        } else {
+            address
+        };
+        self.write(address, value);
+    }
+
     fn nmi(&mut self) {
         let pc = self.pc;
         self.push16(pc);
@@ -332,11 +751,12 @@ impl CPU {
     }
 
     /// Steps the cpu forward by a single instruction
-    /// Returns the number of cycles passed
+    /// Returns the number of cycles passed, including an OAM DMA it started
     pub fn step(&mut self) -> i32 {
         // Stall for a single cycle if stall cycles are still done
         if self.mem.cpu.stall > 0 {
             self.mem.cpu.stall -= 1;
+            self.mem.cpu.cycles += 1;
             return 1;
         }
         let mut cycles = 0;
@@ -352,6 +772,8 @@ impl CPU {
                 self.nmi();
                 cycles += 7;
             }
+            // IRQs are ignored while the interrupt disable flag is set
+            Some(Interrupt::IRQ) if self.i != 0 => {}
             Some(Interrupt::IRQ) => {
                 self.irq();
                 cycles += 7;
@@ -362,6 +784,20 @@ impl CPU {
             let pc = self.pc;
             self.read(pc)
         };
+        if self.trace.is_some() {
+            let entry = TraceEntry {
+                pc: self.pc,
+                opcode,
+                a: self.a,
+                x: self.x,
+                y: self.y,
+                p: self.get_flags(),
+                sp: self.sp,
+            };
+            if let Some(ring) = &mut self.trace {
+                ring.push(entry);
+            }
+        }
         // We now fetch the adress based on what type of addressing the
         // opcode requires, and set the page crossed, in order to
         // increment the cycles if necessary.
@@ -435,7 +871,7 @@ impl CPU {
             }
         };
 
-        self.pc += OP_SIZES[opcode as usize];
+        self.pc = self.pc.wrapping_add(OP_SIZES[opcode as usize]);
         cycles += OP_CYCLES[opcode as usize];
         if page_crossed && EXTRA_PAGECYCLE_OPS.contains(&opcode) {
             cycles += 1;
@@ -444,22 +880,8 @@ impl CPU {
         match opcode {
             // ADC
             0x69 | 0x65 | 0x75 | 0x6D | 0x7D | 0x79 | 0x61 | 0x71 => {
-                let a = self.a;
                 let b = self.read(address);
-                let c = self.c;
-                let a2 = a.wrapping_add(b).wrapping_add(c);
-                self.a = a2;
-                self.set_zn(a2);
-                if u32::from(a) + u32::from(b) + u32::from(c) > 0xFF {
-                    self.c = 1;
-                } else {
-                    self.c = 0;
-                }
-                if (a ^ b) & 0x80 == 0 && (a ^ a2) & 0x80 != 0 {
-                    self.v = 1;
-                } else {
-                    self.v = 0;
-                }
+                self.adc(b);
             }
             // AND
             0x29 | 0x25 | 0x35 | 0x2D | 0x3D | 0x39 | 0x21 | 0x31 => {
@@ -476,10 +898,10 @@ impl CPU {
                     self.set_zn(a);
                 }
                 _ => {
-                    let mut value = self.read(address);
-                    self.c = (value >> 7) & 1;
-                    value <<= 1;
-                    self.write(address, value);
+                    let original = self.read(address);
+                    self.c = (original >> 7) & 1;
+                    let value = original << 1;
+                    self.write_modified(address, original, value);
                     self.set_zn(value);
                 }
             },
@@ -591,8 +1013,9 @@ impl CPU {
             }
             // DEC
             0xC6 | 0xD6 | 0xCE | 0xDE => {
-                let value = self.read(address).wrapping_sub(1);
-                self.write(address, value);
+                let original = self.read(address);
+                let value = original.wrapping_sub(1);
+                self.write_modified(address, original, value);
                 self.set_zn(value);
             }
             // DEX
@@ -615,8 +1038,9 @@ impl CPU {
             }
             // INC
             0xE6 | 0xF6 | 0xEE | 0xFE => {
-                let value = self.read(address).wrapping_add(1);
-                self.write(address, value);
+                let original = self.read(address);
+                let value = original.wrapping_add(1);
+                self.write_modified(address, original, value);
                 self.set_zn(value);
             }
             // INX
@@ -666,10 +1090,10 @@ impl CPU {
                     self.set_zn(a);
                 }
                 _ => {
-                    let mut value = self.read(address);
-                    self.c = value & 1;
-                    value >>= 1;
-                    self.write(address, value);
+                    let original = self.read(address);
+                    self.c = original & 1;
+                    let value = original >> 1;
+                    self.write_modified(address, original, value);
                     self.set_zn(value);
                 }
             },
@@ -710,10 +1134,10 @@ impl CPU {
                 }
                 _ => {
                     let c = self.c;
-                    let mut value = self.read(address);
-                    self.c = (value >> 7) & 1;
-                    value = (value << 1) | c;
-                    self.write(address, value);
+                    let original = self.read(address);
+                    self.c = (original >> 7) & 1;
+                    let value = (original << 1) | c;
+                    self.write_modified(address, original, value);
                     self.set_zn(value);
                 }
             },
@@ -728,10 +1152,10 @@ impl CPU {
                 }
                 _ => {
                     let c = self.c;
-                    let mut value = self.read(address);
-                    self.c = value & 1;
-                    value = (value >> 1) | (c << 7);
-                    self.write(address, value);
+                    let original = self.read(address);
+                    self.c = original & 1;
+                    let value = (original >> 1) | (c << 7);
+                    self.write_modified(address, original, value);
                     self.set_zn(value);
                 }
             },
@@ -743,24 +1167,10 @@ impl CPU {
             }
             // RTS
             0x60 => self.pc = self.pull16().wrapping_add(1),
-            // SBC
-            0xE9 | 0xE5 | 0xF5 | 0xED | 0xFD | 0xF9 | 0xE1 | 0xF1 => {
-                let a = self.a;
+            // SBC, along with the unofficial copy at 0xEB
+            0xE9 | 0xE5 | 0xF5 | 0xED | 0xFD | 0xF9 | 0xE1 | 0xF1 | 0xEB => {
                 let b = self.read(address);
-                let c = self.c;
-                let a2 = a.wrapping_sub(b).wrapping_sub(1 - c);
-                self.a = a2;
-                self.set_zn(a2);
-                if i32::from(a) - i32::from(b) - (1 - i32::from(c)) >= 0 {
-                    self.c = 1;
-                } else {
-                    self.c = 0;
-                }
-                if (a ^ b) & 0x80 != 0 && (a ^ a2) & 0x80 != 0 {
-                    self.v = 1;
-                } else {
-                    self.v = 0;
-                }
+                self.sbc(b);
             }
             // SEC
             0x38 => self.c = 1,
@@ -815,8 +1225,152 @@ impl CPU {
                 self.a = y;
                 self.set_zn(y);
             }
-            _ => panic!("Unimplented Op {:02X}", opcode),
+            // Unofficial opcodes
+
+            // NOP, with the multi byte versions reading their operand
+            0x1A | 0x3A | 0x5A | 0x7A | 0xDA | 0xFA => {}
+            0x80 | 0x82 | 0x89 | 0xC2 | 0xE2 | 0x04 | 0x44 | 0x64 | 0x14 | 0x34 | 0x54 | 0x74
+            | 0xD4 | 0xF4 | 0x0C | 0x1C | 0x3C | 0x5C | 0x7C | 0xDC | 0xFC => {
+                self.read(address);
+            }
+            // SLO
+            0x03 | 0x07 | 0x0F | 0x13 | 0x17 | 0x1B | 0x1F => {
+                let original = self.read(address);
+                self.c = (original >> 7) & 1;
+                let value = original << 1;
+                self.write_modified(address, original, value);
+                let a = self.a | value;
+                self.a = a;
+                self.set_zn(a);
+            }
+            // RLA
+            0x23 | 0x27 | 0x2F | 0x33 | 0x37 | 0x3B | 0x3F => {
+                let original = self.read(address);
+                let c = self.c;
+                self.c = (original >> 7) & 1;
+                let value = (original << 1) | c;
+                self.write_modified(address, original, value);
+                let a = self.a & value;
+                self.a = a;
+                self.set_zn(a);
+            }
+            // SRE
+            0x43 | 0x47 | 0x4F | 0x53 | 0x57 | 0x5B | 0x5F => {
+                let original = self.read(address);
+                self.c = original & 1;
+                let value = original >> 1;
+                self.write_modified(address, original, value);
+                let a = self.a ^ value;
+                self.a = a;
+                self.set_zn(a);
+            }
+            // RRA
+            0x63 | 0x67 | 0x6F | 0x73 | 0x77 | 0x7B | 0x7F => {
+                let original = self.read(address);
+                let c = self.c;
+                self.c = original & 1;
+                let value = (original >> 1) | (c << 7);
+                self.write_modified(address, original, value);
+                self.adc(value);
+            }
+            // SAX
+            0x83 | 0x87 | 0x8F | 0x97 => {
+                let value = self.a & self.x;
+                self.write(address, value);
+            }
+            // LAX
+            0xA3 | 0xA7 | 0xAB | 0xAF | 0xB3 | 0xB7 | 0xBF => {
+                let value = self.read(address);
+                self.a = value;
+                self.x = value;
+                self.set_zn(value);
+            }
+            // DCP
+            0xC3 | 0xC7 | 0xCF | 0xD3 | 0xD7 | 0xDB | 0xDF => {
+                let original = self.read(address);
+                let value = original.wrapping_sub(1);
+                self.write_modified(address, original, value);
+                let a = self.a;
+                self.compare(a, value);
+            }
+            // ISC
+            0xE3 | 0xE7 | 0xEF | 0xF3 | 0xF7 | 0xFB | 0xFF => {
+                let original = self.read(address);
+                let value = original.wrapping_add(1);
+                self.write_modified(address, original, value);
+                self.sbc(value);
+            }
+            // ANC
+            0x0B | 0x2B => {
+                let a = self.a & self.read(address);
+                self.a = a;
+                self.set_zn(a);
+                self.c = (a >> 7) & 1;
+            }
+            // ALR
+            0x4B => {
+                let value = self.a & self.read(address);
+                self.c = value & 1;
+                let a = value >> 1;
+                self.a = a;
+                self.set_zn(a);
+            }
+            // ARR
+            0x6B => {
+                let value = self.a & self.read(address);
+                let a = (value >> 1) | (self.c << 7);
+                self.a = a;
+                self.set_zn(a);
+                self.c = (a >> 6) & 1;
+                self.v = ((a >> 6) ^ (a >> 5)) & 1;
+            }
+            // XAA, using 0xFF for the unstable constant
+            0x8B => {
+                let a = self.x & self.read(address);
+                self.a = a;
+                self.set_zn(a);
+            }
+            // AXS
+            0xCB => {
+                let b = self.read(address);
+                let ax = self.a & self.x;
+                self.c = u8::from(ax >= b);
+                let x = ax.wrapping_sub(b);
+                self.x = x;
+                self.set_zn(x);
+            }
+            // LAS
+            0xBB => {
+                let value = self.read(address) & self.sp;
+                self.a = value;
+                self.x = value;
+                self.sp = value;
+                self.set_zn(value);
+            }
+            // AHX
+            0x93 | 0x9F => {
+                let (y, value) = (self.y, self.a & self.x);
+                self.store_high(address, y, value);
+            }
+            // TAS
+            0x9B => {
+                self.sp = self.a & self.x;
+                let (y, value) = (self.y, self.sp);
+                self.store_high(address, y, value);
+            }
+            // SHY
+            0x9C => {
+                let (x, value) = (self.x, self.y);
+                self.store_high(address, x, value);
+            }
+            // SHX
+            0x9E => {
+                let (y, value) = (self.y, self.x);
+                self.store_high(address, y, value);
+            }
+            // Anything left halts the CPU on hardware
+            _ => self.mem.set_error(EmulationError::UnknownOpcode(opcode)),
         }
-        cycles
+        self.mem.cpu.finish_instruction(cycles)
     }
 }
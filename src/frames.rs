@@ -0,0 +1,91 @@
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+
+use crate::ports::{PixelBuffer, VideoDevice};
+
+/// Set in the index of the middle buffer when it holds a frame not taken yet
+const FRESH: u8 = 0b100;
+
+/// The state shared between both ends of the exchange.
+struct Shared {
+    /// The pixels of the three buffers, one after the other
+    pixels: Box<[AtomicU32]>,
+    /// The buffer neither end is using, along with the fresh flag
+    middle: AtomicU8,
+}
+
+impl Shared {
+    fn buffer(&self, index: u8) -> &[AtomicU32] {
+        let len = self.pixels.len() / 3;
+        let start = usize::from(index) * len;
+        &self.pixels[start..start + len]
+    }
+}
+
+/// Create an exchange of frames, between the thread running the console
+/// and the thread displaying them.
+///
+/// This uses triple buffering: the producer is given to the console as its
+/// video device, and the consumer takes the latest complete frame whenever
+/// it redraws. Neither end ever blocks or waits for the other, so the UI
+/// can run at its own frame rate. Frames the UI doesn't take in time are
+/// replaced by newer ones, and no frame is ever torn.
+pub fn frame_exchange() -> (FrameProducer, FrameConsumer) {
+    let len = PixelBuffer::default().as_ref().len();
+    let pixels: Vec<AtomicU32> = (0..3 * len).map(|_| AtomicU32::new(0)).collect();
+    let shared = Arc::new(Shared {
+        pixels: pixels.into_boxed_slice(),
+        middle: AtomicU8::new(1),
+    });
+    let producer = FrameProducer {
+        shared: shared.clone(),
+        back: 0,
+    };
+    (producer, FrameConsumer { shared, front: 2 })
+}
+
+/// The end of a frame exchange the console blits frames into.
+pub struct FrameProducer {
+    shared: Arc<Shared>,
+    /// The buffer being written to
+    back: u8,
+}
+
+impl VideoDevice for FrameProducer {
+    fn blit_pixels(&mut self, pixels: &PixelBuffer) {
+        let buffer = self.shared.buffer(self.back);
+        for (slot, &argb) in buffer.iter().zip(pixels.as_ref()) {
+            slot.store(argb, Ordering::Relaxed);
+        }
+        let middle = self.shared.middle.swap(self.back | FRESH, Ordering::AcqRel);
+        self.back = middle & !FRESH;
+    }
+}
+
+/// The end of a frame exchange the latest frame is taken from.
+pub struct FrameConsumer {
+    shared: Arc<Shared>,
+    /// The buffer holding the frame taken last
+    front: u8,
+}
+
+impl FrameConsumer {
+    /// Copies the latest frame into `out`, if one was blitted since the last call.
+    ///
+    /// This returns false, leaving `out` alone, when there's no new frame.
+    pub fn take(&mut self, out: &mut PixelBuffer) -> bool {
+        let shared = &*self.shared;
+        if shared.middle.load(Ordering::Relaxed) & FRESH == 0 {
+            return false;
+        }
+        let middle = shared.middle.swap(self.front, Ordering::AcqRel);
+        self.front = middle & !FRESH;
+        let buffer = shared.buffer(self.front);
+        for (argb, slot) in out.pixels_mut().iter_mut().zip(buffer) {
+            *argb = slot.load(Ordering::Relaxed);
+        }
+        true
+    }
+}
@@ -0,0 +1,153 @@
+use alloc::boxed::Box;
+use alloc::string::String;
+
+use crate::ports::{PixelBuffer, VideoDevice, NES_WIDTH};
+
+const TEXT_COLOR: u32 = 0xFFFF_FFFF;
+const BACKGROUND_COLOR: u32 = 0xFF00_0000;
+/// The position of the top left corner of the text
+const MARGIN: usize = 2;
+/// The width of a glyph, including the space after it
+const GLYPH_WIDTH: usize = 4;
+/// The height of a line, including the space after it
+const LINE_HEIGHT: usize = 6;
+/// The number of frames shown between each measure of the FPS
+const FPS_FRAMES: u64 = 60;
+
+/// Returns the rows of a 3x5 glyph, from top to bottom.
+///
+/// Each row uses its 3 lowest bits, with the highest being the leftmost pixel.
+/// Only the characters needed by the HUD are available, and any other
+/// character is drawn as a space.
+fn glyph(c: char) -> [u8; 5] {
+    match c {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        _ => [0; 5],
+    }
+}
+
+/// Draws a line of text, over a dark background to keep it readable
+pub(crate) fn draw_text(buffer: &mut PixelBuffer, line: usize, text: &str) {
+    let top = MARGIN + line * LINE_HEIGHT;
+    let width = (text.len() * GLYPH_WIDTH + 1).min(NES_WIDTH - MARGIN);
+    for y in top..top + LINE_HEIGHT + 1 {
+        for x in MARGIN..MARGIN + width {
+            buffer.write(x, y, BACKGROUND_COLOR);
+        }
+    }
+    for (i, c) in text.chars().enumerate() {
+        let left = MARGIN + 1 + i * GLYPH_WIDTH;
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for col in 0..3 {
+                if bits & (0b100 >> col) != 0 && left + col < NES_WIDTH {
+                    buffer.write(left + col, top + 1 + row, TEXT_COLOR);
+                }
+            }
+        }
+    }
+}
+
+/// A line of text on the HUD, formatted again only when its value changes.
+#[derive(Default)]
+struct HudLine {
+    value: Option<u64>,
+    text: String,
+}
+
+impl HudLine {
+    fn update(&mut self, value: u64, format: fn(u64) -> String) -> &str {
+        if self.value != Some(value) {
+            self.value = Some(value);
+            self.text = format(value);
+        }
+        &self.text
+    }
+}
+
+/// Keeps track of the statistics shown on the HUD.
+pub(crate) struct Hud {
+    /// The frame with the HUD drawn over it
+    buffer: Box<PixelBuffer>,
+    /// The CPU cycles run since the FPS was last measured
+    cycles: u64,
+    /// The number of frames completed since the FPS was last measured
+    frames: u64,
+    /// The last FPS measured
+    fps: u64,
+    lines: [HudLine; 3],
+}
+
+impl Hud {
+    pub fn new() -> Self {
+        Hud {
+            buffer: Box::default(),
+            cycles: 0,
+            frames: 0,
+            fps: 0,
+            lines: Default::default(),
+        }
+    }
+
+    /// Adds CPU cycles run by the console, updating the FPS every
+    /// `FPS_FRAMES` frames, at the CPU frequency given.
+    pub fn add_cycles(&mut self, cycles: i32, frequency: u32) {
+        self.cycles += u64::from(cycles.unsigned_abs());
+        if self.frames >= FPS_FRAMES {
+            let frequency = u64::from(frequency);
+            self.fps = (self.frames * frequency + self.cycles / 2) / self.cycles;
+            self.cycles = 0;
+            self.frames = 0;
+        }
+    }
+}
+
+/// Wraps a video device, drawing the HUD over every frame sent to it.
+pub(crate) struct HudDevice<'a, V> {
+    pub video: &'a mut V,
+    pub hud: &'a mut Hud,
+    /// The number of the frame being completed
+    pub frame: u64,
+    /// The program counter of the CPU
+    pub pc: u16,
+}
+
+impl<'a, V: VideoDevice> VideoDevice for HudDevice<'a, V> {
+    fn blit_pixels(&mut self, pixels: &PixelBuffer) {
+        let hud = &mut *self.hud;
+        hud.frames += 1;
+        (*hud.buffer).clone_from(pixels);
+        let values = [hud.fps, self.frame, u64::from(self.pc)];
+        let formats: [fn(u64) -> String; 3] = [
+            |fps| format!("FPS {}", fps),
+            |frame| format!("FRM {}", frame),
+            |pc| format!("PC {:04X}", pc),
+        ];
+        for (line, (text, value)) in hud.lines.iter_mut().zip(values).enumerate() {
+            draw_text(&mut hud.buffer, line, text.update(value, formats[line]));
+        }
+        self.video.blit_pixels(&hud.buffer);
+    }
+
+    fn scanline_complete(&mut self, line: u16, pixels: &[u32]) {
+        self.video.scanline_complete(line, pixels);
+    }
+}
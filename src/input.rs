@@ -0,0 +1,163 @@
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use crate::controller::ButtonState;
+
+/// Represents the input fed to the console for a single frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FrameInput {
+    /// The buttons held on the first controller
+    pub player1: ButtonState,
+    /// The buttons held on the second controller
+    pub player2: ButtonState,
+    /// Whether or not the microphone on the second controller picks up sound
+    pub microphone: bool,
+}
+
+/// Represents the possible errors when parsing an input script.
+///
+/// Each error contains the number of the line it happened on, starting at 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScriptError {
+    /// A line didn't start with a valid frame or range of frames
+    BadFrame(usize),
+    /// A line contained something other than a button name
+    UnknownButton(usize),
+    /// A range covered more than `MAX_RANGE` frames
+    RangeTooLong(usize),
+}
+
+/// The most frames a single line of a script can cover, which is over
+/// 4 hours at 60 frames per second.
+///
+/// Every frame of a range gets its own input, so this bounds the memory
+/// used by scripts coming from untrusted sources.
+pub const MAX_RANGE: u64 = 1 << 20;
+
+/// Presses a button by name, returning false if the name is unknown
+fn press_button(buttons: &mut ButtonState, name: &str) -> bool {
+    let button = match name.to_ascii_uppercase().as_str() {
+        "A" => &mut buttons.a,
+        "B" => &mut buttons.b,
+        "SELECT" => &mut buttons.select,
+        "START" => &mut buttons.start,
+        "UP" => &mut buttons.up,
+        "DOWN" => &mut buttons.down,
+        "LEFT" => &mut buttons.left,
+        "RIGHT" => &mut buttons.right,
+        _ => return false,
+    };
+    *button = true;
+    true
+}
+
+/// Parses a frame like `frame120` or a range like `frame200-400`
+fn parse_frames(word: &str) -> Option<(u64, u64)> {
+    let frames = word.strip_prefix("frame")?;
+    let (start, end) = match frames.split_once('-') {
+        None => (frames, frames),
+        Some(range) => range,
+    };
+    let start = start.parse().ok()?;
+    let end: u64 = end.parse().ok()?;
+    // The frame after the range is needed to release the buttons
+    if start > end || end == u64::MAX {
+        None
+    } else {
+        Some((start, end))
+    }
+}
+
+/// Represents a script of inputs for the first controller.
+///
+/// Scripts are made of lines, each holding a frame or an inclusive range
+/// of frames, followed by the buttons to hold during those frames:
+///
+/// ```txt
+/// # wait for the title screen
+/// frame120 START
+/// frame200-400 RIGHT
+/// frame300-310 RIGHT A
+/// ```
+///
+/// Buttons are released on every frame no line mentions, and empty lines
+/// or lines starting with `#` are ignored. A line can cover at most
+/// `MAX_RANGE` frames.
+///
+/// Since this crate doesn't have access to the standard library, reading
+/// the script from standard input or from a file is left to the application.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct InputScript {
+    inputs: BTreeMap<u64, FrameInput>,
+}
+
+impl InputScript {
+    /// Parses the text of a script
+    pub fn parse(text: &str) -> Result<Self, ScriptError> {
+        let mut inputs: BTreeMap<u64, FrameInput> = BTreeMap::new();
+        let mut releases = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let mut words = line.split_whitespace();
+            let first = match words.next() {
+                None => continue,
+                Some(word) if word.starts_with('#') => continue,
+                Some(word) => word,
+            };
+            let (start, end) = parse_frames(first).ok_or(ScriptError::BadFrame(line_number))?;
+            if end - start >= MAX_RANGE {
+                return Err(ScriptError::RangeTooLong(line_number));
+            }
+            let mut buttons = ButtonState::default();
+            for word in words {
+                if !press_button(&mut buttons, word) {
+                    return Err(ScriptError::UnknownButton(line_number));
+                }
+            }
+            for frame in start..=end {
+                let input = inputs.entry(frame).or_default();
+                input.player1 = input.player1.merge(buttons);
+            }
+            releases.push(end + 1);
+        }
+        for frame in releases {
+            inputs.entry(frame).or_default();
+        }
+        Ok(InputScript { inputs })
+    }
+
+    /// Returns the input for each frame mentioned in the script, in order.
+    pub fn inputs(&self) -> impl Iterator<Item = (u64, FrameInput)> + '_ {
+        self.inputs.iter().map(|(frame, input)| (*frame, *input))
+    }
+}
+
+/// Holds inputs indexed by the frame they should be applied on.
+///
+/// Inputs aren't removed once they're applied, so that a frame can be
+/// simulated again with the same input after rolling back.
+#[derive(Default)]
+pub(crate) struct InputQueue {
+    inputs: BTreeMap<u64, FrameInput>,
+}
+
+impl InputQueue {
+    pub fn new() -> Self {
+        InputQueue::default()
+    }
+
+    /// Sets the input for a given frame, replacing any input already there.
+    pub fn insert(&mut self, frame: u64, input: FrameInput) {
+        self.inputs.insert(frame, input);
+    }
+
+    /// Returns the input queued for a given frame, if any
+    pub fn get(&self, frame: u64) -> Option<FrameInput> {
+        self.inputs.get(&frame).copied()
+    }
+
+    /// Removes every input queued for a frame before the one given
+    pub fn discard_before(&mut self, frame: u64) {
+        self.inputs = self.inputs.split_off(&frame);
+    }
+}
@@ -1,18 +1,53 @@
 #![no_std]
+#![allow(clippy::upper_case_acronyms)]
 
 #[macro_use]
 extern crate alloc;
 
 pub(crate) mod apu;
 pub mod cart;
+pub mod cheats;
+pub mod config;
 pub mod console;
 pub mod controller;
 pub(crate) mod cpu;
+pub mod frames;
+pub(crate) mod hash;
+pub(crate) mod hud;
+pub mod input;
 pub(crate) mod memory;
+pub mod movie;
+pub mod nsf;
+pub mod palette;
+pub mod png;
 pub mod ports;
 pub(crate) mod ppu;
+pub mod region;
+pub mod ring;
+pub mod state;
 
-pub use cart::{Cart, CartReadingError};
-pub use console::Console;
-pub use controller::ButtonState;
-pub use ports::{AudioDevice, PixelBuffer, VideoDevice, NES_HEIGHT, NES_WIDTH};
+#[cfg(test)]
+mod tests;
+
+pub use apu::{ApuWrite, AudioFilter};
+pub use cart::{Cart, CartInfo, CartReadingError};
+pub use cheats::{Cheat, CheatError};
+pub use config::{Accuracy, GameConfig, RamFill};
+pub use console::{BreakReason, Console, ConsoleBuilder, StepResult};
+pub use controller::{ButtonState, KeyBindings, Turbo};
+pub use cpu::{disassemble, disassemble_rom, disassembly_listing, Registers, TraceEntry};
+pub use frames::{frame_exchange, FrameConsumer, FrameProducer};
+pub use input::{FrameInput, InputScript, ScriptError};
+pub use memory::{EmulationError, WatchHit};
+pub use movie::{Movie, MovieError, MovieFrame};
+pub use nsf::{Nsf, NsfError};
+pub use palette::{Palette, PaletteError};
+pub use png::encode_png;
+pub use ports::{
+    test_pattern, AudioDevice, BufferedAudio, NullAudio, NullVideo, Overscan, PixelBuffer,
+    TestPattern, VideoDevice, NAMETABLES_HEIGHT, NAMETABLES_WIDTH, NES_HEIGHT, NES_WIDTH,
+};
+pub use ppu::{SpriteEntry, SpriteInfo};
+pub use region::Region;
+pub use ring::{audio_ring, AudioConsumer, AudioProducer};
+pub use state::StateError;
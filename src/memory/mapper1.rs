@@ -1,5 +1,8 @@
+use alloc::boxed::Box;
+
 use crate::cart::{Cart, Mirroring};
 use crate::memory::Mapper;
+use crate::state::{StateError, StateReader, StateWriter};
 
 const PRG_BANK_SIZE: usize = 0x4000;
 const CHR_BANK_SIZE: usize = 0x1000;
@@ -54,10 +57,22 @@ impl From<u8> for PRGSwitching {
     }
 }
 
+impl From<PRGSwitching> for u8 {
+    fn from(switching: PRGSwitching) -> Self {
+        match switching {
+            PRGSwitching::DoubleBank => 0,
+            PRGSwitching::Fix0 => 2,
+            PRGSwitching::Fix1 => 3,
+        }
+    }
+}
+
 /// Represents the 32KB bank of PRG data
 struct PRGBanks {
     /// How many 16KB banks exist
     count: u8,
+    /// The first bank of the 256KB half selected on 512KB carts, or 0
+    outer: usize,
     /// The index of the first bank
     bank_0: usize,
     /// The index of the second bank
@@ -72,8 +87,9 @@ impl PRGBanks {
         let count = cart.prg.len() / PRG_BANK_SIZE;
         PRGBanks {
             count: count as u8,
+            outer: 0,
             bank_0: 0,
-            bank_1: count - 1,
+            bank_1: count.min(16) - 1,
             switching: PRGSwitching::Fix1,
             control: 0,
         }
@@ -92,6 +108,48 @@ impl PRGBanks {
         }
     }
 
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.bank_0 as u8);
+        w.u8(self.bank_1 as u8);
+        w.u8(self.switching.into());
+        w.u8(self.control);
+        w.u8(self.outer as u8);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.bank_0 = (r.u8()? % self.count) as usize;
+        self.bank_1 = (r.u8()? % self.count) as usize;
+        self.switching = r.u8()?.into();
+        self.control = r.u8()?;
+        self.outer = match r.u8()? {
+            0 => 0,
+            16 if self.count == 32 => 16,
+            _ => return Err(StateError::InvalidValue),
+        };
+        Ok(())
+    }
+
+    /// Selects which 256KB half of PRG is used, on carts with 512KB of it.
+    ///
+    /// Other sizes above 256KB don't have a full upper half, so only
+    /// the lower one is used.
+    fn set_outer(&mut self, upper: bool) {
+        if self.count != 32 {
+            return;
+        }
+        let outer = if upper { 16 } else { 0 };
+        if self.outer != outer {
+            self.outer = outer;
+            let control = self.control;
+            self.write(control);
+        }
+    }
+
+    /// Whether or not PRG RAM is enabled, which bit 4 of the bank controls
+    fn ram_enabled(&self) -> bool {
+        self.control & 0x10 == 0
+    }
+
     fn set_switching<S: Into<PRGSwitching>>(&mut self, switching: S) {
         let into = switching.into();
         if self.switching != into {
@@ -103,19 +161,24 @@ impl PRGBanks {
 
     fn write(&mut self, control: u8) {
         self.control = control;
+        // Banks are switched within the selected 256KB half
+        let count = self.count.min(16);
+        let outer = self.outer;
         match self.switching {
             PRGSwitching::Fix0 => {
-                let bank = (control & 0xF) % self.count;
-                self.bank_1 = bank as usize;
+                let bank = (control & 0xF) % count;
+                self.bank_0 = outer;
+                self.bank_1 = outer + bank as usize;
             }
             PRGSwitching::Fix1 => {
-                let bank = (control & 0xF) % self.count;
-                self.bank_0 = bank as usize;
+                let bank = (control & 0xF) % count;
+                self.bank_0 = outer + bank as usize;
+                self.bank_1 = outer + count as usize - 1;
             }
             PRGSwitching::DoubleBank => {
-                let bank_0 = (control & 0xE) % self.count;
-                self.bank_0 = bank_0 as usize;
-                self.bank_1 = (bank_0 + 1) as usize;
+                let bank_0 = (control & 0xE) % count;
+                self.bank_0 = outer + bank_0 as usize;
+                self.bank_1 = outer + ((bank_0 + 1) % count) as usize;
             }
         }
     }
@@ -138,6 +201,15 @@ impl From<u8> for CHRSwitching {
     }
 }
 
+impl From<CHRSwitching> for u8 {
+    fn from(switching: CHRSwitching) -> Self {
+        match switching {
+            CHRSwitching::Double => 0,
+            CHRSwitching::Single => 1,
+        }
+    }
+}
+
 struct CHRBanks {
     count: u8,
     bank_0: usize,
@@ -172,6 +244,23 @@ impl CHRBanks {
         }
     }
 
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.bank_0 as u8);
+        w.u8(self.bank_1 as u8);
+        w.u8(self.switching.into());
+        w.u8(self.lower_control);
+        w.u8(self.upper_control);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.bank_0 = (r.u8()? % self.count) as usize;
+        self.bank_1 = (r.u8()? % self.count) as usize;
+        self.switching = r.u8()?.into();
+        self.lower_control = r.u8()?;
+        self.upper_control = r.u8()?;
+        Ok(())
+    }
+
     fn set_switching<S: Into<CHRSwitching>>(&mut self, switching: S) {
         let into = switching.into();
         if self.switching != into {
@@ -207,6 +296,10 @@ impl CHRBanks {
 
 /// The mapper for iNES 1.
 ///
+/// Carts with 512KB of PRG, like SUROM and SXROM boards, use bit 4 of the
+/// CHR bank registers to pick which 256KB half of PRG is mapped. With 4KB
+/// CHR banks, the last of the two registers written is the one used.
+///
 /// More info: https://wiki.nesdev.com/w/index.php/MMC1
 pub struct Mapper1 {
     /// The cartridge data
@@ -220,6 +313,15 @@ pub struct Mapper1 {
     /// This register is part of the behavior of the mapper, and can be used
     /// to control the bank switching behavior.
     shift_register: ShiftRegister,
+    /// Whether or not the serial port was written to during this CPU cycle.
+    ///
+    /// The MMC1 ignores writes on consecutive cycles, which happen when
+    /// a read-modify-write instruction targets the serial port. Since the
+    /// mapper is only stepped after a whole instruction, this is cleared
+    /// after each instruction instead.
+    written: bool,
+    /// Whether or not the upper CHR register was written last, instead of the lower one
+    upper_written: bool,
 }
 
 impl Mapper1 {
@@ -231,9 +333,21 @@ impl Mapper1 {
             prg,
             chr,
             shift_register: ShiftRegister::default(),
+            written: false,
+            upper_written: false,
         }
     }
 
+    /// Selects the half of PRG used by 512KB carts, from the CHR registers
+    fn update_outer(&mut self) {
+        let control = if self.upper_written && self.chr.switching == CHRSwitching::Single {
+            self.chr.upper_control
+        } else {
+            self.chr.lower_control
+        };
+        self.prg.set_outer(control & 0x10 != 0);
+    }
+
     fn write_control(&mut self, control: u8) {
         let mirroring = Mirroring::from(control & 3);
         self.cart.mirroring = mirroring;
@@ -241,6 +355,7 @@ impl Mapper1 {
         self.prg.set_switching(prg_mode);
         let chr_mode = (control >> 4) & 1;
         self.chr.set_switching(chr_mode);
+        self.update_outer();
     }
 
     fn write_shift(&mut self, address: u16, shift: u8) {
@@ -248,8 +363,12 @@ impl Mapper1 {
             self.prg.write(shift);
         } else if address >= 0xC000 {
             self.chr.write_upper(shift);
+            self.upper_written = true;
+            self.update_outer();
         } else if address >= 0xA000 {
             self.chr.write_lower(shift);
+            self.upper_written = false;
+            self.update_outer();
         } else {
             self.write_control(shift);
         }
@@ -263,6 +382,10 @@ impl Mapper for Mapper1 {
         } else if address >= 0x8000 {
             self.cart.prg[self.prg.index(address)]
         } else if address >= 0x6000 {
+            if !self.prg.ram_enabled() {
+                // Approximates open bus, with the high byte of the address
+                return (address >> 8) as u8;
+            }
             let shift = address - 0x6000;
             self.cart.sram[shift as usize]
         } else {
@@ -274,21 +397,69 @@ impl Mapper for Mapper1 {
         self.cart.mirroring
     }
 
+    fn cart(&self) -> &Cart {
+        &self.cart
+    }
+
+    fn cart_mut(&mut self) -> &mut Cart {
+        &mut self.cart
+    }
+
+    fn into_cart(self: Box<Self>) -> Cart {
+        self.cart
+    }
+
+    fn step(&mut self) {
+        self.written = false;
+    }
+
     fn write(&mut self, address: u16, value: u8) {
         if address < 0x2000 {
-            self.cart.chr[self.chr.index(address)] = value;
+            let index = self.chr.index(address);
+            self.cart.write_chr(index, value);
         } else if address >= 0x8000 {
+            if self.written {
+                return;
+            }
+            self.written = true;
             if value & 0x80 != 0 {
+                // This sets the PRG mode to 3, leaving the rest of control as is
                 self.shift_register = ShiftRegister::default();
-                self.write_control(0xC);
+                self.prg.set_switching(PRGSwitching::Fix1);
             } else if let Some(shift) = self.shift_register.shift(value) {
                 self.write_shift(address, shift);
             }
         } else if address >= 0x6000 {
+            if !self.prg.ram_enabled() {
+                return;
+            }
             let shift = address - 0x6000;
             self.cart.sram[shift as usize] = value;
         } else {
             panic!("Mapper1 unhandled write at {:X}", address);
         }
     }
+
+    fn save(&self, w: &mut StateWriter) {
+        self.cart.save(w);
+        self.prg.save(w);
+        self.chr.save(w);
+        w.u8(self.shift_register.register);
+        w.u8(self.shift_register.count);
+        w.bool(self.upper_written);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.cart.load(r)?;
+        self.prg.load(r)?;
+        self.chr.load(r)?;
+        self.shift_register.register = r.u8()?;
+        self.shift_register.count = r.u8()?;
+        // The register is emptied as soon as it holds 5 bits
+        if self.shift_register.count > 4 {
+            return Err(StateError::InvalidValue);
+        }
+        self.upper_written = r.bool()?;
+        Ok(())
+    }
 }
@@ -1,5 +1,8 @@
+use alloc::boxed::Box;
+
 use crate::cart::{Cart, Mirroring};
 use crate::memory::Mapper;
+use crate::state::{StateError, StateReader, StateWriter};
 
 pub struct Mapper2 {
     cart: Cart,
@@ -50,11 +53,23 @@ impl Mapper for Mapper2 {
         self.cart.mirroring
     }
 
+    fn cart(&self) -> &Cart {
+        &self.cart
+    }
+
+    fn cart_mut(&mut self) -> &mut Cart {
+        &mut self.cart
+    }
+
+    fn into_cart(self: Box<Self>) -> Cart {
+        self.cart
+    }
+
     fn write(&mut self, address: u16, value: u8) {
         match address {
-            a if a < 0x2000 => self.cart.chr[a as usize] = value,
+            a if a < 0x2000 => self.cart.write_chr(a as usize, value),
             a if a >= 0x8000 => {
-                let bank = value % self.prg_banks;
+                let bank = self.bus_value(a, value) % self.prg_banks;
                 self.prgbank1 = bank as usize;
             }
             a if a >= 0x6000 => {
@@ -66,4 +81,15 @@ impl Mapper for Mapper2 {
             }
         }
     }
+
+    fn save(&self, w: &mut StateWriter) {
+        self.cart.save(w);
+        w.u32(self.prgbank1 as u32);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.cart.load(r)?;
+        self.prgbank1 = r.u32()? as usize % usize::from(self.prg_banks);
+        Ok(())
+    }
 }
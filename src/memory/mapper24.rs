@@ -0,0 +1,463 @@
+use alloc::boxed::Box;
+
+use crate::cart::{Cart, Mirroring};
+use crate::memory::Mapper;
+use crate::state::{StateError, StateReader, StateWriter};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x400;
+
+/// How loud a single step of VRC6 volume is, compared to the APU.
+///
+/// This puts a VRC6 pulse at full volume roughly on par with an APU pulse.
+const VOLUME_SCALE: f32 = 0.00996;
+
+/// One of the two pulse channels of the VRC6
+#[derive(Default)]
+struct Pulse {
+    /// The duty cycle, between 0 and 7
+    duty: u8,
+    /// Whether or not the channel ignores the duty, and outputs its volume
+    ignore_duty: bool,
+    volume: u8,
+    enabled: bool,
+    period: u16,
+    timer: u16,
+    /// The current step in the 16 step sequence
+    step: u8,
+}
+
+impl Pulse {
+    fn write_control(&mut self, value: u8) {
+        self.ignore_duty = value & 0x80 != 0;
+        self.duty = (value >> 4) & 7;
+        self.volume = value & 0xF;
+    }
+
+    fn write_low(&mut self, value: u8) {
+        self.period = (self.period & 0xF00) | u16::from(value);
+    }
+
+    fn write_high(&mut self, value: u8) {
+        self.period = (self.period & 0xFF) | (u16::from(value & 0xF) << 8);
+        self.enabled = value & 0x80 != 0;
+        if !self.enabled {
+            self.step = 0;
+        }
+    }
+
+    fn step_timer(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.step = (self.step + 1) % 16;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && (self.ignore_duty || self.step <= self.duty) {
+            self.volume
+        } else {
+            0
+        }
+    }
+
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.duty);
+        w.bool(self.ignore_duty);
+        w.u8(self.volume);
+        w.bool(self.enabled);
+        w.u16(self.period);
+        w.u16(self.timer);
+        w.u8(self.step);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.duty = r.u8()? & 7;
+        self.ignore_duty = r.bool()?;
+        self.volume = r.u8()? & 0xF;
+        self.enabled = r.bool()?;
+        self.period = r.u16()? & 0xFFF;
+        self.timer = r.u16()? & 0xFFF;
+        self.step = r.u8()? & 0xF;
+        Ok(())
+    }
+}
+
+/// The sawtooth channel of the VRC6
+#[derive(Default)]
+struct Sawtooth {
+    /// The amount added to the accumulator every other step
+    rate: u8,
+    enabled: bool,
+    period: u16,
+    timer: u16,
+    /// The current step in the 14 step sequence
+    step: u8,
+    accumulator: u8,
+}
+
+impl Sawtooth {
+    fn write_rate(&mut self, value: u8) {
+        self.rate = value & 0x3F;
+    }
+
+    fn write_low(&mut self, value: u8) {
+        self.period = (self.period & 0xF00) | u16::from(value);
+    }
+
+    fn write_high(&mut self, value: u8) {
+        self.period = (self.period & 0xFF) | (u16::from(value & 0xF) << 8);
+        self.enabled = value & 0x80 != 0;
+        if !self.enabled {
+            self.step = 0;
+            self.accumulator = 0;
+        }
+    }
+
+    fn step_timer(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period;
+        self.step += 1;
+        if self.step == 14 {
+            self.step = 0;
+            self.accumulator = 0;
+        } else if self.step.is_multiple_of(2) {
+            self.accumulator = self.accumulator.wrapping_add(self.rate);
+        }
+    }
+
+    fn output(&self) -> u8 {
+        // Only the 5 highest bits of the accumulator are output
+        self.accumulator >> 3
+    }
+
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.rate);
+        w.bool(self.enabled);
+        w.u16(self.period);
+        w.u16(self.timer);
+        w.u8(self.step);
+        w.u8(self.accumulator);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.rate = r.u8()? & 0x3F;
+        self.enabled = r.bool()?;
+        self.period = r.u16()? & 0xFFF;
+        self.timer = r.u16()? & 0xFFF;
+        self.step = r.u8()? % 14;
+        self.accumulator = r.u8()?;
+        Ok(())
+    }
+}
+
+/// The IRQ counter of the VRC6.
+///
+/// This can either count CPU cycles, or approximate scanlines
+/// by counting CPU cycles in groups of 341 / 3.
+struct IRQCounter {
+    latch: u8,
+    counter: u8,
+    /// Used to divide CPU cycles into scanlines
+    prescaler: i16,
+    /// Whether or not to count CPU cycles instead of scanlines
+    cycle_mode: bool,
+    enabled: bool,
+    /// The value enabled takes when the IRQ is acknowledged
+    enable_after_ack: bool,
+    pending: bool,
+}
+
+impl Default for IRQCounter {
+    fn default() -> Self {
+        IRQCounter {
+            latch: 0,
+            counter: 0,
+            prescaler: 341,
+            cycle_mode: false,
+            enabled: false,
+            enable_after_ack: false,
+            pending: false,
+        }
+    }
+}
+
+impl IRQCounter {
+    fn write_control(&mut self, value: u8) {
+        self.enable_after_ack = value & 1 != 0;
+        self.enabled = value & 2 != 0;
+        self.cycle_mode = value & 4 != 0;
+        self.pending = false;
+        if self.enabled {
+            self.counter = self.latch;
+            self.prescaler = 341;
+        }
+    }
+
+    fn acknowledge(&mut self) {
+        self.pending = false;
+        self.enabled = self.enable_after_ack;
+    }
+
+    fn step(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        if self.cycle_mode {
+            self.clock();
+        } else {
+            self.prescaler -= 3;
+            if self.prescaler <= 0 {
+                self.prescaler += 341;
+                self.clock();
+            }
+        }
+    }
+
+    fn clock(&mut self) {
+        if self.counter == 0xFF {
+            self.counter = self.latch;
+            self.pending = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.latch);
+        w.u8(self.counter);
+        w.u16(self.prescaler as u16);
+        w.bool(self.cycle_mode);
+        w.bool(self.enabled);
+        w.bool(self.enable_after_ack);
+        w.bool(self.pending);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.latch = r.u8()?;
+        self.counter = r.u8()?;
+        self.prescaler = (r.u16()? as i16).clamp(1, 341);
+        self.cycle_mode = r.bool()?;
+        self.enabled = r.bool()?;
+        self.enable_after_ack = r.bool()?;
+        self.pending = r.bool()?;
+        Ok(())
+    }
+}
+
+/// The mapper for iNES 24 and 26, used by Konami's VRC6.
+///
+/// The two only differ by having the lowest two address lines swapped.
+/// Only the default PPU banking mode, with 8 1KB CHR banks, is supported.
+///
+/// More info: https://wiki.nesdev.com/w/index.php/VRC6
+pub struct Mapper24 {
+    /// The cartridge data
+    cart: Cart,
+    /// Whether or not the lowest two address lines are swapped, for iNES 26
+    swap_lines: bool,
+    /// The 16KB PRG bank at 0x8000, in units of 8KB banks
+    prg_16k: usize,
+    /// The 8KB PRG bank at 0xC000
+    prg_8k: usize,
+    /// The number of 8KB PRG banks
+    prg_count: usize,
+    /// The 1KB CHR banks
+    chr: [usize; 8],
+    /// The number of 1KB CHR banks
+    chr_count: usize,
+    /// Whether or not PRG RAM is readable and writable
+    ram_enabled: bool,
+    pulse1: Pulse,
+    pulse2: Pulse,
+    sawtooth: Sawtooth,
+    /// Stops every audio channel when set
+    audio_halted: bool,
+    irq: IRQCounter,
+}
+
+impl Mapper24 {
+    pub fn new(cart: Cart, swap_lines: bool) -> Self {
+        let prg_count = cart.prg.len() / PRG_BANK_SIZE;
+        let chr_count = cart.chr.len() / CHR_BANK_SIZE;
+        Mapper24 {
+            cart,
+            swap_lines,
+            prg_16k: 0,
+            prg_8k: 0,
+            prg_count,
+            chr: [0, 1, 2, 3, 4, 5, 6, 7],
+            chr_count,
+            ram_enabled: false,
+            pulse1: Pulse::default(),
+            pulse2: Pulse::default(),
+            sawtooth: Sawtooth::default(),
+            audio_halted: false,
+            irq: IRQCounter::default(),
+        }
+    }
+
+    // Returns an index into cart.prg
+    fn prg_index(&self, address: u16) -> usize {
+        let bank = match address {
+            0x8000..=0xBFFF => self.prg_16k + usize::from((address >> 13) & 1),
+            0xC000..=0xDFFF => self.prg_8k,
+            _ => self.prg_count - 1,
+        };
+        (bank % self.prg_count) * PRG_BANK_SIZE + (address as usize % PRG_BANK_SIZE)
+    }
+
+    // Returns an index into cart.chr
+    fn chr_index(&self, address: u16) -> usize {
+        let bank = self.chr[(address / 0x400) as usize] % self.chr_count;
+        bank * CHR_BANK_SIZE + (address as usize % CHR_BANK_SIZE)
+    }
+
+    fn write_banking_mode(&mut self, value: u8) {
+        self.cart.mirroring = match (value >> 2) & 3 {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleLower,
+            _ => Mirroring::SingleUpper,
+        };
+        self.ram_enabled = value & 0x80 != 0;
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) {
+        let low = address & 3;
+        let low = if self.swap_lines {
+            ((low & 1) << 1) | (low >> 1)
+        } else {
+            low
+        };
+        match (address & 0xF000, low) {
+            (0x8000, _) => self.prg_16k = usize::from(value & 0xF) * 2,
+            (0x9000, 0) => self.pulse1.write_control(value),
+            (0x9000, 1) => self.pulse1.write_low(value),
+            (0x9000, 2) => self.pulse1.write_high(value),
+            (0x9000, _) => self.audio_halted = value & 1 != 0,
+            (0xA000, 0) => self.pulse2.write_control(value),
+            (0xA000, 1) => self.pulse2.write_low(value),
+            (0xA000, 2) => self.pulse2.write_high(value),
+            (0xB000, 0) => self.sawtooth.write_rate(value),
+            (0xB000, 1) => self.sawtooth.write_low(value),
+            (0xB000, 2) => self.sawtooth.write_high(value),
+            (0xB000, _) => self.write_banking_mode(value),
+            (0xC000, _) => self.prg_8k = usize::from(value & 0x1F),
+            (0xD000, i) => self.chr[i as usize] = usize::from(value),
+            (0xE000, i) => self.chr[4 + i as usize] = usize::from(value),
+            (0xF000, 0) => self.irq.latch = value,
+            (0xF000, 1) => self.irq.write_control(value),
+            (0xF000, 2) => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for Mapper24 {
+    fn read(&self, address: u16) -> u8 {
+        if address < 0x2000 {
+            self.cart.chr[self.chr_index(address)]
+        } else if address >= 0x8000 {
+            self.cart.prg[self.prg_index(address)]
+        } else if address >= 0x6000 {
+            if self.ram_enabled {
+                self.cart.sram[(address - 0x6000) as usize]
+            } else {
+                0
+            }
+        } else {
+            panic!("Mapper24 unhandled read at {:X}", address);
+        }
+    }
+
+    fn mirroring_mode(&self) -> Mirroring {
+        self.cart.mirroring
+    }
+
+    fn cart(&self) -> &Cart {
+        &self.cart
+    }
+
+    fn cart_mut(&mut self) -> &mut Cart {
+        &mut self.cart
+    }
+
+    fn into_cart(self: Box<Self>) -> Cart {
+        self.cart
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        if address < 0x2000 {
+            let index = self.chr_index(address);
+            self.cart.write_chr(index, value);
+        } else if address >= 0x8000 {
+            self.write_register(address, value);
+        } else if address >= 0x6000 {
+            if self.ram_enabled {
+                self.cart.sram[(address - 0x6000) as usize] = value;
+            }
+        } else {
+            panic!("Mapper24 unhandled write at {:X}", address);
+        }
+    }
+
+    fn step(&mut self) {
+        self.irq.step();
+        if !self.audio_halted {
+            self.pulse1.step_timer();
+            self.pulse2.step_timer();
+            self.sawtooth.step_timer();
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending
+    }
+
+    fn expansion_audio(&self) -> f32 {
+        let sum = self.pulse1.output() + self.pulse2.output() + self.sawtooth.output();
+        f32::from(sum) * VOLUME_SCALE
+    }
+
+    fn save(&self, w: &mut StateWriter) {
+        self.cart.save(w);
+        w.u8(self.prg_16k as u8);
+        w.u8(self.prg_8k as u8);
+        for &bank in &self.chr {
+            w.u8(bank as u8);
+        }
+        w.bool(self.ram_enabled);
+        self.pulse1.save(w);
+        self.pulse2.save(w);
+        self.sawtooth.save(w);
+        w.bool(self.audio_halted);
+        self.irq.save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.cart.load(r)?;
+        self.prg_16k = usize::from(r.u8()? & 0x1E);
+        self.prg_8k = usize::from(r.u8()? & 0x1F);
+        for bank in self.chr.iter_mut() {
+            *bank = usize::from(r.u8()?);
+        }
+        self.ram_enabled = r.bool()?;
+        self.pulse1.load(r)?;
+        self.pulse2.load(r)?;
+        self.sawtooth.load(r)?;
+        self.audio_halted = r.bool()?;
+        self.irq.load(r)
+    }
+}
@@ -0,0 +1,99 @@
+use alloc::boxed::Box;
+
+use crate::cart::{Cart, Mirroring};
+use crate::memory::Mapper;
+use crate::state::{StateError, StateReader, StateWriter};
+
+const CHR_BANK_SIZE: usize = 0x2000;
+
+/// The mapper for iNES 3, CNROM.
+///
+/// PRG is fixed, and any write to $8000-$FFFF selects the 8KB CHR bank.
+///
+/// More info: https://wiki.nesdev.com/w/index.php/CNROM
+pub struct Mapper3 {
+    cart: Cart,
+    chr_banks: u8,
+    chr_bank: usize,
+}
+
+impl Mapper3 {
+    pub fn new(cart: Cart) -> Self {
+        let chr_banks = cart.chr.len() / CHR_BANK_SIZE;
+        Mapper3 {
+            cart,
+            chr_banks: chr_banks as u8,
+            chr_bank: 0,
+        }
+    }
+}
+
+impl Mapper for Mapper3 {
+    fn read(&self, address: u16) -> u8 {
+        match address {
+            a if a < 0x2000 => {
+                let index = self.chr_bank * CHR_BANK_SIZE + a as usize;
+                self.cart.chr[index]
+            }
+            a if a >= 0x8000 => {
+                // 16KB carts are mirrored at $C000
+                let index = (a - 0x8000) as usize % self.cart.prg.len();
+                self.cart.prg[index]
+            }
+            a if a >= 0x6000 => {
+                let shifted = (address - 0x6000) as usize;
+                self.cart.sram[shifted]
+            }
+            a => {
+                panic!("Mapper3 unhandled read at {:X}", a);
+            }
+        }
+    }
+
+    fn mirroring_mode(&self) -> Mirroring {
+        self.cart.mirroring
+    }
+
+    fn cart(&self) -> &Cart {
+        &self.cart
+    }
+
+    fn cart_mut(&mut self) -> &mut Cart {
+        &mut self.cart
+    }
+
+    fn into_cart(self: Box<Self>) -> Cart {
+        self.cart
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        match address {
+            a if a < 0x2000 => {
+                let index = self.chr_bank * CHR_BANK_SIZE + a as usize;
+                self.cart.write_chr(index, value);
+            }
+            a if a >= 0x8000 => {
+                let bank = self.bus_value(a, value) % self.chr_banks;
+                self.chr_bank = bank as usize;
+            }
+            a if a >= 0x6000 => {
+                let shifted = (address - 0x6000) as usize;
+                self.cart.sram[shifted] = value;
+            }
+            a => {
+                panic!("Mapper3 unhandled write at {:X}", a);
+            }
+        }
+    }
+
+    fn save(&self, w: &mut StateWriter) {
+        self.cart.save(w);
+        w.u8(self.chr_bank as u8);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.cart.load(r)?;
+        self.chr_bank = usize::from(r.u8()? % self.chr_banks);
+        Ok(())
+    }
+}
@@ -0,0 +1,126 @@
+use alloc::boxed::Box;
+
+use crate::cart::{Cart, Mirroring};
+use crate::memory::Mapper;
+use crate::state::{StateError, StateReader, StateWriter};
+
+const PRG_BANK_SIZE: usize = 0x8000;
+const CHR_BANK_SIZE: usize = 0x2000;
+
+/// The mapper for iNES 66, GxROM, and iNES 11, Color Dreams.
+///
+/// Both select a 32KB PRG bank and an 8KB CHR bank with any write to
+/// $8000-$FFFF, and only differ by which bits hold each bank. GxROM
+/// also has bus conflicts, unlike Color Dreams.
+///
+/// More info: https://wiki.nesdev.com/w/index.php/GxROM
+/// and https://wiki.nesdev.com/w/index.php/Color_Dreams
+pub struct Mapper66 {
+    /// The cartridge data
+    cart: Cart,
+    /// Whether or not the banks use the layout of Color Dreams, for iNES 11
+    color_dreams: bool,
+    /// The number of 32KB PRG banks
+    prg_banks: u8,
+    /// The number of 8KB CHR banks
+    chr_banks: u8,
+    prg_bank: usize,
+    chr_bank: usize,
+}
+
+impl Mapper66 {
+    pub fn new(cart: Cart, color_dreams: bool) -> Self {
+        let prg_banks = (cart.prg.len() / PRG_BANK_SIZE).max(1);
+        let chr_banks = (cart.chr.len() / CHR_BANK_SIZE).max(1);
+        Mapper66 {
+            cart,
+            color_dreams,
+            prg_banks: prg_banks as u8,
+            chr_banks: chr_banks as u8,
+            prg_bank: 0,
+            chr_bank: 0,
+        }
+    }
+
+    /// Selects the banks from a value written to $8000-$FFFF
+    fn select_banks(&mut self, value: u8) {
+        let (prg, chr) = if self.color_dreams {
+            (value & 0x03, value >> 4)
+        } else {
+            ((value >> 4) & 0x03, value & 0x03)
+        };
+        self.prg_bank = usize::from(prg % self.prg_banks);
+        self.chr_bank = usize::from(chr % self.chr_banks);
+    }
+}
+
+impl Mapper for Mapper66 {
+    fn read(&self, address: u16) -> u8 {
+        match address {
+            a if a < 0x2000 => {
+                let index = self.chr_bank * CHR_BANK_SIZE + a as usize;
+                self.cart.chr[index % self.cart.chr.len()]
+            }
+            a if a >= 0x8000 => {
+                let index = self.prg_bank * PRG_BANK_SIZE + (a - 0x8000) as usize;
+                self.cart.prg[index % self.cart.prg.len()]
+            }
+            a if a >= 0x6000 => {
+                let shifted = (address - 0x6000) as usize;
+                self.cart.sram[shifted]
+            }
+            a => {
+                panic!("Mapper66 unhandled read at {:X}", a);
+            }
+        }
+    }
+
+    fn mirroring_mode(&self) -> Mirroring {
+        self.cart.mirroring
+    }
+
+    fn cart(&self) -> &Cart {
+        &self.cart
+    }
+
+    fn cart_mut(&mut self) -> &mut Cart {
+        &mut self.cart
+    }
+
+    fn into_cart(self: Box<Self>) -> Cart {
+        self.cart
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        match address {
+            a if a < 0x2000 => {
+                let index = self.chr_bank * CHR_BANK_SIZE + a as usize;
+                self.cart.write_chr(index % self.cart.chr.len(), value);
+            }
+            a if a >= 0x8000 => {
+                let value = self.bus_value(a, value);
+                self.select_banks(value);
+            }
+            a if a >= 0x6000 => {
+                let shifted = (address - 0x6000) as usize;
+                self.cart.sram[shifted] = value;
+            }
+            a => {
+                panic!("Mapper66 unhandled write at {:X}", a);
+            }
+        }
+    }
+
+    fn save(&self, w: &mut StateWriter) {
+        self.cart.save(w);
+        w.u8(self.prg_bank as u8);
+        w.u8(self.chr_bank as u8);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.cart.load(r)?;
+        self.prg_bank = usize::from(r.u8()? % self.prg_banks);
+        self.chr_bank = usize::from(r.u8()? % self.chr_banks);
+        Ok(())
+    }
+}
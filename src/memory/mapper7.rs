@@ -0,0 +1,101 @@
+use alloc::boxed::Box;
+
+use crate::cart::{Cart, Mirroring};
+use crate::memory::Mapper;
+use crate::state::{StateError, StateReader, StateWriter};
+
+const PRG_BANK_SIZE: usize = 0x8000;
+
+/// The mapper for iNES 7, AxROM.
+///
+/// Any write to $8000-$FFFF selects the 32KB PRG bank with its lowest
+/// bits, and which nametable is used for every mirror with bit 4.
+///
+/// More info: https://wiki.nesdev.com/w/index.php/AxROM
+pub struct Mapper7 {
+    cart: Cart,
+    prg_banks: u8,
+    prg_bank: usize,
+}
+
+impl Mapper7 {
+    pub fn new(mut cart: Cart) -> Self {
+        let prg_banks = (cart.prg.len() / PRG_BANK_SIZE).max(1);
+        // The header's mirroring is ignored, since the mapper controls it
+        cart.mirroring = Mirroring::SingleLower;
+        Mapper7 {
+            cart,
+            prg_banks: prg_banks as u8,
+            prg_bank: 0,
+        }
+    }
+}
+
+impl Mapper for Mapper7 {
+    fn read(&self, address: u16) -> u8 {
+        match address {
+            a if a < 0x2000 => self.cart.chr[a as usize],
+            a if a >= 0x8000 => {
+                let shifted = (a - 0x8000) as usize;
+                let index = self.prg_bank * PRG_BANK_SIZE + shifted;
+                self.cart.prg[index % self.cart.prg.len()]
+            }
+            a if a >= 0x6000 => {
+                let shifted = (address - 0x6000) as usize;
+                self.cart.sram[shifted]
+            }
+            a => {
+                panic!("Mapper7 unhandled read at {:X}", a);
+            }
+        }
+    }
+
+    fn mirroring_mode(&self) -> Mirroring {
+        self.cart.mirroring
+    }
+
+    fn cart(&self) -> &Cart {
+        &self.cart
+    }
+
+    fn cart_mut(&mut self) -> &mut Cart {
+        &mut self.cart
+    }
+
+    fn into_cart(self: Box<Self>) -> Cart {
+        self.cart
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        match address {
+            a if a < 0x2000 => self.cart.write_chr(a as usize, value),
+            a if a >= 0x8000 => {
+                let value = self.bus_value(a, value);
+                self.prg_bank = usize::from((value & 0x07) % self.prg_banks);
+                self.cart.mirroring = if value & 0x10 == 0 {
+                    Mirroring::SingleLower
+                } else {
+                    Mirroring::SingleUpper
+                };
+            }
+            a if a >= 0x6000 => {
+                let shifted = (address - 0x6000) as usize;
+                self.cart.sram[shifted] = value;
+            }
+            a => {
+                panic!("Mapper7 unhandled write at {:X}", a);
+            }
+        }
+    }
+
+    fn save(&self, w: &mut StateWriter) {
+        self.cart.save(w);
+        w.u8(self.prg_bank as u8);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.cart.load(r)?;
+        self.prg_bank = usize::from(r.u8()? % self.prg_banks);
+        Ok(())
+    }
+}
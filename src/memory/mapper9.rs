@@ -0,0 +1,165 @@
+use alloc::boxed::Box;
+
+use crate::cart::{Cart, Mirroring};
+use crate::memory::Mapper;
+use crate::state::{StateError, StateReader, StateWriter};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x1000;
+
+/// The mapper for iNES 9, MMC2, used by Punch-Out!!
+///
+/// An 8KB PRG bank is selected at $8000, with the last three banks fixed
+/// at $A000-$FFFF. Each 4KB pattern table has two CHR banks, picked by
+/// a latch that the PPU flips when fetching tile $FD or $FE from that table.
+/// Games use this to switch CHR banks in the middle of a frame, without
+/// any timing.
+///
+/// More info: https://wiki.nesdev.com/w/index.php/MMC2
+pub struct Mapper9 {
+    cart: Cart,
+    /// The number of 8KB PRG banks
+    prg_banks: u8,
+    /// The number of 4KB CHR banks
+    chr_banks: u8,
+    prg_bank: usize,
+    /// The banks of each pattern table, when its latch is $FD, then $FE
+    chr_select: [[u8; 2]; 2],
+    /// Which bank of `chr_select` each pattern table uses, with 1 for $FE
+    latches: [usize; 2],
+}
+
+impl Mapper9 {
+    pub fn new(cart: Cart) -> Self {
+        let prg_banks = (cart.prg.len() / PRG_BANK_SIZE).max(1);
+        let chr_banks = (cart.chr.len() / CHR_BANK_SIZE).max(1);
+        Mapper9 {
+            cart,
+            prg_banks: prg_banks as u8,
+            chr_banks: chr_banks as u8,
+            prg_bank: 0,
+            chr_select: [[0; 2]; 2],
+            latches: [1, 1],
+        }
+    }
+
+    /// Returns the index in CHR of an address in the pattern tables
+    fn chr_index(&self, address: u16) -> usize {
+        let table = usize::from(address >> 12);
+        let bank = self.chr_select[table][self.latches[table]] % self.chr_banks;
+        let index = usize::from(bank) * CHR_BANK_SIZE + usize::from(address & 0xFFF);
+        index % self.cart.chr.len()
+    }
+
+    /// Returns the 8KB PRG bank at an address of $8000-$FFFF
+    fn prg_bank_at(&self, address: u16) -> usize {
+        let last = usize::from(self.prg_banks);
+        match (address - 0x8000) as usize / PRG_BANK_SIZE {
+            0 => self.prg_bank,
+            slot => (last + slot).saturating_sub(4) % last,
+        }
+    }
+}
+
+impl Mapper for Mapper9 {
+    fn read(&self, address: u16) -> u8 {
+        match address {
+            a if a < 0x2000 => self.cart.chr[self.chr_index(a)],
+            a if a >= 0x8000 => {
+                let shifted = a as usize % PRG_BANK_SIZE;
+                let index = self.prg_bank_at(a) * PRG_BANK_SIZE + shifted;
+                self.cart.prg[index % self.cart.prg.len()]
+            }
+            a if a >= 0x6000 => {
+                let shifted = (address - 0x6000) as usize;
+                self.cart.sram[shifted]
+            }
+            a => {
+                panic!("Mapper9 unhandled read at {:X}", a);
+            }
+        }
+    }
+
+    fn mirroring_mode(&self) -> Mirroring {
+        self.cart.mirroring
+    }
+
+    fn cart(&self) -> &Cart {
+        &self.cart
+    }
+
+    fn cart_mut(&mut self) -> &mut Cart {
+        &mut self.cart
+    }
+
+    fn into_cart(self: Box<Self>) -> Cart {
+        self.cart
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        match address {
+            a if a < 0x2000 => {
+                let index = self.chr_index(a);
+                self.cart.write_chr(index, value);
+            }
+            0xA000..=0xAFFF => self.prg_bank = usize::from((value & 0x0F) % self.prg_banks),
+            0xB000..=0xBFFF => self.chr_select[0][0] = value & 0x1F,
+            0xC000..=0xCFFF => self.chr_select[0][1] = value & 0x1F,
+            0xD000..=0xDFFF => self.chr_select[1][0] = value & 0x1F,
+            0xE000..=0xEFFF => self.chr_select[1][1] = value & 0x1F,
+            0xF000..=0xFFFF => {
+                self.cart.mirroring = if value & 1 == 0 {
+                    Mirroring::Vertical
+                } else {
+                    Mirroring::Horizontal
+                };
+            }
+            // $8000-$9FFF has no registers
+            a if a >= 0x8000 => {}
+            a if a >= 0x6000 => {
+                let shifted = (address - 0x6000) as usize;
+                self.cart.sram[shifted] = value;
+            }
+            a => {
+                panic!("Mapper9 unhandled write at {:X}", a);
+            }
+        }
+    }
+
+    /// Flips the latch of a pattern table after fetching the bottom half
+    /// of tile $FD or $FE. The first table only reacts to the first row.
+    fn pattern_fetched(&mut self, address: u16) {
+        let table = usize::from(address >> 12);
+        let latch = match (table, address & 0xFFF) {
+            (0, 0xFD8) | (1, 0xFD8..=0xFDF) => 0,
+            (0, 0xFE8) | (1, 0xFE8..=0xFEF) => 1,
+            _ => return,
+        };
+        self.latches[table] = latch;
+    }
+
+    fn save(&self, w: &mut StateWriter) {
+        self.cart.save(w);
+        w.u8(self.prg_bank as u8);
+        for banks in &self.chr_select {
+            w.u8(banks[0]);
+            w.u8(banks[1]);
+        }
+        for &latch in &self.latches {
+            w.u8(latch as u8);
+        }
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.cart.load(r)?;
+        self.prg_bank = usize::from(r.u8()? % self.prg_banks);
+        for banks in self.chr_select.iter_mut() {
+            banks[0] = r.u8()? & 0x1F;
+            banks[1] = r.u8()? & 0x1F;
+        }
+        for latch in self.latches.iter_mut() {
+            *latch = usize::from(r.u8()? & 1);
+        }
+        Ok(())
+    }
+}
@@ -1,19 +1,126 @@
 mod mapper1;
 mod mapper2;
+mod mapper24;
+mod mapper3;
+mod mapper66;
+mod mapper7;
+mod mapper9;
+mod nsf;
 
 use alloc::boxed::Box;
+use alloc::vec::Vec;
 
-use super::apu::APUState;
+use super::apu::{APUState, ApuLog, ApuWrite};
 use super::cart::{Cart, MapperID, Mirroring};
-use super::controller::Controller;
+use super::cheats::Cheat;
+use super::config::RamFill;
+use super::controller::{Controller, Zapper};
 use super::cpu::CPUState;
 use super::ppu::PPUState;
+use crate::state::{StateError, StateReader, StateWriter};
 
-/// Used to abstract over the different types of Mappers
-pub trait Mapper {
+/// Represents the errors the console can run into while emulating a game.
+///
+/// These come from games doing something the emulator doesn't support,
+/// or from bugs in the emulator. The console keeps running after an error,
+/// like hardware would, but the game is likely to misbehave.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EmulationError {
+    /// The CPU read from an address nothing is mapped to
+    UnhandledRead(u16),
+    /// The CPU wrote to an address nothing is mapped to
+    UnhandledWrite(u16),
+    /// The CPU ran into an opcode that isn't implemented
+    UnknownOpcode(u8),
+}
+
+/// Describes an access to an address being watched.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WatchHit {
+    /// The address of the instruction making the access
+    pub pc: u16,
+    /// The address accessed, as used by the instruction
+    pub address: u16,
+    /// The value read or written
+    pub value: u8,
+    /// Whether the value was written, instead of read
+    pub write: bool,
+}
+
+/// An address to watch, along with the accesses that trigger it
+#[derive(Clone, Copy)]
+pub(crate) struct Watch {
+    pub address: u16,
+    pub on_read: bool,
+    pub on_write: bool,
+}
+
+/// Used to abstract over the different types of Mappers.
+///
+/// Mappers have to be `Send`, so that a console can be moved to its own thread.
+pub trait Mapper: Send {
     fn read(&self, address: u16) -> u8;
     fn mirroring_mode(&self) -> Mirroring;
     fn write(&mut self, address: u16, value: u8);
+
+    /// Returns the cart plugged into this mapper
+    fn cart(&self) -> &Cart;
+    fn cart_mut(&mut self) -> &mut Cart;
+
+    /// Unplugs the cart, dropping the mapper
+    fn into_cart(self: Box<Self>) -> Cart;
+
+    /// Steps the mapper forward by a single CPU cycle.
+    ///
+    /// Most mappers don't need to keep track of time.
+    fn step(&mut self) {}
+
+    /// Returns true if the mapper is holding the IRQ line.
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// Saves the registers of the mapper, along with the parts of the cart
+    /// that can change while playing.
+    fn save(&self, w: &mut StateWriter);
+
+    /// Restores the state written by `save`.
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError>;
+
+    /// Returns the output of any extra audio channels on the cart.
+    ///
+    /// This is mixed with the APU output, and should use the same scale.
+    fn expansion_audio(&self) -> f32 {
+        0.0
+    }
+
+    /// Returns the value a register in ROM sees when written.
+    ///
+    /// With bus conflicts, ROM wins over the CPU for 0 bits, so the value
+    /// written is ANDed with the byte of ROM at that address.
+    fn bus_value(&self, address: u16, value: u8) -> u8 {
+        if self.cart().bus_conflicts {
+            value & self.read(address)
+        } else {
+            value
+        }
+    }
+
+    /// Called by the PPU after fetching a byte of the pattern tables while
+    /// rendering, for mappers switching CHR banks based on the tiles drawn.
+    fn pattern_fetched(&mut self, _address: u16) {}
+
+    /// Reads from $4020-$5FFF, returning None if nothing is mapped there.
+    ///
+    /// Most carts leave this range empty.
+    fn read_low(&self, _address: u16) -> Option<u8> {
+        None
+    }
+
+    /// Writes to $4020-$5FFF, returning false if nothing is mapped there.
+    fn write_low(&mut self, _address: u16, _value: u8) -> bool {
+        false
+    }
 }
 
 impl dyn Mapper {
@@ -23,10 +130,83 @@ impl dyn Mapper {
         match cart.mapper {
             MapperID::M1 => Box::new(mapper1::Mapper1::new(cart)),
             MapperID::M2 => Box::new(mapper2::Mapper2::new(cart)),
+            MapperID::M3 => Box::new(mapper3::Mapper3::new(cart)),
+            MapperID::M7 => Box::new(mapper7::Mapper7::new(cart)),
+            MapperID::M9 => Box::new(mapper9::Mapper9::new(cart)),
+            MapperID::M11 => Box::new(mapper66::Mapper66::new(cart, true)),
+            MapperID::M24 => Box::new(mapper24::Mapper24::new(cart, false)),
+            MapperID::M26 => Box::new(mapper24::Mapper24::new(cart, true)),
+            MapperID::M66 => Box::new(mapper66::Mapper66::new(cart, false)),
+            MapperID::Nsf => Box::new(nsf::NsfMapper::new(cart)),
         }
     }
 }
 
+/// A small pseudo random number generator, using SplitMix64.
+///
+/// This is only used to fill memory on power up, where being reproducible
+/// matters more than the quality of the numbers.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn fill(&mut self, bytes: &mut [u8]) {
+        for chunk in bytes.chunks_mut(8) {
+            let random = self.next().to_le_bytes();
+            chunk.copy_from_slice(&random[..chunk.len()]);
+        }
+    }
+}
+
+/// Maps the mirrors of RAM to the first 2KB, leaving other addresses as is
+fn mirror_ram(address: u16) -> u16 {
+    if address < 0x2000 {
+        address % 0x800
+    } else {
+        address
+    }
+}
+
+/// Controls what memory contains when the console is powered on
+#[derive(Clone, Copy, Default)]
+pub(crate) struct PowerOn {
+    /// Whether to fill palette ram and OAM with the values found on hardware,
+    /// instead of zeroing them.
+    pub accurate_ppu: bool,
+    /// If present, ram, palette ram and OAM are filled with random values
+    /// generated from this seed, taking precedence over `accurate_ppu`.
+    pub seed: Option<u64>,
+    /// What ram is filled with, unless a seed is given
+    pub ram: RamFill,
+}
+
+impl PowerOn {
+    /// Returns the contents of ram, along with the state of the PPU
+    fn memory(self) -> ([u8; 0x2000], PPUState) {
+        let mut ram = [0; 0x2000];
+        for (address, byte) in ram.iter_mut().enumerate() {
+            *byte = self.ram.byte(address);
+        }
+        let mut ppu = PPUState::new();
+        if self.accurate_ppu {
+            ppu.power_up();
+        }
+        if let Some(seed) = self.seed {
+            let mut rng = SplitMix64(seed);
+            rng.fill(&mut ram);
+            ppu.fill_memory(|bytes| rng.fill(bytes));
+        }
+        (ram, ppu)
+    }
+}
+
 /// Holds cart memory
 pub(crate) struct MemoryBus {
     // Contains the mapper logic for interfacing with the cart
@@ -38,86 +218,327 @@ pub(crate) struct MemoryBus {
     pub ppu: PPUState,
     // public for access by the cpu
     pub controller1: Controller,
-    controller2: Controller,
+    pub controller2: Controller,
+    /// The Zapper, replacing the second controller if plugged in
+    pub zapper: Option<Zapper>,
+    /// Whether or not the microphone on the second Famicom controller
+    /// is picking up sound
+    pub microphone: bool,
+    /// The first error since this was last cleared
+    pub error: Option<EmulationError>,
+    /// The cheats patching reads of PRG
+    pub cheats: Vec<Cheat>,
+    /// The last value read or written by the CPU.
+    ///
+    /// Reading from an address nothing drives returns this value,
+    /// since it lingers on the data bus.
+    last_bus_value: u8,
+    /// The address of the last access by the CPU, and whether it was a write
+    last_access: (u16, bool),
+    /// Whether or not the APU is being stepped on the cycle of the last
+    /// access of the CPU, which a DMC fetch would repeat
+    pub on_last_access: bool,
+    /// The addresses watched for reads or writes
+    pub watches: Vec<Watch>,
+    /// The last writes to APU registers, if they're being logged
+    pub apu_log: Option<ApuLog>,
+    /// The first watched access since this was last cleared.
+    ///
+    /// The PC is left as 0 here, since the bus doesn't know it.
+    pub watch_hit: Option<WatchHit>,
     ram: [u8; 0x2000],
+    power_on: PowerOn,
 }
 
 impl MemoryBus {
-    /// Creates a memory bus from a c
-    pub fn with_cart(cart: Cart) -> Self {
+    /// Creates a memory bus from a cart
+    pub fn with_cart(cart: Cart, power_on: PowerOn) -> Self {
         let mapper = <dyn Mapper>::with_cart(cart);
+        let (ram, ppu) = power_on.memory();
         MemoryBus {
             mapper,
             apu: APUState::new(),
             cpu: CPUState::new(),
-            ppu: PPUState::new(),
+            ppu,
             controller1: Controller::new(),
             controller2: Controller::new(),
-            ram: [0; 0x2000],
+            zapper: None,
+            microphone: false,
+            error: None,
+            cheats: Vec::new(),
+            last_bus_value: 0,
+            last_access: (0, false),
+            on_last_access: false,
+            watches: Vec::new(),
+            apu_log: None,
+            watch_hit: None,
+            ram,
+            power_on,
         }
     }
 
-    /// Clears ram as well as cpu and ppu state
+    /// Clears pending interrupts, and silences the APU, keeping memory
     pub fn reset(&mut self) {
-        for byte in self.ram.iter_mut() {
-            *byte = 0;
-        }
         self.cpu = CPUState::new();
-        self.ppu = PPUState::new();
+        self.apu.write_register(0x4015, 0);
+    }
+
+    /// Brings back ram, along with cpu, ppu, and apu state, to how they
+    /// are when the console is powered on.
+    pub fn power_cycle(&mut self) {
+        let (ram, ppu) = self.power_on.memory();
+        self.ram = ram;
+        self.last_bus_value = 0;
+        self.cpu = CPUState::new();
+        self.ppu.replace(ppu);
+        self.apu.power_cycle();
+    }
+
+    /// Plugs the cart into a new mapper, as it is on power up.
+    ///
+    /// The cart keeps its SRAM and CHR RAM, but bank registers, IRQ
+    /// counters and expansion audio start over, and the mirroring goes back
+    /// to the one in the header.
+    pub fn rebuild_mapper(&mut self) {
+        // The mapper has to be moved out to take its cart, so an empty one
+        // stands in for it until the new mapper is ready
+        let empty = Box::new(nsf::NsfMapper::new(Cart::with_prg(
+            Vec::new(),
+            MapperID::Nsf,
+            None,
+        )));
+        let mut cart = core::mem::replace(&mut self.mapper, empty).into_cart();
+        cart.mirroring = cart.info().mirroring;
+        self.mapper = <dyn Mapper>::with_cart(cart);
+    }
+
+    /// Swaps the cart for another, then power cycles.
+    ///
+    /// Cheats are dropped, since they were made for the previous game.
+    pub fn insert_cart(&mut self, cart: Cart) {
+        self.mapper = <dyn Mapper>::with_cart(cart);
+        self.cheats.clear();
+        self.error = None;
+        self.power_cycle();
+    }
+
+    /// Saves ram, the state of every processor, and the mapper
+    pub fn save(&self, w: &mut StateWriter) {
+        w.bytes(&self.ram);
+        self.controller1.save(w);
+        self.controller2.save(w);
+        w.bool(self.microphone);
+        w.u8(self.last_bus_value);
+        self.apu.save(w);
+        self.cpu.save(w);
+        self.ppu.save(w);
+        self.mapper.save(w);
+    }
+
+    pub fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        r.bytes(&mut self.ram)?;
+        self.controller1.load(r)?;
+        self.controller2.load(r)?;
+        self.microphone = r.bool()?;
+        self.last_bus_value = r.u8()?;
+        self.apu.load(r)?;
+        self.cpu.load(r)?;
+        self.ppu.replace(PPUState::load(r)?);
+        self.mapper.load(r)
+    }
+
+    /// Steps the mapper forward by a CPU cycle, passing along its IRQs.
+    pub fn step_mapper(&mut self) {
+        self.mapper.step();
+        if self.mapper.irq() {
+            self.cpu.set_irq();
+        }
     }
 
+    /// Reads a value like the CPU does, keeping it as the last value on the bus.
     pub fn cpu_read(&mut self, address: u16) -> u8 {
-        match address {
+        self.last_access = (address, false);
+        let open_bus = self.last_bus_value;
+        let value = match address {
             a if a < 0x2000 => self.ram[(a % 0x800) as usize],
             a if a < 0x4000 => {
                 let adr = 0x2000 + a % 8;
-                self.ppu.read_register(&*self.mapper, adr)
+                self.ppu.read_register(&*self.mapper, adr, open_bus)
+            }
+            // Bit 5 isn't driven by the APU
+            0x4015 => self.apu.read_register(address) | (open_bus & 0x20),
+            // Controllers only drive the lowest bits
+            0x4016 => {
+                let microphone = u8::from(self.microphone) << 2;
+                self.controller1.read() | microphone | (open_bus & 0xE0)
+            }
+            0x4017 => match &self.zapper {
+                Some(zapper) => zapper.read() | (open_bus & 0xE0),
+                None => self.controller2.read() | (open_bus & 0xE0),
+            },
+            // The other APU and IO registers can only be written to
+            0x4000..=0x401F => open_bus,
+            a if a >= 0x8000 && !self.cheats.is_empty() => {
+                let original = self.mapper.read(a);
+                let cheats = self.cheats.iter();
+                cheats.fold(original, |value, cheat| cheat.apply(a, value))
             }
-            0x4014 => self.ppu.read_register(&*self.mapper, 0x4014),
-            0x4015 => self.apu.read_register(address),
-            0x4016 => self.controller1.read(),
-            0x4017 => self.controller2.read(),
             a if a >= 0x6000 => self.mapper.read(address),
-            a => {
-                panic!("Unhandled CPU read at {:X}", a);
+            a => match self.mapper.read_low(a) {
+                Some(value) => value,
+                None => {
+                    self.set_error(EmulationError::UnhandledRead(a));
+                    open_bus
+                }
+            },
+        };
+        self.last_bus_value = value;
+        if !self.watches.is_empty() {
+            self.check_watches(address, value, false);
+        }
+        value
+    }
+
+    /// Reads a sample byte for the DMC, halting the CPU meanwhile.
+    ///
+    /// The CPU can only be halted on a read, so this takes 3 cycles after
+    /// a write, and 4 otherwise. While halted, the CPU keeps repeating its
+    /// read, so a fetch landing on the cycle of a controller read clocks
+    /// the controller an extra time, losing one of its bits. The APU is
+    /// stepped after each instruction, whose last access is taken to be on
+    /// its last cycle, as with the loads games use to read controllers.
+    pub fn dmc_fetch(&mut self, address: u16) -> u8 {
+        match self.last_access {
+            (_, true) => self.cpu.add_stall(3),
+            (last, false) => {
+                self.cpu.add_stall(4);
+                match last {
+                    0x4016 if self.on_last_access => {
+                        self.controller1.read();
+                    }
+                    0x4017 if self.on_last_access && self.zapper.is_none() => {
+                        self.controller2.read();
+                    }
+                    _ => {}
+                }
             }
         }
+        self.cpu_read(address)
+    }
+
+    /// Reads a value without any of the side effects of a CPU read.
+    ///
+    /// Registers return their best guess of what the CPU would read, without
+    /// changing the state of the console, see `PPUState::peek_register`.
+    /// Controllers return their next bit, and the value last on the bus
+    /// is returned for unmapped addresses instead of reporting an error.
+    /// Neither the bus value nor the watchpoints are touched.
+    pub fn peek(&self, address: u16) -> u8 {
+        let open_bus = self.last_bus_value;
+        match address {
+            a if a < 0x2000 => self.ram[(a % 0x800) as usize],
+            a if a < 0x4000 => {
+                let adr = 0x2000 + a % 8;
+                self.ppu.peek_register(&*self.mapper, adr, open_bus)
+            }
+            0x4015 => self.apu.peek_register(address) | (open_bus & 0x20),
+            0x4016 => {
+                let microphone = u8::from(self.microphone) << 2;
+                self.controller1.peek() | microphone | (open_bus & 0xE0)
+            }
+            0x4017 => match &self.zapper {
+                Some(zapper) => zapper.read() | (open_bus & 0xE0),
+                None => self.controller2.peek() | (open_bus & 0xE0),
+            },
+            a if a >= 0x8000 => {
+                let original = self.mapper.read(a);
+                let cheats = self.cheats.iter();
+                cheats.fold(original, |value, cheat| cheat.apply(a, value))
+            }
+            a if a >= 0x6000 => self.mapper.read(a),
+            a => self.mapper.read_low(a).unwrap_or(self.last_bus_value),
+        }
     }
 
     pub fn cpu_write(&mut self, address: u16, value: u8) {
+        self.last_access = (address, true);
+        self.last_bus_value = value;
+        if !self.watches.is_empty() {
+            self.check_watches(address, value, true);
+        }
         match address {
             a if a < 0x2000 => self.ram[(a % 0x800) as usize] = value,
             a if a < 0x4000 => {
                 let adr = 0x2000 + a % 8;
                 self.ppu.write_register(&mut *self.mapper, adr, value);
             }
-            a if a < 0x4014 => self.apu.write_register(a, value),
+            a if a < 0x4014 => self.write_apu(a, value),
             0x4014 => {
                 self.ppu.write_register(&mut *self.mapper, 0x4014, value);
                 self.write_dma(value);
             }
-            0x4015 => self.apu.write_register(address, value),
+            0x4015 => self.write_apu(address, value),
             0x4016 => {
                 self.controller1.write(value);
                 self.controller2.write(value);
             }
-            0x4017 => self.apu.write_register(address, value),
+            0x4017 => self.write_apu(address, value),
             a if a >= 0x6000 => self.mapper.write(address, value),
             a => {
-                panic!("Unhandled CPU write at {:X}", a);
+                if !self.mapper.write_low(a, value) {
+                    self.set_error(EmulationError::UnhandledWrite(a));
+                }
             }
         }
     }
 
+    fn write_apu(&mut self, address: u16, value: u8) {
+        if let Some(log) = &mut self.apu_log {
+            log.push(ApuWrite {
+                cycle: self.cpu.cycles(),
+                address,
+                value,
+            });
+        }
+        self.apu.write_register(address, value);
+    }
+
+    /// Records an error, unless there's already one to report.
+    pub fn set_error(&mut self, error: EmulationError) {
+        if self.error.is_none() {
+            self.error = Some(error);
+        }
+    }
+
+    /// Records an access to a watched address, unless there's already one.
+    ///
+    /// Mirrors of RAM count as the same address.
+    fn check_watches(&mut self, address: u16, value: u8, write: bool) {
+        if self.watch_hit.is_some() {
+            return;
+        }
+        let mirrored = mirror_ram(address);
+        let hit = self.watches.iter().any(|watch| {
+            mirror_ram(watch.address) == mirrored
+                && ((write && watch.on_write) || (!write && watch.on_read))
+        });
+        if hit {
+            self.watch_hit = Some(WatchHit {
+                pc: 0,
+                address,
+                value,
+                write,
+            });
+        }
+    }
+
     fn write_dma(&mut self, value: u8) {
-        let mut address = u16::from(value) << 8;
-        // Stall for DMA
-        self.cpu.add_stall(513);
-        for _ in 0..256 {
+        let page = u16::from(value) << 8;
+        self.cpu.start_dma();
+        for offset in 0..256 {
             let oam_address = self.ppu.oam_address as usize;
-            self.ppu.oam.0[oam_address] = self.cpu_read(address);
+            self.ppu.oam.0[oam_address] = self.cpu_read(page + offset);
             self.ppu.oam_address = self.ppu.oam_address.wrapping_add(1);
-            address += 1;
         }
     }
 }
@@ -0,0 +1,114 @@
+use alloc::boxed::Box;
+
+use crate::cart::{Cart, Mirroring};
+use crate::memory::Mapper;
+use crate::nsf::{BANK_SIZE, IDLE_LOOP};
+use crate::state::{StateError, StateReader, StateWriter};
+
+/// The mapper used to play NSF music files.
+///
+/// $8000-$FFFF is split in 8 banks of 4KB, selected by writing to
+/// $5FF8-$5FFF, with 8KB of RAM at $6000-$7FFF. A `JMP` to itself is
+/// served at `IDLE_LOOP`, for the CPU to wait in between routines.
+///
+/// More info: https://wiki.nesdev.com/w/index.php/NSF#Bankswitching
+pub struct NsfMapper {
+    cart: Cart,
+    banks: [u8; 8],
+}
+
+impl NsfMapper {
+    pub fn new(cart: Cart) -> Self {
+        NsfMapper {
+            cart,
+            banks: [0, 1, 2, 3, 4, 5, 6, 7],
+        }
+    }
+}
+
+impl Mapper for NsfMapper {
+    fn read(&self, address: u16) -> u8 {
+        match address {
+            a if a < 0x2000 => self.cart.chr[a as usize],
+            a if a >= 0x8000 => {
+                let shifted = (a - 0x8000) as usize;
+                let bank = usize::from(self.banks[shifted / BANK_SIZE]);
+                let index = bank * BANK_SIZE + shifted % BANK_SIZE;
+                self.cart.prg[index % self.cart.prg.len()]
+            }
+            a if a >= 0x6000 => {
+                let shifted = (address - 0x6000) as usize;
+                self.cart.sram[shifted]
+            }
+            a => {
+                panic!("NsfMapper unhandled read at {:X}", a);
+            }
+        }
+    }
+
+    fn mirroring_mode(&self) -> Mirroring {
+        self.cart.mirroring
+    }
+
+    fn cart(&self) -> &Cart {
+        &self.cart
+    }
+
+    fn cart_mut(&mut self) -> &mut Cart {
+        &mut self.cart
+    }
+
+    fn into_cart(self: Box<Self>) -> Cart {
+        self.cart
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        match address {
+            a if a < 0x2000 => self.cart.write_chr(a as usize, value),
+            // The data is in ROM
+            a if a >= 0x8000 => {}
+            a if a >= 0x6000 => {
+                let shifted = (address - 0x6000) as usize;
+                self.cart.sram[shifted] = value;
+            }
+            a => {
+                panic!("NsfMapper unhandled write at {:X}", a);
+            }
+        }
+    }
+
+    fn read_low(&self, address: u16) -> Option<u8> {
+        let [lo, hi] = IDLE_LOOP.to_le_bytes();
+        match address.wrapping_sub(IDLE_LOOP) {
+            0 => Some(0x4C),
+            1 => Some(lo),
+            2 => Some(hi),
+            _ => None,
+        }
+    }
+
+    fn write_low(&mut self, address: u16, value: u8) -> bool {
+        match address {
+            0x5FF8..=0x5FFF => {
+                self.banks[usize::from(address - 0x5FF8)] = value;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn save(&self, w: &mut StateWriter) {
+        self.cart.save(w);
+        for &bank in &self.banks {
+            w.u8(bank);
+        }
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.cart.load(r)?;
+        for bank in self.banks.iter_mut() {
+            *bank = r.u8()?;
+        }
+        Ok(())
+    }
+}
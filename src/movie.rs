@@ -0,0 +1,236 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::controller::ButtonState;
+
+/// Represents the possible errors when reading a movie.
+///
+/// Errors about a line contain its number, starting at 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MovieError {
+    /// An input line didn't have the commands and ports expected
+    BadInput(usize),
+    /// The ROM checksum in the header couldn't be decoded
+    BadChecksum(usize),
+    /// The movie was recorded with a different ROM than the one playing
+    WrongRom,
+}
+
+/// Represents the input recorded for a single frame of a movie.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MovieFrame {
+    /// The buttons held on the first controller
+    pub player1: ButtonState,
+    /// The buttons held on the second controller
+    pub player2: ButtonState,
+    /// Whether the console is reset at the start of this frame
+    pub reset: bool,
+    /// Whether the console is power cycled at the start of this frame
+    pub power: bool,
+}
+
+/// The characters used by base64, for each value of 6 bits
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encodes bytes as base64, padded with `=`
+fn encode_base64(bytes: &[u8]) -> String {
+    let mut text = String::new();
+    for chunk in bytes.chunks(3) {
+        let mut group = [0; 3];
+        group[..chunk.len()].copy_from_slice(chunk);
+        let bits = u32::from_be_bytes([0, group[0], group[1], group[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                let value = (bits >> (18 - 6 * i)) & 0x3F;
+                text.push(char::from(BASE64[value as usize]));
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+/// Decodes a string of base64, returning None if it isn't valid
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut bits = 0u32;
+    let mut count = 0;
+    for c in text.trim_end_matches('=').bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        bits = (bits << 6) | u32::from(value);
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            bytes.push((bits >> count) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+/// Writes the buttons of a gamepad, in the order read by `parse_gamepad`
+fn write_gamepad(text: &mut String, buttons: ButtonState) {
+    let held = [
+        (buttons.right, 'R'),
+        (buttons.left, 'L'),
+        (buttons.down, 'D'),
+        (buttons.up, 'U'),
+        (buttons.start, 'T'),
+        (buttons.select, 'S'),
+        (buttons.b, 'B'),
+        (buttons.a, 'A'),
+    ];
+    for (pressed, name) in held {
+        text.push(if pressed { name } else { '.' });
+    }
+}
+
+/// Reads the buttons of a gamepad, written as `RLDUTSBA`.
+///
+/// Released buttons are written as `.` or a space, and an empty field
+/// means nothing is plugged in.
+fn parse_gamepad(field: &str) -> Option<ButtonState> {
+    if field.is_empty() {
+        return Some(ButtonState::default());
+    }
+    let pressed: Vec<bool> = field.chars().map(|c| c != '.' && c != ' ').collect();
+    match pressed[..] {
+        [right, left, down, up, start, select, b, a] => Some(ButtonState {
+            a,
+            b,
+            select,
+            start,
+            up,
+            down,
+            left,
+            right,
+        }),
+        _ => None,
+    }
+}
+
+/// Represents a movie in the `.fm2` format used by FCEUX.
+///
+/// These start with a header of `key value` lines, followed by a line
+/// for each frame, like:
+///
+/// ```txt
+/// version 3
+/// romChecksum base64:kYCw4TVqV9aGaoHvAGJlbA==
+/// palFlag 0
+/// |0|....T...|........||
+/// |0|R......A|........||
+/// ```
+///
+/// Each frame holds the commands, with 1 for a reset and 2 for a power
+/// cycle, followed by the gamepad on each port. Only gamepads are supported,
+/// and movies have to start from power on, instead of from a saved state.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Movie {
+    checksum: Option<[u8; 16]>,
+    pal: bool,
+    frames: Vec<MovieFrame>,
+}
+
+impl Movie {
+    /// Creates a movie from the input of each frame.
+    ///
+    /// The checksum is the MD5 digest of the ROM, as given by `Cart::md5`.
+    pub fn new(checksum: Option<[u8; 16]>, pal: bool, frames: Vec<MovieFrame>) -> Self {
+        Movie {
+            checksum,
+            pal,
+            frames,
+        }
+    }
+
+    /// Parses the text of a movie
+    pub fn parse(text: &str) -> Result<Self, MovieError> {
+        let mut movie = Movie::default();
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            if let Some(input) = line.strip_prefix('|') {
+                let frame = Movie::parse_frame(input).ok_or(MovieError::BadInput(line_number))?;
+                movie.frames.push(frame);
+                continue;
+            }
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            match key {
+                "romChecksum" => {
+                    let bad = MovieError::BadChecksum(line_number);
+                    let encoded = value.trim().strip_prefix("base64:").ok_or(bad)?;
+                    let digest = decode_base64(encoded).ok_or(bad)?;
+                    movie.checksum = Some(digest.try_into().map_err(|_| bad)?);
+                }
+                "palFlag" => movie.pal = value.trim() == "1",
+                _ => {}
+            }
+        }
+        Ok(movie)
+    }
+
+    /// Parses an input line, after the first `|`
+    fn parse_frame(input: &str) -> Option<MovieFrame> {
+        let mut fields = input.split('|');
+        let commands: u8 = fields.next()?.parse().ok()?;
+        let player1 = parse_gamepad(fields.next()?)?;
+        let player2 = parse_gamepad(fields.next()?)?;
+        Some(MovieFrame {
+            player1,
+            player2,
+            reset: commands & 1 != 0,
+            power: commands & 2 != 0,
+        })
+    }
+
+    /// Writes the movie in the `.fm2` format, with gamepads on both ports.
+    ///
+    /// This can be read back by `parse`, or played by FCEUX.
+    pub fn to_fm2(&self) -> String {
+        let mut text = String::from("version 3\n");
+        if let Some(checksum) = self.checksum {
+            text.push_str("romChecksum base64:");
+            text.push_str(&encode_base64(&checksum));
+            text.push('\n');
+        }
+        text.push_str(if self.pal {
+            "palFlag 1\n"
+        } else {
+            "palFlag 0\n"
+        });
+        text.push_str("port0 1\nport1 1\nport2 0\n");
+        for frame in &self.frames {
+            let commands = u8::from(frame.reset) | (u8::from(frame.power) << 1);
+            text.push('|');
+            text.push(char::from(b'0' + commands));
+            text.push('|');
+            write_gamepad(&mut text, frame.player1);
+            text.push('|');
+            write_gamepad(&mut text, frame.player2);
+            text.push_str("||\n");
+        }
+        text
+    }
+
+    /// Returns the MD5 digest of the ROM the movie was recorded with, if known
+    pub fn checksum(&self) -> Option<[u8; 16]> {
+        self.checksum
+    }
+
+    /// Returns true if the movie was recorded on a PAL console
+    pub fn pal(&self) -> bool {
+        self.pal
+    }
+
+    /// Returns the input recorded for each frame, in order
+    pub fn frames(&self) -> &[MovieFrame] {
+        &self.frames
+    }
+}
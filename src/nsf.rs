@@ -0,0 +1,204 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::cart::{Cart, MapperID};
+use crate::region::Region;
+
+/// The address of the loop the CPU idles in between calls to INIT and PLAY.
+///
+/// Nothing is mapped there on a console, so the NSF mapper serves a
+/// `JMP` to itself, and routines return to it with their `RTS`.
+pub(crate) const IDLE_LOOP: u16 = 0x4100;
+
+/// The size of an NSF bank, mapped at each 4KB of $8000-$FFFF
+pub(crate) const BANK_SIZE: usize = 0x1000;
+
+/// The PLAY period used when the header leaves it at 0, in micro seconds
+const NTSC_SPEED: u16 = 16639;
+const PAL_SPEED: u16 = 19997;
+
+/// Represents the possible errors when reading an NSF file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NsfError {
+    /// The file doesn't start with an NSF header, or is cut short
+    UnrecognisedFormat,
+    /// The data is loaded below $8000, which isn't supported without bank switching
+    BadLoadAddress(u16),
+    /// The file doesn't have any songs
+    NoSongs,
+}
+
+/// Reads a string padded with zeros in the header
+fn read_string(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).trim().into()
+}
+
+/// Represents an NSF music file, ripped from the code and data of a game.
+///
+/// Instead of running a game, the console calls INIT once to start a song,
+/// and PLAY at the rate given in the header, usually once per frame.
+/// Expansion audio chips aren't supported, so songs using them are missing
+/// those channels.
+///
+/// See: https://wiki.nesdev.com/w/index.php/NSF
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Nsf {
+    /// The name of the song, or of the game
+    pub title: String,
+    /// The composer of the music
+    pub artist: String,
+    /// The holder of the copyright, usually with its year
+    pub copyright: String,
+    /// The number of songs, or tracks, in the file
+    pub song_count: u8,
+    /// The song to play first, starting at 1
+    pub starting_song: u8,
+    /// Where the data is loaded, in CPU memory
+    pub load_address: u16,
+    /// The routine starting a song, with its number in A and the region in X
+    pub init_address: u16,
+    /// The routine called on every tick of the song
+    pub play_address: u16,
+    /// The period between calls to PLAY on NTSC, in micro seconds
+    pub ntsc_speed: u16,
+    /// The period between calls to PLAY on PAL, in micro seconds
+    pub pal_speed: u16,
+    /// The 4KB bank mapped at each 4KB of $8000-$FFFF, if bank switching is used
+    pub bankswitch: Option<[u8; 8]>,
+    /// Whether or not the music is made for PAL consoles
+    pub pal: bool,
+    /// Whether or not the music plays on both regions
+    pub dual_region: bool,
+    /// The bits of the expansion audio chips used, which aren't emulated
+    pub expansion: u8,
+    data: Vec<u8>,
+}
+
+impl Nsf {
+    /// Reads an NSF file, including its header
+    pub fn from_bytes(buffer: &[u8]) -> Result<Nsf, NsfError> {
+        if buffer.len() < 0x80 || &buffer[0..5] != b"NESM\x1A" {
+            return Err(NsfError::UnrecognisedFormat);
+        }
+        let word = |i: usize| u16::from_le_bytes([buffer[i], buffer[i + 1]]);
+        let mut banks = [0; 8];
+        banks.copy_from_slice(&buffer[0x70..0x78]);
+        let bankswitch = if banks.iter().any(|&b| b != 0) {
+            Some(banks)
+        } else {
+            None
+        };
+        let load_address = word(0x08);
+        if bankswitch.is_none() && load_address < 0x8000 {
+            return Err(NsfError::BadLoadAddress(load_address));
+        }
+        if buffer[6] == 0 {
+            return Err(NsfError::NoSongs);
+        }
+        Ok(Nsf {
+            title: read_string(&buffer[0x0E..0x2E]),
+            artist: read_string(&buffer[0x2E..0x4E]),
+            copyright: read_string(&buffer[0x4E..0x6E]),
+            song_count: buffer[6],
+            starting_song: buffer[7].clamp(1, buffer[6]),
+            load_address,
+            init_address: word(0x0A),
+            play_address: word(0x0C),
+            ntsc_speed: word(0x6E),
+            pal_speed: word(0x78),
+            bankswitch,
+            pal: buffer[0x7A] & 1 != 0,
+            dual_region: buffer[0x7A] & 2 != 0,
+            expansion: buffer[0x7B],
+            data: buffer[0x80..].to_vec(),
+        })
+    }
+
+    /// Returns the code and data of the music, loaded at `load_address`
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Returns the region the music is meant to play on
+    pub fn region(&self) -> Region {
+        if self.pal && !self.dual_region {
+            Region::PAL
+        } else {
+            Region::NTSC
+        }
+    }
+
+    /// Lays out the data in 4KB banks, as a cart for the NSF mapper.
+    ///
+    /// With bank switching, the data starts at the offset of the load
+    /// address within its bank. Otherwise, it starts at the load address,
+    /// with the banks mapped in order.
+    pub(crate) fn cart(&self) -> Cart {
+        let padding = match self.bankswitch {
+            Some(_) => usize::from(self.load_address) % BANK_SIZE,
+            None => usize::from(self.load_address - 0x8000),
+        };
+        let mut prg = vec![0; padding];
+        prg.extend_from_slice(&self.data);
+        let banks = prg.len().div_ceil(BANK_SIZE).max(1);
+        prg.resize(banks * BANK_SIZE, 0);
+        let title = Some(self.title.clone()).filter(|title| !title.is_empty());
+        Cart::with_prg(prg, MapperID::Nsf, title)
+    }
+}
+
+/// Keeps track of the song being played from an NSF, and when to call PLAY
+pub(crate) struct NsfPlayer {
+    init: u16,
+    pub play: u16,
+    pub song_count: u8,
+    /// The song being played, starting at 0
+    pub track: u8,
+    banks: [u8; 8],
+    ntsc_speed: u16,
+    pal_speed: u16,
+    /// The time left until PLAY is due, in micro seconds times the CPU frequency
+    countdown: i64,
+}
+
+impl NsfPlayer {
+    pub fn new(nsf: &Nsf) -> Self {
+        let speed = |speed, default| if speed == 0 { default } else { speed };
+        NsfPlayer {
+            init: nsf.init_address,
+            play: nsf.play_address,
+            song_count: nsf.song_count,
+            track: nsf.starting_song - 1,
+            banks: nsf.bankswitch.unwrap_or([0, 1, 2, 3, 4, 5, 6, 7]),
+            ntsc_speed: speed(nsf.ntsc_speed, NTSC_SPEED),
+            pal_speed: speed(nsf.pal_speed, PAL_SPEED),
+            countdown: 0,
+        }
+    }
+
+    /// Returns the address of INIT, along with the banks to map before calling it
+    pub fn start(&mut self, track: u8, region: Region) -> (u16, [u8; 8]) {
+        self.track = track % self.song_count;
+        self.countdown = self.period(region);
+        (self.init, self.banks)
+    }
+
+    fn period(&self, region: Region) -> i64 {
+        let speed = match region {
+            Region::NTSC => self.ntsc_speed,
+            Region::PAL => self.pal_speed,
+        };
+        i64::from(speed) * i64::from(region.cpu_frequency())
+    }
+
+    /// Advances the timer by some CPU cycles, returning true if PLAY is due
+    pub fn tick(&mut self, cycles: i32, region: Region) -> bool {
+        self.countdown -= i64::from(cycles) * 1_000_000;
+        if self.countdown > 0 {
+            return false;
+        }
+        self.countdown += self.period(region);
+        true
+    }
+}
@@ -0,0 +1,68 @@
+use alloc::vec::Vec;
+
+use crate::ppu::PALETTE;
+
+/// Represents the possible errors when reading a palette file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PaletteError {
+    /// The file had neither 64 nor 512 colors, containing this many bytes
+    BadLength(usize),
+}
+
+/// Represents the colors the PPU outputs for each of its 64 color indices.
+///
+/// Palettes can also contain colors for every combination of the emphasis
+/// bits, in which case those are used instead of darkening colors.
+/// The default palette is the one built into the crate.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Palette {
+    /// Either 64 colors, or 512 colors, with 64 for each emphasis
+    colors: Vec<u32>,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::from_colors(PALETTE)
+    }
+}
+
+impl Palette {
+    /// Creates a palette from 64 colors, in ARGB format.
+    pub fn from_colors(colors: [u32; 64]) -> Self {
+        Palette {
+            colors: colors.to_vec(),
+        }
+    }
+
+    /// Reads a palette from the contents of a `.pal` file.
+    ///
+    /// These files contain RGB triples, either 192 bytes for 64 colors, or
+    /// 1536 bytes for 512 colors. The larger files contain the 64 colors
+    /// for each combination of the emphasis bits, in the order of their
+    /// value in PPUMASK, with red as the lowest bit.
+    pub fn from_pal(bytes: &[u8]) -> Result<Self, PaletteError> {
+        if bytes.len() != 64 * 3 && bytes.len() != 512 * 3 {
+            return Err(PaletteError::BadLength(bytes.len()));
+        }
+        let colors = bytes
+            .chunks(3)
+            .map(|rgb| 0xFF00_0000 | u32::from_be_bytes([0, rgb[0], rgb[1], rgb[2]]))
+            .collect();
+        Ok(Palette { colors })
+    }
+
+    /// Returns true if the palette has colors for each emphasis
+    pub(crate) fn has_emphasis(&self) -> bool {
+        self.colors.len() > 64
+    }
+
+    /// Returns the color for an index, using the emphasis bits if the palette has them
+    pub(crate) fn color(&self, index: u8, emphasis: u8) -> u32 {
+        let offset = if self.has_emphasis() {
+            usize::from(emphasis) * 64
+        } else {
+            0
+        };
+        self.colors[offset + usize::from(index % 64)]
+    }
+}
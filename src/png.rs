@@ -0,0 +1,77 @@
+use alloc::vec::Vec;
+
+use crate::hash::{adler32, crc32};
+use crate::ports::{Overscan, PixelBuffer};
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+/// The most bytes a stored deflate block can hold
+const MAX_BLOCK: usize = 0xFFFF;
+
+/// Writes a chunk, with its length and checksum
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Wraps data in a zlib stream, using stored blocks without compression
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let blocks = data.len() / MAX_BLOCK + 1;
+    let mut stream = Vec::with_capacity(data.len() + blocks * 5 + 6);
+    // Deflate with a 32KB window, and no preset dictionary
+    stream.extend_from_slice(&[0x78, 0x01]);
+    let mut chunks = data.chunks(MAX_BLOCK).peekable();
+    if chunks.peek().is_none() {
+        stream.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(chunk) = chunks.next() {
+        let last = chunks.peek().is_none();
+        stream.push(u8::from(last));
+        let len = chunk.len() as u16;
+        stream.extend_from_slice(&len.to_le_bytes());
+        stream.extend_from_slice(&(!len).to_le_bytes());
+        stream.extend_from_slice(chunk);
+    }
+    stream.extend_from_slice(&adler32(data).to_be_bytes());
+    stream
+}
+
+/// Encodes a frame as a PNG image, with 8 bit RGB colors, for screenshots.
+///
+/// The edges given by `overscan` are cropped off, like for the video
+/// device, so screenshots match what's on screen. Use `Overscan::none()`
+/// to keep the whole 256x240 frame.
+///
+/// The image isn't compressed, which keeps the encoder small, at the cost
+/// of files around 180KB. Since this crate doesn't have access to the
+/// standard library, writing the file is left to the application.
+pub fn encode_png(pixels: &PixelBuffer, overscan: Overscan) -> Vec<u8> {
+    let (width, height) = (overscan.width(), overscan.height());
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // 8 bits per channel, RGB, default compression and filters, no interlacing
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    let mut cropped = Vec::with_capacity(width * height);
+    overscan.crop_into(pixels, &mut cropped);
+    let mut image = Vec::with_capacity(height * (width * 3 + 1));
+    for row in cropped.chunks(width.max(1)) {
+        // Each line starts with its filter, which is none here
+        image.push(0);
+        for &argb in row {
+            image.extend_from_slice(&argb.to_be_bytes()[1..]);
+        }
+    }
+
+    let mut png = Vec::new();
+    png.extend_from_slice(&SIGNATURE);
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&image));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
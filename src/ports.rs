@@ -1,3 +1,7 @@
+use alloc::vec::Vec;
+
+use crate::ppu::PALETTE;
+
 /// This represents an audio device we can push samples to.
 ///
 /// The APU will dump its samples into an object implementing
@@ -6,11 +10,51 @@ pub trait AudioDevice {
     fn push_sample(&mut self, sample: f32);
 }
 
+/// An audio device throwing away every sample.
+///
+/// This is useful to run the console headlessly, when only video matters.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NullAudio;
+
+impl AudioDevice for NullAudio {
+    fn push_sample(&mut self, _sample: f32) {}
+}
+
+/// An audio device collecting every sample into a buffer.
+///
+/// This is useful in tests, or to write audio to a file. The samples
+/// keep accumulating until they're taken out of the buffer.
+#[derive(Clone, Debug, Default)]
+pub struct BufferedAudio {
+    /// The samples pushed so far, from oldest to newest
+    pub samples: Vec<f32>,
+}
+
+impl AudioDevice for BufferedAudio {
+    fn push_sample(&mut self, sample: f32) {
+        self.samples.push(sample);
+    }
+}
+
+/// A video device throwing away every frame.
+///
+/// Frames can still be read back with `Console::capture_frame`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NullVideo;
+
+impl VideoDevice for NullVideo {
+    fn blit_pixels(&mut self, _pixels: &PixelBuffer) {}
+}
+
 /// This represents the width of the display in pixels
 pub const NES_WIDTH: usize = 256;
 /// This represents the height of the display in pixels
 pub const NES_HEIGHT: usize = 240;
 const BUFFER_PIXELS: usize = NES_WIDTH * NES_HEIGHT;
+/// The width of the four nametables drawn by `Console::render_nametables`
+pub const NAMETABLES_WIDTH: usize = NES_WIDTH * 2;
+/// The height of the four nametables drawn by `Console::render_nametables`
+pub const NAMETABLES_HEIGHT: usize = NES_HEIGHT * 2;
 
 /// Represents a buffer of pixels the PPU writes to.
 ///
@@ -29,6 +73,17 @@ impl Default for PixelBuffer {
     }
 }
 
+impl Clone for PixelBuffer {
+    fn clone(&self) -> Self {
+        PixelBuffer(self.0)
+    }
+
+    /// This copies the pixels in place, without using the stack.
+    fn clone_from(&mut self, source: &Self) {
+        self.0.copy_from_slice(&source.0);
+    }
+}
+
 impl AsRef<[u32]> for PixelBuffer {
     /// This will return the pixels row by row, in ARGB (big endian) format.
     fn as_ref(&self) -> &[u32] {
@@ -41,14 +96,146 @@ impl PixelBuffer {
         let index = NES_WIDTH * y + x;
         self.0[index] = argb;
     }
+
+    pub(crate) fn pixels_mut(&mut self) -> &mut [u32] {
+        &mut self.0
+    }
+}
+
+/// How many pixels to cut off each edge of a frame, hiding the overscan.
+///
+/// TVs didn't show the edges of the picture, so many games leave garbage
+/// there, like the tiles being scrolled in. The default crops 8 pixels
+/// from every edge, leaving 240x224 pixels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Overscan {
+    /// The number of pixel rows cut off the top of the frame
+    pub top: usize,
+    /// The number of pixel rows cut off the bottom of the frame
+    pub bottom: usize,
+    /// The number of pixel columns cut off the left of the frame
+    pub left: usize,
+    /// The number of pixel columns cut off the right of the frame
+    pub right: usize,
+}
+
+impl Default for Overscan {
+    fn default() -> Self {
+        Overscan {
+            top: 8,
+            bottom: 8,
+            left: 8,
+            right: 8,
+        }
+    }
+}
+
+impl Overscan {
+    /// Keeps the whole frame, without cropping anything
+    pub fn none() -> Self {
+        Overscan {
+            top: 0,
+            bottom: 0,
+            left: 0,
+            right: 0,
+        }
+    }
+
+    /// Returns the width of cropped frames, to size windows with
+    pub fn width(&self) -> usize {
+        NES_WIDTH.saturating_sub(self.left + self.right)
+    }
+
+    /// Returns the height of cropped frames, to size windows with
+    pub fn height(&self) -> usize {
+        NES_HEIGHT.saturating_sub(self.top + self.bottom)
+    }
+
+    /// Copies the visible part of a frame, row by row, replacing `out`.
+    ///
+    /// This is meant to be called from a `VideoDevice`, reusing the same
+    /// buffer every frame, which ends up with `width() * height()` pixels.
+    pub fn crop_into(&self, pixels: &PixelBuffer, out: &mut Vec<u32>) {
+        out.clear();
+        let left = self.left.min(NES_WIDTH);
+        let (width, height) = (self.width(), self.height());
+        for row in pixels.0.chunks(NES_WIDTH).skip(self.top).take(height) {
+            out.extend_from_slice(&row[left..left + width]);
+        }
+    }
 }
 
 /// This represents a video device we can write a pixel buffer to.
-/// 
+///
 /// When implementing this trait, the device should be scaled to a factor
 /// of NES_WIDTH * NES_HEIGHT, and be able to accept a pixel buffer of those
 /// dimensions.
 pub trait VideoDevice {
     /// Transfer a buffer of pixels onto this device.
     fn blit_pixels(&mut self, pixels: &PixelBuffer);
+
+    /// Called once a visible scanline is done, with its `NES_WIDTH` pixels.
+    ///
+    /// This happens 240 times per frame, from line 0 to 239, before the
+    /// frame is blitted. Devices can use it to look at the frame while it's
+    /// being drawn, like for raster effects changing things mid frame.
+    /// By default, this does nothing.
+    fn scanline_complete(&mut self, _line: u16, _pixels: &[u32]) {}
+}
+
+/// The different kinds of images `test_pattern` can generate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TestPattern {
+    /// 8 vertical bars, from left to right: white, yellow, cyan, green,
+    /// magenta, red, blue, and black.
+    ColorBars,
+    /// 4 horizontal bands, from top to bottom: red, green, blue, and gray.
+    /// Each band goes from black on the left to full intensity on the right.
+    Gradient,
+    /// The 64 colors of the NES palette, in a grid of 16 columns and 4 rows.
+    /// Color 0x00 is in the top left, and 0x3F in the bottom right.
+    PaletteGrid,
+}
+
+const COLOR_BARS: [u32; 8] = [
+    0xFFFF_FFFF,
+    0xFFFF_FF00,
+    0xFF00_FFFF,
+    0xFF00_FF00,
+    0xFFFF_00FF,
+    0xFFFF_0000,
+    0xFF00_00FF,
+    0xFF00_0000,
+];
+
+/// Generate a test image, independently of any console.
+///
+/// This is useful when writing a new `VideoDevice`, to check that colors,
+/// byte order, and scaling come out as expected before running a game.
+pub fn test_pattern(kind: TestPattern) -> PixelBuffer {
+    let mut buffer = PixelBuffer::default();
+    for y in 0..NES_HEIGHT {
+        for x in 0..NES_WIDTH {
+            let argb = match kind {
+                TestPattern::ColorBars => COLOR_BARS[x / (NES_WIDTH / 8)],
+                TestPattern::Gradient => {
+                    let intensity = x as u32;
+                    let mask = match y / (NES_HEIGHT / 4) {
+                        0 => 0xFF_0000,
+                        1 => 0x00_FF00,
+                        2 => 0x00_00FF,
+                        _ => 0xFF_FFFF,
+                    };
+                    0xFF00_0000 | ((intensity * 0x01_0101) & mask)
+                }
+                TestPattern::PaletteGrid => {
+                    let column = x / (NES_WIDTH / 16);
+                    let row = y / (NES_HEIGHT / 4);
+                    PALETTE[row * 16 + column]
+                }
+            };
+            buffer.write(x, y, argb);
+        }
+    }
+    buffer
 }
@@ -1,10 +1,14 @@
 use alloc::boxed::Box;
+use alloc::vec::Vec;
 
 use super::memory::{Mapper, MemoryBus};
 
-use crate::ports::{PixelBuffer, VideoDevice};
+use crate::palette::Palette;
+use crate::ports::{PixelBuffer, VideoDevice, NAMETABLES_WIDTH, NES_HEIGHT, NES_WIDTH};
+use crate::region::Region;
+use crate::state::{StateError, StateReader, StateWriter};
 
-const PALETTE: [u32; 64] = [
+pub(crate) const PALETTE: [u32; 64] = [
     0xFF75_7575,
     0xFF27_1B8F,
     0xFF00_00AB,
@@ -71,21 +75,34 @@ const PALETTE: [u32; 64] = [
     0xFF00_0000,
 ];
 
-struct NameTables([u8; 2048]);
+/// The contents of palette ram when the console is powered on.
+///
+/// These are the values read back by blargg's power_up_palette test on
+/// real hardware, and are what most emulators use.
+const POWER_UP_PALETTE: [u8; 32] = [
+    0x09, 0x01, 0x00, 0x01, 0x00, 0x02, 0x02, 0x0D, 0x08, 0x10, 0x08, 0x24, 0x00, 0x00, 0x04, 0x2C,
+    0x09, 0x01, 0x34, 0x03, 0x00, 0x04, 0x00, 0x14, 0x08, 0x3A, 0x00, 0x02, 0x00, 0x20, 0x2C, 0x08,
+];
+
+/// The RAM holding nametables.
+///
+/// The console only has room for 2 tables, but carts using four screen
+/// mirroring add enough RAM for all 4, which is included here.
+struct NameTables([u8; 4096]);
 
 impl Default for NameTables {
     fn default() -> Self {
-        NameTables([0; 2048])
+        NameTables([0; 4096])
     }
 }
 
 impl NameTables {
     fn read(&self, addr: u16) -> u8 {
-        self.0[(addr % 2048) as usize]
+        self.0[(addr % 4096) as usize]
     }
 
     fn write(&mut self, addr: u16, val: u8) {
-        self.0[(addr % 2048) as usize] = val;
+        self.0[(addr % 4096) as usize] = val;
     }
 }
 
@@ -97,9 +114,113 @@ impl Default for OAM {
     }
 }
 
+/// Applies the color emphasis bits of PPUMASK to a color.
+///
+/// Emphasizing a channel darkens the two other channels, so emphasizing
+/// every channel darkens the whole color.
+/// See: https://wiki.nesdev.com/w/index.php/NTSC_video#Color_Tint_Bits
+fn emphasize(argb: u32, red: bool, green: bool, blue: bool) -> u32 {
+    // The shift of each channel, along with whether or not it gets darkened
+    let channels = [(16, green || blue), (8, red || blue), (0, red || green)];
+    let mut result = argb;
+    for &(shift, darkened) in &channels {
+        if darkened {
+            // Darkened channels keep about 74.6% of their brightness
+            let value = (argb >> shift) & 0xFF;
+            result = (result & !(0xFF << shift)) | ((value * 746 / 1000) << shift);
+        }
+    }
+    result
+}
+
+/// Represents a sprite found on a scanline.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SpriteEntry {
+    /// The index of the sprite in OAM, between 0 and 63
+    pub index: u8,
+    /// The Y position of the sprite, as stored in OAM
+    pub y: u8,
+    /// The index of the tile the sprite uses
+    pub tile: u8,
+    /// The attribute byte of the sprite
+    pub attributes: u8,
+    /// The X position of the sprite
+    pub x: u8,
+    /// The row of the sprite displayed on the scanline, before flipping
+    pub row: u8,
+    /// Whether or not the sprite is among the 8 the PPU draws on this line
+    pub drawn: bool,
+}
+
+/// Represents a sprite in OAM, with its attributes decoded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SpriteInfo {
+    /// The Y position of the sprite, as stored in OAM
+    pub y: u8,
+    /// The index of the tile the sprite uses
+    pub tile: u8,
+    /// The sprite palette used, between 0 and 3
+    pub palette: u8,
+    /// Whether or not the sprite is drawn behind the background
+    pub behind_background: bool,
+    /// Whether or not the sprite is flipped horizontally
+    pub flip_horizontal: bool,
+    /// Whether or not the sprite is flipped vertically
+    pub flip_vertical: bool,
+    /// The X position of the sprite
+    pub x: u8,
+    /// The height of the sprite in pixels, 8 or 16 depending on the sprite size
+    pub height: u8,
+}
+
+impl SpriteInfo {
+    fn from_oam(bytes: &[u8], height: u8) -> Self {
+        let attributes = bytes[2];
+        SpriteInfo {
+            y: bytes[0],
+            tile: bytes[1],
+            palette: attributes & 3,
+            behind_background: attributes & 0x20 != 0,
+            flip_horizontal: attributes & 0x40 != 0,
+            flip_vertical: attributes & 0x80 != 0,
+            x: bytes[3],
+            height,
+        }
+    }
+
+    /// Returns true if the sprite is displayed on a scanline.
+    ///
+    /// Like with `Console::sprites_on_scanline`, sprites appear one line
+    /// below their Y position, and being in range doesn't mean the sprite
+    /// is among the 8 the PPU draws.
+    pub fn on_scanline(&self, line: i32) -> bool {
+        let row = line - 1 - i32::from(self.y);
+        (0..i32::from(self.height)).contains(&row)
+    }
+}
+
+/// How many dots it takes for a rising NMI to reach the CPU.
+///
+/// Reading PPUSTATUS on the dot before the vblank flag is set keeps it clear
+/// for the whole frame, with no NMI. Reading it on the dot the flag is set,
+/// or the next one, sees the flag but still suppresses the NMI, which is
+/// only seen by the CPU after that. This makes a window of 3 dots.
+/// Since the PPU catches up after each instruction, the position seen by
+/// a read is the one at the end of the previous instruction.
+/// See: https://wiki.nesdev.com/w/index.php/PPU_frame_timing#VBL_Flag_Timing
+const NMI_DELAY: u8 = 2;
+
 /// Represents openly modifiable PPU state
 #[derive(Default)]
 pub struct PPUState {
+    /// The colors output for each color index.
+    ///
+    /// This is a setting rather than state, so it isn't saved.
+    pub colors: Palette,
+    /// Whether the red and green emphasis bits are swapped, like on PAL.
+    ///
+    /// This comes from the region, so it isn't saved either.
+    pub swap_emphasis: bool,
     // Memory
     palettes: [u8; 32],
     nametables: NameTables,
@@ -112,16 +233,21 @@ pub struct PPUState {
     w: u8,
     /// Fine x scroll (3 bit)
     x: u8,
-    register: u8,
     // Nmi flags
     nmi_occurred: bool,
     nmi_output: bool,
     nmi_previous: bool,
+    /// The dots left before a rising NMI reaches the CPU
     nmi_delay: u8,
+    /// Whether or not the next dot sets the vblank flag
+    vblank_next: bool,
+    /// Whether or not the vblank flag is kept clear for this frame, because
+    /// PPUSTATUS was read on the dot before it would have been set
+    vblank_suppressed: bool,
 
     // $2000 PPUCTRL
     // 0: $2000, 1: $2400, 2: $2800, 3: $2C00
-    flg_nametable: u8,
+    pub flg_nametable: u8,
     // 0: add 1, 1: add 32
     pub flg_increment: u8, // Pub for access during Bus IO
     // 0: $0000, 1: $1000
@@ -167,14 +293,251 @@ impl PPUState {
         PPUState::default()
     }
 
+    /// Fills palette ram and OAM with the values they have on power up.
+    ///
+    /// OAM is semi-random on hardware, so we fill it with 0xFF instead,
+    /// which places every sprite below the visible part of the screen.
+    pub fn power_up(&mut self) {
+        self.palettes = POWER_UP_PALETTE;
+        self.oam = OAM([0xFF; 256]);
+    }
+
+    /// Fills palette ram and OAM using a function filling a slice of bytes
+    pub fn fill_memory(&mut self, mut fill: impl FnMut(&mut [u8])) {
+        fill(&mut self.palettes);
+        // Palette ram only has 6 bits per entry
+        for entry in self.palettes.iter_mut() {
+            *entry &= 0x3F;
+        }
+        fill(&mut self.oam.0);
+    }
+
     fn nmi_change(&mut self) {
         let nmi = self.nmi_output && self.nmi_occurred;
         if nmi && !self.nmi_previous {
-            self.nmi_delay = 15;
+            self.nmi_delay = NMI_DELAY;
         }
         self.nmi_previous = nmi;
     }
 
+    /// Writes every field, in the order `load` reads them back
+    pub fn save(&self, w: &mut StateWriter) {
+        w.bytes(&self.palettes);
+        w.bytes(&self.nametables.0);
+        w.bytes(&self.oam.0);
+        w.u16(self.v);
+        w.u16(self.t);
+        w.bool(self.nmi_occurred);
+        w.bool(self.nmi_output);
+        w.bool(self.nmi_previous);
+        w.u8(self.w);
+        w.u8(self.x);
+        w.u8(self.nmi_delay);
+        w.bool(self.vblank_next);
+        w.bool(self.vblank_suppressed);
+        w.u8(self.flg_nametable);
+        w.u8(self.flg_increment);
+        w.u8(self.flg_spritetable);
+        w.u8(self.flg_backgroundtable);
+        w.u8(self.flg_spritesize);
+        w.u8(self.flg_masterslave);
+        w.u8(self.flg_grayscale);
+        w.u8(self.flg_showleftbg);
+        w.u8(self.flg_showleftsprites);
+        w.u8(self.flg_showbg);
+        w.u8(self.flg_showsprites);
+        w.u8(self.flg_redtint);
+        w.u8(self.flg_greentint);
+        w.u8(self.flg_bluetint);
+        w.u8(self.flg_sprite0hit);
+        w.u8(self.flg_spriteoverflow);
+        w.u8(self.oam_address);
+        w.u8(self.buffer_data);
+    }
+
+    /// Reads back state written by `save`
+    pub fn load(r: &mut StateReader) -> Result<Self, StateError> {
+        let mut state = PPUState::new();
+        r.bytes(&mut state.palettes)?;
+        r.bytes(&mut state.nametables.0)?;
+        r.bytes(&mut state.oam.0)?;
+        state.v = r.u16()? & 0x7FFF;
+        state.t = r.u16()? & 0x7FFF;
+        state.nmi_occurred = r.bool()?;
+        state.nmi_output = r.bool()?;
+        state.nmi_previous = r.bool()?;
+        state.w = r.u8()? & 1;
+        state.x = r.u8()? & 7;
+        state.nmi_delay = r.u8()?.min(NMI_DELAY);
+        state.vblank_next = r.bool()?;
+        state.vblank_suppressed = r.bool()?;
+        state.flg_nametable = r.u8()? & 3;
+        state.flg_increment = r.u8()? & 1;
+        state.flg_spritetable = r.u8()? & 1;
+        state.flg_backgroundtable = r.u8()? & 1;
+        state.flg_spritesize = r.u8()? & 1;
+        state.flg_masterslave = r.u8()? & 1;
+        state.flg_grayscale = r.u8()? & 1;
+        state.flg_showleftbg = r.u8()? & 1;
+        state.flg_showleftsprites = r.u8()? & 1;
+        state.flg_showbg = r.u8()? & 1;
+        state.flg_showsprites = r.u8()? & 1;
+        state.flg_redtint = r.u8()? & 1;
+        state.flg_greentint = r.u8()? & 1;
+        state.flg_bluetint = r.u8()? & 1;
+        state.flg_sprite0hit = r.u8()? & 1;
+        state.flg_spriteoverflow = r.u8()? & 1;
+        state.oam_address = r.u8()?;
+        state.buffer_data = r.u8()?;
+        Ok(state)
+    }
+
+    /// Returns the height of sprites in pixels, depending on the sprite size
+    fn sprite_height(&self) -> i32 {
+        if self.flg_spritesize == 0 {
+            8
+        } else {
+            16
+        }
+    }
+
+    /// Returns every sprite in range of a scanline, in the order of OAM.
+    ///
+    /// Each item contains the index of the sprite, along with the row
+    /// of the sprite that lies on the scanline.
+    fn sprites_in_range(&self, scanline: i32) -> impl Iterator<Item = (usize, i32)> + '_ {
+        let height = self.sprite_height();
+        (0..64).filter_map(move |i| {
+            let row = scanline - i32::from(self.oam.0[i * 4]);
+            if (0..height).contains(&row) {
+                Some((i, row))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Returns whether the hardware flags sprite overflow on a scanline.
+    ///
+    /// After finding 8 sprites, the PPU keeps looking for another one in
+    /// range, but a bug moves on to the next byte of each sprite along with
+    /// the next sprite, whenever one isn't in range. Tiles, attributes and
+    /// X positions then get compared as Y positions, which both misses
+    /// sprites in range and flags lines without any more sprites.
+    ///
+    /// See: https://wiki.nesdev.com/w/index.php/PPU_sprite_evaluation#Sprite_overflow_bug
+    fn buggy_overflow(&self, scanline: i32) -> bool {
+        let height = self.sprite_height();
+        let in_range = |y: u8| (0..height).contains(&(scanline - i32::from(y)));
+        let mut sprite = 0;
+        let mut found = 0;
+        while sprite < 64 && found < 8 {
+            if in_range(self.oam.0[sprite * 4]) {
+                found += 1;
+            }
+            sprite += 1;
+        }
+        let mut byte = 0;
+        while sprite < 64 {
+            if in_range(self.oam.0[sprite * 4 + byte]) {
+                return true;
+            }
+            sprite += 1;
+            byte = (byte + 1) % 4;
+        }
+        false
+    }
+
+    /// Returns the sprites displayed on a scanline, without changing any state.
+    ///
+    /// See `Console::sprites_on_scanline`.
+    pub fn sprites_on_scanline(&self, line: i32) -> Vec<SpriteEntry> {
+        // Sprites are evaluated on the line before the one they're displayed on
+        self.sprites_in_range(line - 1)
+            .enumerate()
+            .map(|(count, (i, row))| SpriteEntry {
+                index: i as u8,
+                y: self.oam.0[i * 4],
+                tile: self.oam.0[i * 4 + 1],
+                attributes: self.oam.0[i * 4 + 2],
+                x: self.oam.0[i * 4 + 3],
+                row: row as u8,
+                drawn: count < 8,
+            })
+            .collect()
+    }
+
+    /// Returns every sprite in OAM, decoded.
+    pub fn sprites(&self) -> [SpriteInfo; 64] {
+        let height = self.sprite_height() as u8;
+        let mut sprites = [SpriteInfo::default(); 64];
+        for (sprite, bytes) in sprites.iter_mut().zip(self.oam.0.chunks(4)) {
+            *sprite = SpriteInfo::from_oam(bytes, height);
+        }
+        sprites
+    }
+
+    /// Returns the color displayed for an entry in palette ram.
+    ///
+    /// Grayscale is applied to the palette index first, by keeping only
+    /// the brightness of the color, and emphasis is then applied to the
+    /// resulting color. Each emphasis bit darkens the two other channels.
+    ///
+    /// Palettes with colors for each emphasis use those instead.
+    pub(crate) fn pixel_color(&self, color: u8) -> u32 {
+        self.index_color(self.color_index(color))
+    }
+
+    /// Returns the index in the system palette of an entry in palette ram,
+    /// after applying grayscale.
+    pub(crate) fn color_index(&self, color: u8) -> u8 {
+        let color_index = self.read_palette(u16::from(color)) % 64;
+        if self.flg_grayscale != 0 {
+            color_index & 0x30
+        } else {
+            color_index
+        }
+    }
+
+    /// Returns the color of an index in the system palette, after applying emphasis
+    pub(crate) fn index_color(&self, color_index: u8) -> u32 {
+        let (red, green) = if self.swap_emphasis {
+            (self.flg_greentint, self.flg_redtint)
+        } else {
+            (self.flg_redtint, self.flg_greentint)
+        };
+        let emphasis = red | (green << 1) | (self.flg_bluetint << 2);
+        let argb = self.colors.color(color_index, emphasis);
+        if self.colors.has_emphasis() {
+            return argb;
+        }
+        emphasize(argb, red != 0, green != 0, self.flg_bluetint != 0)
+    }
+
+    /// Replaces this state with another, keeping the settings used for colors
+    pub(crate) fn replace(&mut self, mut state: PPUState) {
+        state.colors = core::mem::take(&mut self.colors);
+        state.swap_emphasis = self.swap_emphasis;
+        *self = state;
+    }
+
+    /// Returns the colors of a row of a tile, from left to right.
+    ///
+    /// The row is read from CHR at `address`, and colored with one
+    /// of the background palettes, like the PPU does while rendering.
+    fn tile_row(&self, mapper: &dyn Mapper, address: u16, palette: u8) -> [u32; 8] {
+        let low = self.read(mapper, address);
+        let high = self.read(mapper, address + 8);
+        let mut row = [0; 8];
+        for (x, argb) in row.iter_mut().enumerate() {
+            let bit = 7 - x;
+            let pattern = ((low >> bit) & 1) | (((high >> bit) & 1) << 1);
+            let color = if pattern == 0 { 0 } else { palette | pattern };
+            *argb = self.pixel_color(color);
+        }
+        row
+    }
+
     fn read(&self, mapper: &dyn Mapper, address: u16) -> u8 {
         let wrapped = address % 0x4000;
         match wrapped {
@@ -184,10 +547,7 @@ impl PPUState {
                 let mirrored = mode.mirror_address(a);
                 self.nametables.read(mirrored)
             }
-            a if a < 0x4000 => self.read_palette(a % 32),
-            a => {
-                panic!("Unhandled PPU memory read at {:X}", a);
-            }
+            a => self.read_palette(a % 32),
         }
     }
 
@@ -200,17 +560,12 @@ impl PPUState {
                 let mirrored = mode.mirror_address(a);
                 self.nametables.write(mirrored, value);
             }
-            a if a < 0x4000 => {
-                self.write_palette(a % 32, value);
-            }
-            a => {
-                panic!("Unhandled PPU memory write at {:X}", a);
-            }
+            a => self.write_palette(a % 32, value),
         }
     }
 
     fn read_palette(&self, address: u16) -> u8 {
-        let wrapped = if address >= 16 && address % 4 == 0 {
+        let wrapped = if address >= 16 && address.is_multiple_of(4) {
             address - 16
         } else {
             address
@@ -219,7 +574,7 @@ impl PPUState {
     }
 
     fn write_palette(&mut self, address: u16, value: u8) {
-        let wrapped = if address >= 16 && address % 4 == 0 {
+        let wrapped = if address >= 16 && address.is_multiple_of(4) {
             address - 16
         } else {
             address
@@ -227,23 +582,49 @@ impl PPUState {
         self.palettes[wrapped as usize] = value;
     }
 
-    /// Needs the wrapper because it might read from CHR data
-    pub fn read_register(&mut self, m: &dyn Mapper, address: u16) -> u8 {
+    /// Needs the wrapper because it might read from CHR data.
+    ///
+    /// Write only registers, and the unused bits of PPUSTATUS,
+    /// return the last value on the bus.
+    pub fn read_register(&mut self, m: &dyn Mapper, address: u16, open_bus: u8) -> u8 {
         match address {
-            0x2002 => self.read_status(),
+            0x2002 => self.read_status(open_bus),
             0x2004 => self.read_oam_data(),
             0x2007 => self.read_data(m),
-            _ => 0,
+            _ => open_bus,
         }
     }
 
-    fn read_status(&mut self) -> u8 {
-        let mut res = self.register & 0x1F;
+    /// Returns what reading a register would, without any side effects.
+    ///
+    /// PPUSTATUS keeps its vblank flag, and the write toggle isn't reset.
+    /// PPUDATA returns the value a read would, but neither the buffer nor
+    /// the address are updated. OAMDATA ignores the glitches it has
+    /// during rendering, so values read then are approximate.
+    pub fn peek_register(&self, m: &dyn Mapper, address: u16, open_bus: u8) -> u8 {
+        match address {
+            0x2002 => self.peek_status(open_bus),
+            0x2004 => self.read_oam_data(),
+            0x2007 => self.peek_data(m),
+            _ => open_bus,
+        }
+    }
+
+    fn peek_status(&self, open_bus: u8) -> u8 {
+        let mut res = open_bus & 0x1F;
         res |= self.flg_spriteoverflow << 5;
         res |= self.flg_sprite0hit << 6;
         if self.nmi_occurred {
             res |= 1 << 7;
         }
+        res
+    }
+
+    fn read_status(&mut self, open_bus: u8) -> u8 {
+        let res = self.peek_status(open_bus);
+        if self.vblank_next {
+            self.vblank_suppressed = true;
+        }
         self.nmi_occurred = false;
         self.nmi_change();
         self.w = 0;
@@ -254,6 +635,14 @@ impl PPUState {
         self.oam.0[self.oam_address as usize]
     }
 
+    fn peek_data(&self, mapper: &dyn Mapper) -> u8 {
+        if self.v % 0x4000 < 0x3F00 {
+            self.buffer_data
+        } else {
+            self.read(mapper, self.v)
+        }
+    }
+
     fn read_data(&mut self, mapper: &dyn Mapper) -> u8 {
         let v = self.v;
         let mut value = self.read(mapper, v);
@@ -263,16 +652,11 @@ impl PPUState {
             let read = self.read(mapper, v - 0x1000);
             self.buffer_data = read;
         }
-        if self.flg_increment == 0 {
-            self.v += 1;
-        } else {
-            self.v += 32;
-        }
+        self.increment_address();
         value
     }
 
     pub fn write_register(&mut self, mapper: &mut dyn Mapper, address: u16, value: u8) {
-        self.register = value;
         match address {
             0x2000 => self.write_control(value),
             0x2001 => self.write_mask(value),
@@ -288,7 +672,7 @@ impl PPUState {
 
     // See: https://wiki.nesdev.com/w/index.php/PPU_registers#PPUCTRL
     fn write_control(&mut self, value: u8) {
-        self.flg_nametable = value & 2;
+        self.flg_nametable = value & 3;
         self.flg_increment = (value >> 2) & 1;
         self.flg_spritetable = (value >> 3) & 1;
         self.flg_backgroundtable = (value >> 4) & 1;
@@ -332,6 +716,19 @@ impl PPUState {
         }
     }
 
+    /// Writes half of the vram address, through $2006.
+    ///
+    /// The second write copies the temporary address to `v` immediately,
+    /// even while rendering. Since rendering fetches tiles using `v`,
+    /// and only increments it from there, a write in the middle of the frame
+    /// changes the scroll starting with the next tile fetched. Writes during
+    /// hblank, after the horizontal bits of `t` are copied on dot 257, thus
+    /// set the scroll for the next line, including fine Y, which comes from
+    /// bits 4 and 5 of the first write. This is what games rely on when
+    /// combining $2006 and $2005 writes for split screens.
+    ///
+    /// On hardware, the copy happens a few dots late, and can conflict with
+    /// the increments done by rendering on the same dot. This isn't modeled.
     fn write_address(&mut self, value: u8) {
         if self.w == 0 {
             self.t = (self.t & 0x80FF) | ((u16::from(value) & 0x3F) << 8);
@@ -346,11 +743,14 @@ impl PPUState {
     fn write_data(&mut self, mapper: &mut dyn Mapper, value: u8) {
         let v = self.v;
         self.write(mapper, v, value);
-        if self.flg_increment == 0 {
-            self.v += 1;
-        } else {
-            self.v += 32;
-        }
+        self.increment_address();
+    }
+
+    /// Moves to the next address after an access to PPUDATA
+    fn increment_address(&mut self) {
+        let increment = if self.flg_increment == 0 { 1 } else { 32 };
+        // The address is only 15 bits wide, and wraps around
+        self.v = self.v.wrapping_add(increment) & 0x7FFF;
     }
 
     fn copy_y(&mut self) {
@@ -390,13 +790,32 @@ impl PPUState {
     }
 }
 
+/// Reads a byte of the pattern tables while rendering, telling the mapper
+fn fetch_pattern(m: &mut MemoryBus, address: u16) -> u8 {
+    let value = m.ppu.read(&*m.mapper, address);
+    m.mapper.pattern_fetched(address);
+    value
+}
+
 /// Represents the PPU
 pub(crate) struct PPU {
     cycle: i32,
     scanline: i32,
+    /// The index of the pre-render scanline, which is the last in a frame
+    preline: i32,
+    /// Whether or not to skip a dot on odd frames while rendering
+    skips_odd_dot: bool,
+    /// Whether or not sprite overflow follows the buggy scan of the hardware
+    overflow_bug: bool,
 
     // This need to be boxed to avoid blowing up the stack
     v_buffer: Box<PixelBuffer>,
+    /// The last completed frame, which isn't touched while rendering
+    front_buffer: Box<PixelBuffer>,
+    /// The index in the system palette of each pixel being rendered
+    v_indices: Box<[u8; NES_WIDTH * NES_HEIGHT]>,
+    /// The indices of each pixel of `front_buffer`
+    front_indices: Box<[u8; NES_WIDTH * NES_HEIGHT]>,
 
     // Background temporary variables
     nametable_byte: u8,
@@ -418,10 +837,17 @@ pub(crate) struct PPU {
 impl PPU {
     /// Creates a new PPU
     pub fn new(m: &mut MemoryBus) -> Self {
+        let region = Region::default();
         let mut ppu = PPU {
             cycle: 0,
             scanline: 0,
+            preline: region.scanlines() - 1,
+            skips_odd_dot: region.skips_odd_dot(),
+            overflow_bug: false,
             v_buffer: Box::default(),
+            front_buffer: Box::default(),
+            v_indices: Box::new([0; NES_WIDTH * NES_HEIGHT]),
+            front_indices: Box::new([0; NES_WIDTH * NES_HEIGHT]),
             nametable_byte: 0,
             attributetable_byte: 0,
             lowtile_byte: 0,
@@ -440,17 +866,176 @@ impl PPU {
 
     /// Resets the PPU to its initial state
     pub fn reset(&mut self, m: &mut MemoryBus) {
-        self.cycle = 340;
-        self.scanline = 240;
+        self.reset_position();
         m.ppu.write_control(0);
         m.ppu.write_mask(0);
         m.ppu.write_oam_address(0);
+        m.ppu.w = 0;
+    }
+
+    /// Moves back to the end of the last visible scanline
+    fn reset_position(&mut self) {
+        self.cycle = 340;
+        self.scanline = 240;
+    }
+
+    /// Adjusts the number of scanlines and odd frame behavior to match a region.
+    ///
+    /// This moves the PPU back to the end of the visible scanlines, since
+    /// the current position might not exist in the new region.
+    pub fn set_region(&mut self, region: Region) {
+        self.preline = region.scanlines() - 1;
+        self.skips_odd_dot = region.skips_odd_dot();
+        self.reset_position();
+    }
+
+    /// Sets whether the sprite overflow flag is set like on hardware,
+    /// instead of whenever more than 8 sprites are on a line.
+    ///
+    /// See `PPUState::buggy_overflow`.
+    pub fn set_overflow_bug(&mut self, enabled: bool) {
+        self.overflow_bug = enabled;
     }
 
     /// Used to clear vbuffers to make image completely neutral
     /// This isn't called in the standard reset.
     pub fn clear_vbuffers(&mut self) {
-        self.v_buffer = Box::default();
+        *self.v_buffer = PixelBuffer::default();
+        *self.front_buffer = PixelBuffer::default();
+        self.v_indices.fill(0);
+        self.front_indices.fill(0);
+    }
+
+    /// Returns the current scanline, and the current dot on that scanline
+    pub fn position(&self) -> (i32, i32) {
+        (self.scanline, self.cycle)
+    }
+
+    /// Returns the last frame the PPU completed
+    pub fn front_buffer(&self) -> &PixelBuffer {
+        &self.front_buffer
+    }
+
+    /// Returns the index in the system palette of each pixel of the last frame
+    pub fn index_buffer(&self) -> &[u8; NES_WIDTH * NES_HEIGHT] {
+        &self.front_indices
+    }
+
+    /// Draws the background of the four nametables, as arranged in PPU memory.
+    ///
+    /// `out` holds the pixels of the whole arrangement, which is
+    /// `NAMETABLES_WIDTH` by `NAMETABLES_HEIGHT`, in row order.
+    /// Mirroring is respected, so mirrored nametables show up twice.
+    /// This only reads memory, leaving the state used for rendering alone.
+    pub fn render_nametables(&self, m: &MemoryBus, out: &mut [u32]) {
+        let ppu = &m.ppu;
+        let mapper = &*m.mapper;
+        let table = 0x1000 * u16::from(ppu.flg_backgroundtable);
+        for nametable in 0..4 {
+            let base = 0x2000 + nametable * 0x400;
+            let left = (nametable % 2) as usize * NES_WIDTH;
+            let top = (nametable / 2) as usize * NES_HEIGHT;
+            for row in 0..30 {
+                for column in 0..32 {
+                    let tile = u16::from(ppu.read(mapper, base + row * 32 + column));
+                    let attribute_address = base + 0x3C0 + (row / 4) * 8 + column / 4;
+                    let shift = ((row & 2) << 1) | (column & 2);
+                    let attribute = ppu.read(mapper, attribute_address);
+                    let palette = ((attribute >> shift) & 3) << 2;
+                    for y in 0..8 {
+                        let colors = ppu.tile_row(mapper, table + tile * 16 + y, palette);
+                        let py = top + (row * 8 + y) as usize;
+                        let px = left + column as usize * 8;
+                        let start = py * NAMETABLES_WIDTH + px;
+                        out[start..start + 8].copy_from_slice(&colors);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Draws both pattern tables side by side, as sheets of 16 by 16 tiles.
+    ///
+    /// The table at $0000 is on the left, and the one at $1000 on the right,
+    /// taking up the top 128 lines of `out`, with the rest left transparent.
+    /// Tiles are read through the mapper, showing the banks currently
+    /// selected, and colored with one of the 4 background palettes.
+    pub fn render_pattern_tables(&self, m: &MemoryBus, palette: u8, out: &mut PixelBuffer) {
+        let ppu = &m.ppu;
+        let mapper = &*m.mapper;
+        let palette = (palette & 3) << 2;
+        *out = PixelBuffer::default();
+        for table in 0..2 {
+            for tile in 0..256 {
+                let address = table * 0x1000 + tile * 16;
+                let left = (table * 128 + (tile % 16) * 8) as usize;
+                let top = (tile / 16 * 8) as usize;
+                for y in 0..8 {
+                    let colors = ppu.tile_row(mapper, address + y, palette);
+                    for (x, &argb) in colors.iter().enumerate() {
+                        out.write(left + x, top + y as usize, argb);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Writes the position and rendering state, including both frames
+    pub fn save(&self, w: &mut StateWriter) {
+        w.i32(self.cycle);
+        w.i32(self.scanline);
+        w.u8(self.f);
+        w.u8(self.nametable_byte);
+        w.u8(self.attributetable_byte);
+        w.u8(self.lowtile_byte);
+        w.u8(self.hightile_byte);
+        w.u64(self.tiledata);
+        w.i32(self.sprite_count);
+        for i in 0..8 {
+            w.u32(self.sprite_patterns[i]);
+            w.u8(self.sprite_positions[i]);
+            w.u8(self.sprite_priorities[i]);
+            w.u8(self.sprite_indices[i]);
+        }
+        for buffer in [&self.v_buffer, &self.front_buffer] {
+            for &pixel in buffer.as_ref().as_ref() {
+                w.u32(pixel);
+            }
+        }
+        w.bytes(&*self.v_indices);
+        w.bytes(&*self.front_indices);
+    }
+
+    /// Reads back state written by `save`.
+    ///
+    /// The region should already be set, since it isn't part of the state.
+    pub fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.cycle = r.i32()?;
+        self.scanline = r.i32()?;
+        if !(0..=340).contains(&self.cycle) || !(0..=self.preline).contains(&self.scanline) {
+            return Err(StateError::InvalidValue);
+        }
+        self.f = r.u8()?;
+        self.nametable_byte = r.u8()?;
+        self.attributetable_byte = r.u8()?;
+        self.lowtile_byte = r.u8()?;
+        self.hightile_byte = r.u8()?;
+        self.tiledata = r.u64()?;
+        self.sprite_count = r.i32()?.clamp(0, 8);
+        for i in 0..8 {
+            self.sprite_patterns[i] = r.u32()?;
+            self.sprite_positions[i] = r.u8()?;
+            self.sprite_priorities[i] = r.u8()?;
+            self.sprite_indices[i] = r.u8()?;
+        }
+        for buffer in [&mut self.v_buffer, &mut self.front_buffer] {
+            for pixel in buffer.pixels_mut() {
+                *pixel = r.u32()?;
+            }
+        }
+        r.bytes(&mut *self.v_indices)?;
+        r.bytes(&mut *self.front_indices)?;
+        Ok(())
     }
 
     fn fetch_nametable_byte(&mut self, m: &mut MemoryBus) {
@@ -472,7 +1057,7 @@ impl PPU {
         let table = m.ppu.flg_backgroundtable;
         let tile = u16::from(self.nametable_byte);
         let address = 0x1000 * u16::from(table) + tile * 16 + fine_y;
-        self.lowtile_byte = m.ppu.read(&*m.mapper, address);
+        self.lowtile_byte = fetch_pattern(m, address);
     }
 
     fn fetch_hightile_byte(&mut self, m: &mut MemoryBus) {
@@ -480,7 +1065,7 @@ impl PPU {
         let table = m.ppu.flg_backgroundtable;
         let tile = u16::from(self.nametable_byte);
         let address = 0x1000 * u16::from(table) + tile * 16 + fine_y;
-        self.hightile_byte = m.ppu.read(&*m.mapper, address + 8);
+        self.hightile_byte = fetch_pattern(m, address + 8);
     }
 
     fn store_tiledata(&mut self) {
@@ -507,6 +1092,7 @@ impl PPU {
             let table = m.ppu.flg_spritetable;
             0x1000 * u16::from(table) + u16::from(tile) * 16 + (row as u16)
         } else {
+            // Flipping covers both tiles, drawing the bottom one on top
             if attributes & 0x80 == 0x80 {
                 row = 15 - row;
             }
@@ -519,8 +1105,8 @@ impl PPU {
             0x1000 * u16::from(table) + u16::from(tile) * 16 + (row as u16)
         };
         let a = (attributes & 3) << 2;
-        let mut lowtile_byte = m.ppu.read(&*m.mapper, address);
-        let mut hightile_byte = m.ppu.read(&*m.mapper, address + 8);
+        let mut lowtile_byte = fetch_pattern(m, address);
+        let mut hightile_byte = fetch_pattern(m, address + 8);
         let mut data: u32 = 0;
         for _ in 0..8 {
             let (p1, p2) = if attributes & 0x40 == 0x40 {
@@ -543,34 +1129,44 @@ impl PPU {
     }
 
     fn evaluate_sprites(&mut self, m: &mut MemoryBus) {
-        let h: i32 = if m.ppu.flg_spritesize == 0 { 8 } else { 16 };
+        let mut selected = [(0, 0); 8];
         let mut count = 0;
-        for i in 0..64 {
-            let y = m.ppu.oam.0[i * 4];
-            let a_reg = m.ppu.oam.0[i * 4 + 2];
-            let x = m.ppu.oam.0[i * 4 + 3];
-            let row = self.scanline - i32::from(y);
-            if row < 0 || row >= h {
-                continue;
-            }
+        for (i, row) in m.ppu.sprites_in_range(self.scanline) {
             if count < 8 {
-                let pattern = self.fetch_sprite_pattern(m, i, row);
-                self.sprite_patterns[count] = pattern;
-                self.sprite_positions[count] = x;
-                self.sprite_priorities[count] = (a_reg >> 5) & 1;
-                self.sprite_indices[count] = i as u8;
+                selected[count] = (i, row);
             }
             count += 1;
         }
-        if count > 8 {
-            count = 8;
+        let overflow = if self.overflow_bug {
+            m.ppu.buggy_overflow(self.scanline)
+        } else {
+            count > 8
+        };
+        if overflow {
             m.ppu.flg_spriteoverflow = 1;
         }
+        count = count.min(8);
+        for (slot, &(i, row)) in selected[..count].iter().enumerate() {
+            let a_reg = m.ppu.oam.0[i * 4 + 2];
+            let x = m.ppu.oam.0[i * 4 + 3];
+            let pattern = self.fetch_sprite_pattern(m, i, row);
+            self.sprite_patterns[slot] = pattern;
+            self.sprite_positions[slot] = x;
+            self.sprite_priorities[slot] = (a_reg >> 5) & 1;
+            self.sprite_indices[slot] = i as u8;
+        }
         self.sprite_count = count as i32;
     }
 
     fn set_vblank(&mut self, m: &mut MemoryBus, video: &mut impl VideoDevice) {
+        self.front_buffer.clone_from(&self.v_buffer);
+        self.front_indices.copy_from_slice(&*self.v_indices);
         video.blit_pixels(self.v_buffer.as_ref());
+        m.ppu.vblank_next = false;
+        if m.ppu.vblank_suppressed {
+            m.ppu.vblank_suppressed = false;
+            return;
+        }
         m.ppu.nmi_occurred = true;
         m.ppu.nmi_change();
     }
@@ -607,7 +1203,7 @@ impl PPU {
                 let shift = (offset * 4) as u8;
                 let pattern = self.sprite_patterns[i as usize];
                 let color = ((pattern >> shift) & 0x0F) as u8;
-                if color % 4 == 0 {
+                if color.is_multiple_of(4) {
                     continue;
                 }
                 return (i as u8, color);
@@ -627,14 +1223,16 @@ impl PPU {
         if x < 8 && m.ppu.flg_showleftsprites == 0 {
             sprite = 0;
         }
-        let bg = background % 4 != 0;
-        let sp = sprite % 4 != 0;
+        let bg = !background.is_multiple_of(4);
+        let sp = !sprite.is_multiple_of(4);
         let color = match (bg, sp) {
             (false, false) => 0,
             (false, true) => sprite | 0x10,
             (true, false) => background,
             (true, true) => {
                 let ind = i as usize;
+                // Masked columns were cleared above, and the hardware never
+                // checks the last pixel of a line
                 if self.sprite_indices[ind] == 0 && x < 255 {
                     m.ppu.flg_sprite0hit = 1;
                 }
@@ -645,19 +1243,20 @@ impl PPU {
                 }
             }
         };
-        let mut color_index = m.ppu.read_palette(u16::from(color)) % 64;
-        if m.ppu.flg_grayscale != 0 {
-            color_index &= 0x30;
-        }
-        let argb = PALETTE[color_index as usize];
+        let index = m.ppu.color_index(color);
+        let argb = m.ppu.index_color(index);
         self.v_buffer.write(x as usize, y as usize, argb);
+        self.v_indices[NES_WIDTH * y as usize + x as usize] = index;
+        if let Some(zapper) = &mut m.zapper {
+            zapper.sense(x as usize, y as usize, argb);
+        }
     }
 
     /// Steps the ppu forward
     pub fn step(&mut self, m: &mut MemoryBus, video: &mut impl VideoDevice) -> bool {
         self.tick(m);
         let rendering = m.ppu.flg_showbg != 0 || m.ppu.flg_showsprites != 0;
-        let preline = self.scanline == 261;
+        let preline = self.scanline == self.preline;
         let visibleline = self.scanline < 240;
         let renderline = preline || visibleline;
         let prefetch_cycle = self.cycle >= 321 && self.cycle <= 336;
@@ -705,8 +1304,22 @@ impl PPU {
             }
         }
 
+        if visibleline && self.cycle == 257 {
+            let start = NES_WIDTH * self.scanline as usize;
+            let pixels: &[u32] = self.v_buffer.as_ref().as_ref();
+            let line = &pixels[start..start + NES_WIDTH];
+            video.scanline_complete(self.scanline as u16, line);
+        }
+
+        if self.cycle == 340 {
+            if let Some(zapper) = &mut m.zapper {
+                zapper.end_line();
+            }
+        }
+
         let mut frame_happened = false;
         // Vblank logic
+        m.ppu.vblank_next = self.scanline == 241 && self.cycle == 0;
         if self.scanline == 241 && self.cycle == 1 {
             self.set_vblank(m, video);
             frame_happened = true;
@@ -728,19 +1341,18 @@ impl PPU {
             }
         }
         let show_something = m.ppu.flg_showbg != 0 || m.ppu.flg_showsprites != 0;
-        let should_reset = self.f == 1 && self.scanline == 261 && self.cycle == 339;
-        if show_something && should_reset {
-            self.cycle = 0;
-            self.scanline = 0;
-            self.f ^= 1;
-            return;
+        let should_reset = self.f == 1 && self.scanline == self.preline && self.cycle == 339;
+        // Odd frames skip the last dot of the pre-render line while rendering,
+        // going through the same wraparound, so the parity flips exactly once
+        if self.skips_odd_dot && show_something && should_reset {
+            self.cycle = 340;
         }
 
         self.cycle += 1;
         if self.cycle > 340 {
             self.cycle = 0;
             self.scanline += 1;
-            if self.scanline > 261 {
+            if self.scanline > self.preline {
                 self.scanline = 0;
                 self.f ^= 1;
             }
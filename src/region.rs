@@ -0,0 +1,74 @@
+/// Represents the video standard a console is built for.
+///
+/// This affects the timing of every processor in the console, and
+/// thus the speed at which games run.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Region {
+    /// North American and Japanese consoles, running at 60 Hz
+    #[default]
+    NTSC,
+    /// European consoles, running at 50 Hz
+    PAL,
+}
+
+impl Region {
+    /// The number of cpu cycles in a second
+    pub(crate) fn cpu_frequency(self) -> u32 {
+        match self {
+            Region::NTSC => 1_789_773,
+            Region::PAL => 1_662_607,
+        }
+    }
+
+    /// The number of PPU dots per cpu cycle, as a fraction.
+    ///
+    /// The first element is the numerator, and the second the denominator.
+    pub(crate) fn ppu_dots_per_cycle(self) -> (i32, i32) {
+        match self {
+            Region::NTSC => (3, 1),
+            Region::PAL => (16, 5),
+        }
+    }
+
+    /// The number of cpu cycles in a frame, counted in half cycles.
+    ///
+    /// On NTSC, a frame is 341 * 262 dots, minus the dot skipped on odd
+    /// frames, at 3 dots per cycle, which averages to 29780.5 cycles.
+    /// On PAL, a frame is 341 * 312 dots at 3.2 dots per cycle,
+    /// which is 33247.5 cycles.
+    pub(crate) fn frame_half_cycles(self) -> i32 {
+        match self {
+            Region::NTSC => 59561,
+            Region::PAL => 66495,
+        }
+    }
+
+    /// The number of scanlines in a frame, including vblank.
+    pub(crate) fn scanlines(self) -> i32 {
+        match self {
+            Region::NTSC => 262,
+            Region::PAL => 312,
+        }
+    }
+
+    /// Whether or not the red and green emphasis bits of PPUMASK are swapped.
+    pub(crate) fn swaps_emphasis(self) -> bool {
+        self == Region::PAL
+    }
+
+    /// Whether or not a dot is skipped on odd frames while rendering.
+    pub(crate) fn skips_odd_dot(self) -> bool {
+        self == Region::NTSC
+    }
+
+    /// The number of cpu cycles between each step of the APU frame counter.
+    ///
+    /// This should be a half integer, but we round it up to an even number,
+    /// since the APU timers are clocked on even cycles.
+    pub(crate) fn frame_counter_period(self) -> u16 {
+        match self {
+            Region::NTSC => 7458,
+            Region::PAL => 8314,
+        }
+    }
+}
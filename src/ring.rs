@@ -0,0 +1,109 @@
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+use crate::ports::AudioDevice;
+
+/// The state shared between both ends of the ring.
+struct Shared {
+    /// The bits of each sample, with one slot always left empty
+    samples: Box<[AtomicU32]>,
+    /// The index of the next sample to read
+    read: AtomicUsize,
+    /// The index of the next sample to write
+    write: AtomicUsize,
+    /// The number of times a sample was read while the ring was empty
+    underruns: AtomicUsize,
+}
+
+impl Shared {
+    fn next(&self, index: usize) -> usize {
+        (index + 1) % self.samples.len()
+    }
+}
+
+/// Create a ring buffer of audio samples, holding up to `capacity` samples.
+///
+/// This is meant to pass samples from the thread running the console to
+/// a real time audio callback, without locking. The producer is given to
+/// the console as its audio device, and the consumer is drained by the
+/// callback. Neither end ever blocks: samples pushed while the ring is full
+/// are dropped, and reading from an empty ring returns silence.
+///
+/// A capacity of a few frames worth of samples gives the callback some
+/// slack, while keeping latency low.
+pub fn audio_ring(capacity: usize) -> (AudioProducer, AudioConsumer) {
+    let samples: Vec<AtomicU32> = (0..capacity + 1).map(|_| AtomicU32::new(0)).collect();
+    let shared = Arc::new(Shared {
+        samples: samples.into_boxed_slice(),
+        read: AtomicUsize::new(0),
+        write: AtomicUsize::new(0),
+        underruns: AtomicUsize::new(0),
+    });
+    let producer = AudioProducer {
+        shared: shared.clone(),
+    };
+    (producer, AudioConsumer { shared })
+}
+
+/// The end of an audio ring samples are pushed into.
+pub struct AudioProducer {
+    shared: Arc<Shared>,
+}
+
+impl AudioDevice for AudioProducer {
+    fn push_sample(&mut self, sample: f32) {
+        let shared = &*self.shared;
+        let write = shared.write.load(Ordering::Relaxed);
+        let next = shared.next(write);
+        if next == shared.read.load(Ordering::Acquire) {
+            return;
+        }
+        shared.samples[write].store(sample.to_bits(), Ordering::Relaxed);
+        shared.write.store(next, Ordering::Release);
+    }
+}
+
+/// The end of an audio ring samples are read from.
+pub struct AudioConsumer {
+    shared: Arc<Shared>,
+}
+
+impl AudioConsumer {
+    /// Returns the oldest sample in the ring.
+    ///
+    /// If the ring is empty, this returns silence, and counts an underrun.
+    pub fn pop(&mut self) -> f32 {
+        let shared = &*self.shared;
+        let read = shared.read.load(Ordering::Relaxed);
+        if read == shared.write.load(Ordering::Acquire) {
+            shared.underruns.fetch_add(1, Ordering::Relaxed);
+            return 0.0;
+        }
+        let sample = shared.samples[read].load(Ordering::Relaxed);
+        shared.read.store(shared.next(read), Ordering::Release);
+        f32::from_bits(sample)
+    }
+
+    /// Returns the number of samples waiting to be read.
+    pub fn len(&self) -> usize {
+        let shared = &*self.shared;
+        let read = shared.read.load(Ordering::Relaxed);
+        let write = shared.write.load(Ordering::Acquire);
+        (write + shared.samples.len() - read) % shared.samples.len()
+    }
+
+    /// Returns true if there are no samples waiting to be read.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of times `pop` was called on an empty ring.
+    ///
+    /// Underruns are heard as crackling, so a growing count means the
+    /// console isn't keeping up, or the ring is too small.
+    pub fn underruns(&self) -> usize {
+        self.shared.underruns.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,143 @@
+use alloc::vec::Vec;
+
+/// The version of the format used for saved states.
+///
+/// This should be bumped whenever the layout of a saved state changes.
+const VERSION: u8 = 1;
+
+/// Represents the possible errors when loading a saved state
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StateError {
+    /// The data doesn't start with the header of the expected kind of state
+    BadHeader,
+    /// The state was saved by an incompatible version of this crate
+    BadVersion(u8),
+    /// The data ended before the whole state could be read
+    UnexpectedEnd,
+    /// The state was saved while playing a different cart
+    WrongCart,
+    /// A value in the state is out of the range it can take, so the
+    /// state is corrupted, or was crafted
+    InvalidValue,
+}
+
+/// Used to write state as a sequence of bytes.
+///
+/// Every state starts with a header identifying what kind of state it is,
+/// followed by the version of the format.
+pub(crate) struct StateWriter {
+    bytes: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new(magic: &[u8; 4]) -> Self {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(magic);
+        bytes.push(VERSION);
+        StateWriter { bytes }
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn i32(&mut self, value: i32) {
+        self.u32(value as u32);
+    }
+
+    pub fn f32(&mut self, value: f32) {
+        self.u32(value.to_bits());
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    /// Returns the bytes written so far
+    pub fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// Used to read back state written by a `StateWriter`.
+pub(crate) struct StateReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    /// Starts reading state, checking that the header matches.
+    pub fn new(bytes: &'a [u8], magic: &[u8; 4]) -> Result<Self, StateError> {
+        if bytes.len() < 4 || bytes[..4] != magic[..] {
+            return Err(StateError::BadHeader);
+        }
+        let mut reader = StateReader { bytes: &bytes[4..] };
+        let version = reader.u8()?;
+        if version != VERSION {
+            return Err(StateError::BadVersion(version));
+        }
+        Ok(reader)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        let mut byte = [0];
+        self.bytes(&mut byte)?;
+        Ok(byte[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        let mut bytes = [0; 2];
+        self.bytes(&mut bytes)?;
+        Ok(u16::from_le_bytes(bytes))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, StateError> {
+        let mut bytes = [0; 4];
+        self.bytes(&mut bytes)?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        let mut bytes = [0; 8];
+        self.bytes(&mut bytes)?;
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub fn i32(&mut self) -> Result<i32, StateError> {
+        Ok(self.u32()? as i32)
+    }
+
+    pub fn f32(&mut self) -> Result<f32, StateError> {
+        Ok(f32::from_bits(self.u32()?))
+    }
+
+    pub fn bool(&mut self) -> Result<bool, StateError> {
+        Ok(self.u8()? != 0)
+    }
+
+    /// Fills a buffer with the next bytes
+    pub fn bytes(&mut self, buffer: &mut [u8]) -> Result<(), StateError> {
+        if self.bytes.len() < buffer.len() {
+            return Err(StateError::UnexpectedEnd);
+        }
+        let (read, rest) = self.bytes.split_at(buffer.len());
+        buffer.copy_from_slice(read);
+        self.bytes = rest;
+        Ok(())
+    }
+}
@@ -0,0 +1,295 @@
+use alloc::vec::Vec;
+use core::f32::consts::PI;
+
+use super::{make_bus, make_console, NullDevice};
+use crate::apu::{mix, AudioFilter, FilterChain, APU};
+use crate::memory::MemoryBus;
+use crate::ports::BufferedAudio;
+use crate::region::Region;
+
+#[test]
+fn dmc_steps_output_per_bit() {
+    let mut m = make_bus();
+    let mut apu = APU::new(44100);
+    // The sample starts at $C000, where the PRG is mirrored, with 0xFF
+    // followed by 0x02. It plays at the fastest rate, with IRQs enabled.
+    m.cpu_write(0x4010, 0x8F);
+    m.cpu_write(0x4011, 64);
+    m.cpu_write(0x4012, 0);
+    m.cpu_write(0x4013, 1);
+    m.cpu_write(0x4015, 0x10);
+    let mut levels = Vec::new();
+    for _ in 0..54 * 26 {
+        apu.step(&mut m, &mut NullDevice);
+        let level = m.apu.dmc_output();
+        if levels.last().copied().unwrap_or(64) != level {
+            levels.push(level);
+        }
+    }
+    // 8 bits of 0xFF go up, then 0x02 goes down, up, and down from there
+    let expected = [
+        66, 68, 70, 72, 74, 76, 78, 80, 78, 80, 78, 76, 74, 72, 70, 68,
+    ];
+    assert_eq!(levels[..16], expected);
+    // The sample is 17 bytes long, so it's still playing
+    assert_eq!(m.cpu_read(0x4015) & 0x90, 0x10);
+    for _ in 0..54 * 8 * 16 {
+        apu.step(&mut m, &mut NullDevice);
+    }
+    // Once done, only the IRQ flag is left, until $4015 is written
+    assert_eq!(m.cpu_read(0x4015) & 0x90, 0x80);
+    m.cpu_write(0x4015, 0);
+    assert_eq!(m.cpu_read(0x4015) & 0x90, 0);
+}
+
+#[test]
+fn mixer_matches_formula() {
+    // The levels of the channels, along with the values from the formulas at
+    // https://wiki.nesdev.com/w/index.php/APU_Mixer
+    let levels = [
+        ((0, 0, 0, 0, 0), 0.0),
+        ((15, 0, 0, 0, 0), 0.149_377),
+        ((15, 15, 0, 0, 0), 0.258_483),
+        ((0, 0, 15, 0, 0), 0.246_412),
+        ((0, 0, 0, 15, 0), 0.174_431),
+        ((0, 0, 0, 0, 127), 0.574_264),
+        ((15, 15, 15, 15, 127), 0.999_999),
+    ];
+    for ((p1, p2, t, n, d), expected) in levels {
+        let mixed = mix(p1, p2, t, n, d);
+        assert!((mixed - expected).abs() < 0.000_1, "{mixed} != {expected}");
+    }
+}
+
+// Steps the APU until the frame interrupt is raised, returning the ticks taken
+fn ticks_until_frame_irq(m: &mut MemoryBus, apu: &mut APU, limit: u32) -> Option<u32> {
+    (1..=limit).find(|_| {
+        apu.step(m, &mut NullDevice);
+        m.cpu_read(0x4015) & 0x40 != 0
+    })
+}
+
+#[test]
+fn frame_irq_follows_mode() {
+    let mut m = make_bus();
+    let mut apu = APU::new(44100);
+    let sequence = 4 * u32::from(Region::NTSC.frame_counter_period());
+    // The 4 step mode raises an IRQ at the end of every sequence
+    m.cpu_write(0x4017, 0x00);
+    for _ in 0..3 {
+        let ticks = ticks_until_frame_irq(&mut m, &mut apu, 2 * sequence);
+        assert_eq!(ticks, Some(sequence));
+    }
+    // Reading $4015 acknowledged the interrupt
+    assert_eq!(m.cpu_read(0x4015) & 0x40, 0);
+    // Neither the 5 step mode, nor inhibiting IRQs, raise any
+    m.cpu_write(0x4017, 0x80);
+    assert_eq!(ticks_until_frame_irq(&mut m, &mut apu, 3 * sequence), None);
+    m.cpu_write(0x4017, 0x40);
+    assert_eq!(ticks_until_frame_irq(&mut m, &mut apu, 3 * sequence), None);
+    // Going back restarts the sequence
+    m.cpu_write(0x4017, 0x00);
+    apu.step(&mut m, &mut NullDevice);
+    let ticks = ticks_until_frame_irq(&mut m, &mut apu, 2 * sequence);
+    assert_eq!(ticks, Some(sequence - 1));
+}
+
+// Steps the APU through a number of half frames, in the 4 step mode
+fn step_half_frames(m: &mut MemoryBus, apu: &mut APU, count: u32) {
+    let ticks = 2 * u32::from(Region::NTSC.frame_counter_period());
+    for _ in 0..count * ticks {
+        apu.step(m, &mut NullDevice);
+    }
+}
+
+#[test]
+fn length_counter_silences_channel() {
+    let mut m = make_bus();
+    let mut apu = APU::new(44100);
+    m.cpu_write(0x4017, 0x40);
+    // Loading the length counter does nothing while disabled
+    m.cpu_write(0x4000, 0x0F);
+    m.cpu_write(0x4003, 0x00);
+    assert_eq!(m.cpu_read(0x4015) & 1, 0);
+    // A length of 10 half frames
+    m.cpu_write(0x4015, 0x01);
+    m.cpu_write(0x4003, 0x00);
+    step_half_frames(&mut m, &mut apu, 9);
+    assert_eq!(m.cpu_read(0x4015) & 1, 1);
+    step_half_frames(&mut m, &mut apu, 1);
+    assert_eq!(m.cpu_read(0x4015) & 1, 0);
+    // The halt flag keeps the counter going
+    m.cpu_write(0x4000, 0x2F);
+    m.cpu_write(0x4003, 0x00);
+    step_half_frames(&mut m, &mut apu, 20);
+    assert_eq!(m.cpu_read(0x4015) & 1, 1);
+}
+
+#[test]
+fn sweep_stops_before_overflow() {
+    let mut m = make_bus();
+    let mut apu = APU::new(44100);
+    m.cpu_write(0x4017, 0x40);
+    m.cpu_write(0x4015, 0x01);
+    // Add half the period on every half frame, starting from $200
+    m.cpu_write(0x4001, 0x81);
+    m.cpu_write(0x4002, 0x00);
+    m.cpu_write(0x4003, 0x02);
+    let mut periods = Vec::new();
+    for _ in 0..5 {
+        step_half_frames(&mut m, &mut apu, 1);
+        periods.push(m.apu.square1_period());
+    }
+    // The next target would be past $7FF, which mutes instead
+    assert_eq!(periods, [0x300, 0x480, 0x6C0, 0x6C0, 0x6C0]);
+}
+
+// Steps the APU through a second, returning the number of samples sent
+fn samples_in_second(m: &mut MemoryBus, apu: &mut APU, region: Region) -> usize {
+    let mut audio = BufferedAudio::default();
+    for _ in 0..region.cpu_frequency() {
+        apu.step(m, &mut audio);
+    }
+    audio.samples.len()
+}
+
+#[test]
+fn samples_match_output_rate() {
+    let mut m = make_bus();
+    let mut apu = APU::new(44100);
+    // A constant level on the DMC
+    m.cpu_write(0x4011, 64);
+    assert_eq!(samples_in_second(&mut m, &mut apu, Region::NTSC), 44100);
+    apu.set_sample_rate(48000);
+    assert_eq!(samples_in_second(&mut m, &mut apu, Region::NTSC), 48000);
+    apu.set_region(Region::PAL);
+    assert_eq!(samples_in_second(&mut m, &mut apu, Region::PAL), 48000);
+}
+
+// Returns the cosine of an angle, since core has no trigonometry
+fn cos(x: f32) -> f32 {
+    let mut term = 1.0;
+    let mut sum = 1.0;
+    for n in (2..40).step_by(2) {
+        term *= -x * x / (n * (n - 1)) as f32;
+        sum += term;
+    }
+    sum
+}
+
+// Returns the gain of the filters on a sine wave at some frequency
+fn filtered_gain(frequency: f32) -> f32 {
+    let mut filter = FilterChain::new(44100, AudioFilter::default());
+    // Generate the sine by rotating, which only needs one cosine
+    let k = 2.0 * cos(2.0 * PI * frequency / 44100.0);
+    let (mut previous, mut current) = (0.0, 0.0);
+    let (mut input, mut output): (f32, f32) = (0.0, 0.0);
+    for i in 0..44100 {
+        let next = if i == 0 { 1.0 } else { k * current - previous };
+        (previous, current) = (current, next);
+        let y = filter.step(current);
+        // Skip the first half second, while the filters settle
+        if i > 22050 {
+            input = input.max(current.abs());
+            output = output.max(y.abs());
+        }
+    }
+    output / input
+}
+
+#[test]
+fn filters_attenuate_outside_cutoffs() {
+    assert!(filtered_gain(20.0) < 0.1);
+    assert!(filtered_gain(1000.0) > 0.8);
+    assert!(filtered_gain(20000.0) < 0.6);
+}
+
+#[test]
+fn dmc_fetches_stall_cpu() {
+    let mut console = make_console(&[0x4C, 0x00, 0x80], 0, 0);
+    // The fastest rate, looping over the longest sample
+    console.poke(0x4010, 0x4F);
+    console.poke(0x4012, 0x00);
+    console.poke(0x4013, 0xFF);
+    console.poke(0x4015, 0x10);
+    let mut cycle = 0;
+    let mut stalls = Vec::new();
+    let mut stalled = 0;
+    while cycle < 10_000 {
+        let cycles = console.step(&mut NullDevice, &mut NullDevice).unwrap();
+        // Stalled cycles are stepped one at a time, unlike any instruction
+        if cycles == 1 {
+            if stalled == 0 {
+                stalls.push(cycle);
+            }
+            stalled += 1;
+        } else if stalled > 0 {
+            assert_eq!(stalled, 4);
+            stalled = 0;
+        }
+        cycle += cycles;
+    }
+    // A byte is fetched every 8 bits, each lasting 54 cycles
+    assert!(stalls.len() > 20);
+    for pair in stalls.windows(2).skip(1) {
+        assert!((430..=436).contains(&(pair[1] - pair[0])), "{:?}", pair);
+    }
+}
+
+#[test]
+fn apu_log_records_register_writes() {
+    let program = [
+        0xA9, 0x3F, // LDA #$3F
+        0x8D, 0x00, 0x40, // STA $4000
+        0xA9, 0x08, // LDA #$08
+        0x8D, 0x01, 0x40, // STA $4001
+        0x8D, 0x16, 0x40, // STA $4016, which isn't an APU register
+        0xA9, 0x0F, // LDA #$0F
+        0x8D, 0x15, 0x40, // STA $4015
+        0x4C, 0x12, 0x80, // JMP $8012
+    ];
+    let mut console = make_console(&program, 0, 0);
+    console.enable_apu_log(16);
+    for _ in 0..8 {
+        console.step(&mut NullDevice, &mut NullDevice).unwrap();
+    }
+    let log = console.take_apu_log();
+    let writes: Vec<(u16, u8)> = log.iter().map(|w| (w.address, w.value)).collect();
+    assert_eq!(writes, [(0x4000, 0x3F), (0x4001, 0x08), (0x4015, 0x0F)]);
+    // Each write is stamped with the cycle its instruction started on
+    assert_eq!(log[1].cycle - log[0].cycle, 6);
+    assert_eq!(log[2].cycle - log[1].cycle, 10);
+    // Taking the log empties it
+    assert!(console.take_apu_log().is_empty());
+}
+
+#[test]
+fn dmc_irq_waits_for_interrupts_enabled() {
+    let program = [
+        0x78, // SEI
+        0xA9, 0x40, // LDA #$40
+        0x8D, 0x17, 0x40, // STA $4017, inhibiting the frame interrupt
+        0xA9, 0x80, // LDA #$80
+        0x8D, 0x10, 0x40, // STA $4010, enabling the DMC IRQ
+        0xA9, 0x00, // LDA #$00
+        0x8D, 0x12, 0x40, // STA $4012
+        0x8D, 0x13, 0x40, // STA $4013, for a single byte
+        0xA9, 0x10, // LDA #$10
+        0x8D, 0x15, 0x40, // STA $4015
+        0x2C, 0x15, 0x40, // BIT $4015
+        0x10, 0xFB, // BPL -5, until the DMC interrupt flag is set
+        0x58, // CLI
+        0x4C, 0x1E, 0x80, // JMP $801E
+        // The IRQ handler at $8021
+        0xE6, 0x10, // INC $10
+        0xA9, 0x00, // LDA #$00
+        0x8D, 0x15, 0x40, // STA $4015, acknowledging the interrupt
+        0x40, // RTI
+    ];
+    // The IRQ is still taken once interrupts are enabled again
+    let mut console = make_console(&program, 0x8021, 0x8021);
+    for _ in 0..2 {
+        console.step_frame(&mut NullDevice, &mut NullDevice);
+    }
+    assert_eq!(console.peek(0x10), 1);
+}
@@ -1,56 +1,6 @@
-use super::super::cart::*;
-
-// Makes an ines file with anything filling the PRG and CHR
-// 0xFF is used as a marker file for the beginning of PRG and CHR
-fn make_ines(
-    mirroring: Mirroring,
-    has_battery: bool,
-    trainer: bool,
-    mapper: u8,
-    prg_chunks: usize,
-    chr_chunks: usize,
-) -> Vec<u8> {
-    let trainer_offset = if trainer { 512 } else { 0 };
-    let mut buffer = {
-        let rom_size = 0x4000 * prg_chunks + 0x2000 * chr_chunks;
-        Vec::with_capacity(16 + trainer_offset + rom_size)
-    };
-    let mut flag6 = 0;
-    if mirroring.is_vertical() {
-        flag6 |= 0b1;
-    }
-    if has_battery {
-        flag6 |= 0b10;
-    }
-    if trainer {
-        flag6 |= 0b100;
-    }
-    flag6 |= (mapper & 0x0F) << 4;
-    let flag7 = (mapper & 0xF0) << 4;
-    buffer.push(0x4E);
-    buffer.push(0x45);
-    buffer.push(0x53);
-    buffer.push(0x1A);
-    buffer.push(prg_chunks as u8);
-    buffer.push(chr_chunks as u8);
-    buffer.push(flag6);
-    buffer.push(flag7);
-    for _ in 8..16 {
-        buffer.push(0);
-    }
-    for _ in 0..trainer_offset {
-        buffer.push(0x1);
-    }
-    buffer.push(0xFF);
-    for _ in 1..prg_chunks * 0x4000 {
-        buffer.push(0x2);
-    }
-    buffer.push(0xFF);
-    for _ in 1..chr_chunks * 0x2000 {
-        buffer.push(0x3);
-    }
-    buffer
-}
+use super::make_ines;
+use crate::cart::*;
+use crate::state::{StateError, StateReader, StateWriter};
 
 #[test]
 fn cart_decoding() {
@@ -60,7 +10,86 @@ fn cart_decoding() {
     let cart = cart_res.unwrap(); // we just asserted, so it's ok
     assert_eq!(cart.prg[0], 0xFF);
     assert_eq!(cart.chr[0], 0xFF);
-    assert_eq!(cart.mapper, 1);
+    assert_eq!(cart.mapper, MapperID::M1);
     assert!(!cart.mirroring.is_vertical());
-    assert_eq!(cart.has_battery, true);
+    assert!(cart.has_battery);
+}
+
+#[test]
+fn cart_without_prg_is_rejected() {
+    // Every mapper expects at least one PRG bank
+    for mapper in [0, 1, 2, 3, 7, 9, 66] {
+        let mut buffer = make_ines(Mirroring::Horizontal, false, false, mapper, 1, 1);
+        buffer[4] = 0;
+        let result = Cart::from_bytes(&buffer);
+        assert!(matches!(result, Err(CartReadingError::UnrecognisedFormat)));
+    }
+}
+
+#[test]
+fn trainer_is_skipped_and_loaded() {
+    let buffer = make_ines(Mirroring::Horizontal, false, true, 0, 1, 1);
+    let cart = Cart::from_bytes(&buffer).unwrap();
+    assert_eq!(cart.prg[0], 0xFF);
+    assert_eq!(cart.prg[1], 0x02);
+    assert_eq!(cart.chr[0], 0xFF);
+    assert_eq!(cart.sram[0xFFF], 0);
+    assert_eq!(cart.sram[0x1000], 0x01);
+    assert_eq!(cart.sram[0x11FF], 0x01);
+    assert_eq!(cart.sram[0x1200], 0);
+}
+
+#[test]
+fn info_describes_header() {
+    let mut buffer = make_ines(Mirroring::Vertical, true, false, 66, 2, 0);
+    let cart = Cart::from_bytes(&buffer).unwrap();
+    let info = cart.info();
+    assert_eq!(info.mapper, 66);
+    assert_eq!((info.prg_size, info.chr_size), (0x8000, 0));
+    assert_eq!(info.mirroring, Mirroring::Vertical);
+    assert!(info.has_battery);
+    assert_eq!(info.title, None);
+    assert_eq!(info.caption("game.nes"), "Ludus - game.nes");
+    // Some dumps have a title padded with zeroes after the ROM data
+    buffer.extend_from_slice(b"Some Game\0\0\0");
+    let cart = Cart::from_bytes(&buffer).unwrap();
+    assert_eq!(cart.info().title.as_deref(), Some("Some Game"));
+    assert_eq!(
+        cart.info().caption("game.nes"),
+        "Ludus - Some Game (game.nes)"
+    );
+}
+
+#[test]
+fn info_checksums_skip_header_and_trainer() {
+    let sha1 = [
+        0xEA, 0x2A, 0xA4, 0x1E, 0x48, 0xAB, 0x33, 0xEF, 0x69, 0x8E, 0x13, 0x75, 0x9D, 0x34, 0x28,
+        0x8A, 0x72, 0xB4, 0x13, 0xD2,
+    ];
+    for trainer in [false, true] {
+        let buffer = make_ines(Mirroring::Horizontal, false, trainer, 0, 1, 1);
+        let cart = Cart::from_bytes(&buffer).unwrap();
+        assert_eq!(cart.info().crc32, 0x2B06_F41D);
+        assert_eq!(cart.info().crc32, cart.hash);
+        assert_eq!(cart.info().sha1, sha1);
+    }
+}
+
+#[test]
+fn cart_state_checks_mirroring() {
+    let buffer = make_ines(Mirroring::Vertical, false, false, 0, 1, 1);
+    let mut cart = Cart::from_bytes(&buffer).unwrap();
+    let mut w = StateWriter::new(b"TEST");
+    cart.save(&mut w);
+    let mut saved = w.finish();
+    // The mirroring follows the header, version, and 8KB of SRAM
+    let mirroring = 5 + 0x2000;
+    assert_eq!(saved[mirroring], 1);
+    saved[mirroring] = 4;
+    let mut r = StateReader::new(&saved, b"TEST").unwrap();
+    assert_eq!(cart.load(&mut r), Ok(()));
+    assert_eq!(cart.mirroring, Mirroring::FourScreen);
+    saved[mirroring] = 5;
+    let mut r = StateReader::new(&saved, b"TEST").unwrap();
+    assert_eq!(cart.load(&mut r), Err(StateError::InvalidValue));
 }
@@ -0,0 +1,43 @@
+use super::make_bus;
+use crate::cheats::{Cheat, CheatError};
+
+#[test]
+fn decode_game_genie_codes() {
+    let cheat = Cheat::from_game_genie("SXIOPO").unwrap();
+    assert_eq!(cheat.address, 0x91D9);
+    assert_eq!(cheat.value, 0xAD);
+    assert_eq!(cheat.compare, None);
+    assert_eq!(Cheat::from_game_genie("sxiopo"), Ok(cheat));
+    let cheat = Cheat::from_game_genie("ZEXPYGLA").unwrap();
+    assert_eq!(cheat.address, 0x94A7);
+    assert_eq!(cheat.value, 0x02);
+    assert_eq!(cheat.compare, Some(0x03));
+    assert_eq!(
+        Cheat::from_game_genie("SXIOP"),
+        Err(CheatError::BadLength(5))
+    );
+    assert_eq!(
+        Cheat::from_game_genie("SXIOPB"),
+        Err(CheatError::BadLetter('B'))
+    );
+}
+
+#[test]
+fn cheats_patch_prg_reads() {
+    let mut m = make_bus();
+    // PRG is filled with 0x02, except for the first byte
+    let cheat = Cheat::from_game_genie("PAAAAAAP").unwrap();
+    m.cheats.push(Cheat {
+        address: 0x8001,
+        ..cheat
+    });
+    m.cheats.push(Cheat {
+        address: 0x8002,
+        compare: Some(0x02),
+        ..cheat
+    });
+    assert_eq!(m.cpu_read(0x8000), 0xFF);
+    // The compare value doesn't match
+    assert_eq!(m.cpu_read(0x8001), 0x02);
+    assert_eq!(m.cpu_read(0x8002), 0x01);
+}
@@ -0,0 +1,686 @@
+use alloc::collections::BTreeMap;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+
+use super::{make_console, make_ines, NullDevice};
+use crate::cart::{Cart, Mirroring};
+use crate::config::{Accuracy, GameConfig, RamFill};
+use crate::console::{Console, ConsoleBuilder};
+use crate::controller::{ButtonState, Turbo};
+use crate::hash::crc32;
+use crate::hud::draw_text;
+use crate::memory::EmulationError;
+use crate::palette::Palette;
+use crate::ports::{BufferedAudio, NullAudio, PixelBuffer, VideoDevice, NES_HEIGHT, NES_WIDTH};
+use crate::region::Region;
+
+#[test]
+fn step_detailed_keeps_ntsc_ratio() {
+    // Loop forever
+    let mut console = make_console(&[0x4C, 0x00, 0x80], 0, 0);
+    for _ in 0..100 {
+        let result = console.step_detailed(&mut NullDevice, &mut NullDevice);
+        assert_eq!(result.cpu_cycles, 3);
+        assert_eq!(result.ppu_dots, 3 * result.cpu_cycles);
+        assert_eq!(result.apu_ticks, result.cpu_cycles);
+    }
+}
+
+#[test]
+fn console_from_rom_bytes() {
+    let rom = make_ines(Mirroring::Horizontal, false, false, 0, 2, 1);
+    let console = Console::from_rom_bytes(&rom, 44100).unwrap();
+    assert_eq!(console.peek(0x8000), 0xFF);
+    assert!(Console::from_rom_bytes(&rom[..8], 44100).is_err());
+}
+
+#[test]
+fn sram_round_trip() {
+    let program = [
+        0xA9, 0x42, // LDA #$42
+        0x8D, 0x00, 0x60, // STA $6000
+        0xA9, 0x24, // LDA #$24
+        0x8D, 0x01, 0x60, // STA $6001
+        0x4C, 0x0A, 0x80, // JMP $800A
+    ];
+    let mut buffer = make_ines(Mirroring::Horizontal, true, false, 0, 1, 1);
+    buffer[16..16 + program.len()].copy_from_slice(&program);
+    buffer[16 + 0x3FFC..16 + 0x3FFE].copy_from_slice(&0x8000u16.to_le_bytes());
+    let mut console = Console::new(Cart::from_bytes(&buffer).unwrap(), 44100);
+    for _ in 0..5 {
+        console.step(&mut NullDevice, &mut NullDevice).unwrap();
+    }
+    let sram = console.sram().unwrap().to_vec();
+    assert_eq!(sram[..2], [0x42, 0x24]);
+
+    let mut restored = Console::new(Cart::from_bytes(&buffer).unwrap(), 44100);
+    assert_eq!(restored.sram().unwrap()[..2], [0, 0]);
+    restored.load_sram(&sram);
+    assert_eq!(restored.sram().unwrap(), &sram[..]);
+
+    // Carts without a battery have nothing to save
+    let console = make_console(&program, 0, 0);
+    assert_eq!(console.sram(), None);
+}
+
+#[test]
+fn step_frame_exact_keeps_pace() {
+    // Loop forever, with instructions of 2 cycles
+    let mut console = make_console(&[0xEA, 0x4C, 0x00, 0x80], 0, 0);
+    let mut total = 0;
+    for _ in 0..100 {
+        let cycles = console.step_frame_exact(&mut NullDevice, &mut NullDevice);
+        assert!((29778..=29784).contains(&cycles));
+        total += cycles;
+    }
+    // The budget carries over, so this never drifts by more than an instruction
+    assert!((2_978_050 - total).abs() <= 3);
+}
+
+#[test]
+fn buffered_audio_collects_samples() {
+    let mut console = make_console(&[0x4C, 0x00, 0x80], 0, 0);
+    let mut audio = BufferedAudio::default();
+    console.step_frame_exact(&mut audio, &mut NullDevice);
+    // Roughly 44100 Hz at 60 frames per second
+    assert!((720..=740).contains(&audio.samples.len()));
+    console.step_frame_exact(&mut NullAudio, &mut NullDevice);
+    assert!((720..=740).contains(&audio.samples.len()));
+}
+
+#[test]
+fn step_reports_errors_without_panicking() {
+    let program = [
+        0xAD, 0x00, 0x50, // LDA $5000
+        0x8D, 0x00, 0x50, // STA $5000
+        0x02, // An opcode that locks up the CPU
+    ];
+    let mut console = make_console(&program, 0, 0);
+    let mut step = || console.step(&mut NullDevice, &mut NullDevice);
+    assert_eq!(step(), Err(EmulationError::UnhandledRead(0x5000)));
+    assert_eq!(step(), Err(EmulationError::UnhandledWrite(0x5000)));
+    assert_eq!(step(), Err(EmulationError::UnknownOpcode(0x02)));
+}
+
+#[test]
+fn region_from_header_and_builder() {
+    let mut buffer = make_ines(Mirroring::Horizontal, false, false, 0, 1, 1);
+    buffer[9] = 1;
+    let cart = Cart::from_bytes(&buffer).unwrap();
+    assert_eq!(cart.region, Some(Region::PAL));
+    let mut console = Console::new(cart, 44100);
+    assert_eq!(console.region(), Region::PAL);
+    let cycles = console.step_frame_exact(&mut NullDevice, &mut NullDevice);
+    assert!((33245..=33250).contains(&cycles));
+
+    let cart = Cart::from_bytes(&buffer).unwrap();
+    let console = ConsoleBuilder::new(cart, 44100)
+        .region(Region::NTSC)
+        .build();
+    assert_eq!(console.region(), Region::NTSC);
+}
+
+#[test]
+fn region_switch_from_late_pal_scanline() {
+    let mut console = ConsoleBuilder::new(looping_cart(), 44100)
+        .region(Region::PAL)
+        .build();
+    // PAL has 50 more scanlines than NTSC, after vblank
+    while console.ppu_position().0 <= 280 {
+        console.step(&mut NullDevice, &mut NullDevice).unwrap();
+    }
+    console.set_region(Region::NTSC);
+    let (scanline, dot) = console.ppu_position();
+    assert!((0..=261).contains(&scanline));
+    assert!((0..=340).contains(&dot));
+    // Once back at the start of a frame, frames have the NTSC length
+    console.step_frame_exact(&mut NullDevice, &mut NullDevice);
+    let cycles = console.step_frame_exact(&mut NullDevice, &mut NullDevice);
+    assert!((29779..=29782).contains(&cycles));
+}
+
+// Runs a few frames of a test rom, returning a hash of the last frame
+fn hash_frames(frames: usize) -> u32 {
+    let rom = include_bytes!("../../test_roms/palette.nes");
+    let mut console = Console::new(Cart::from_bytes(rom).unwrap(), 44100);
+    for _ in 1..frames {
+        console.run_frame();
+    }
+    let pixels = console.run_frame().as_ref();
+    assert!(pixels.iter().any(|&pixel| pixel != pixels[0]));
+    let bytes: Vec<u8> = pixels
+        .iter()
+        .flat_map(|pixel| pixel.to_le_bytes())
+        .collect();
+    crc32(&bytes)
+}
+
+#[test]
+fn run_frame_is_deterministic() {
+    assert_eq!(hash_frames(30), hash_frames(30));
+}
+
+#[test]
+fn frozen_values_are_written_every_frame() {
+    let program = [
+        0xE6, 0x10, // INC $10
+        0x4C, 0x00, 0x80, // JMP $8000
+    ];
+    let mut console = make_console(&program, 0, 0);
+    assert!(console.freeze(0x10, 0x42));
+    assert!(!console.freeze(0x2000, 0x42));
+    console.step_frame(&mut NullDevice, &mut NullDevice);
+    assert_eq!(console.read(0x10), 0x42);
+    console.unfreeze(0x10);
+    console.step_frame(&mut NullDevice, &mut NullDevice);
+    assert_ne!(console.read(0x10), 0x42);
+    console.poke(0x10, 0x24);
+    assert_eq!(console.read(0x10), 0x24);
+}
+
+#[test]
+fn turbo_alternates_every_frame() {
+    let mut console = make_console(&[0x4C, 0x00, 0x80], 0, 0);
+    let held = ButtonState {
+        a: true,
+        b: true,
+        ..ButtonState::default()
+    };
+    console.update_controller(held);
+    console.set_turbo(Turbo {
+        a: true,
+        ..Turbo::default()
+    });
+    let mut a_bits = Vec::new();
+    for _ in 0..4 {
+        console.step_frame(&mut NullDevice, &mut NullDevice);
+        console.poke(0x4016, 1);
+        console.poke(0x4016, 0);
+        a_bits.push(console.read(0x4016) & 1);
+        // B isn't turbo, so it stays held
+        assert_eq!(console.read(0x4016) & 1, 1);
+    }
+    assert!(a_bits == [1, 0, 1, 0] || a_bits == [0, 1, 0, 1]);
+}
+
+// Counts the frames sent to it
+#[derive(Default)]
+struct FrameCounter(usize);
+
+impl VideoDevice for FrameCounter {
+    fn blit_pixels(&mut self, _pixels: &PixelBuffer) {
+        self.0 += 1;
+    }
+}
+
+// Keeps the last frame it received
+#[derive(Default)]
+struct FrameCapture(Option<PixelBuffer>);
+
+impl VideoDevice for FrameCapture {
+    fn blit_pixels(&mut self, pixels: &PixelBuffer) {
+        self.0 = Some(pixels.clone());
+    }
+}
+
+#[test]
+fn video_device_receives_frames() {
+    let mut console = make_console(&[0x4C, 0x00, 0x80], 0, 0);
+    let mut video = FrameCapture::default();
+    console.step_frame(&mut NullDevice, &mut video);
+    let frame = video.0.take().unwrap();
+    assert!(frame.as_ref() == console.framebuffer().as_ref());
+    assert_eq!(frame.as_ref().len(), NES_WIDTH * NES_HEIGHT);
+}
+
+#[test]
+fn hud_draws_over_frames() {
+    let mut console = make_console(&[0x4C, 0x00, 0x80], 0, 0);
+    console.set_hud(true);
+    let mut video = FrameCapture::default();
+    // The FPS is measured every 60 frames, the first ones being cut short
+    for _ in 0..125 {
+        console.step_frame(&mut NullDevice, &mut video);
+    }
+    let frame = video.0.take().unwrap();
+    let pixels = frame.as_ref();
+    // A dark background, with the top of the F in white
+    assert_eq!(pixels[2 * NES_WIDTH + 2], 0xFF00_0000);
+    assert_eq!(pixels[3 * NES_WIDTH + 3], 0xFFFF_FFFF);
+    // Only the frame sent to the video device has the HUD over it
+    let mut expected = console.capture_frame();
+    assert!(expected.as_ref() != pixels);
+    let lines = [
+        "FPS 60".to_string(),
+        format!("FRM {}", console.frame() - 1),
+        "PC 8000".to_string(),
+    ];
+    for (line, text) in lines.iter().enumerate() {
+        draw_text(&mut expected, line, text);
+    }
+    assert!(expected.as_ref() == pixels);
+}
+
+#[test]
+fn fast_forward_skips_audio_and_frames() {
+    let mut console = make_console(&[0x4C, 0x00, 0x80], 0, 0);
+    console.step_frame(&mut NullDevice, &mut NullDevice);
+    console.set_fast_forward(4);
+    let mut audio = BufferedAudio::default();
+    let mut video = FrameCounter::default();
+    let start = console.frame();
+    console.step_frame(&mut audio, &mut video);
+    assert_eq!(console.frame() - start, 4);
+    assert_eq!(video.0, 1);
+    assert!(audio.samples.is_empty());
+    // Back at normal speed, audio plays again
+    console.set_fast_forward(1);
+    console.step_frame(&mut audio, &mut video);
+    assert_eq!(console.frame() - start, 5);
+    assert_eq!(video.0, 2);
+    assert!(!audio.samples.is_empty());
+}
+
+#[test]
+fn rewind_restores_earlier_frames() {
+    let rom = include_bytes!("../../test_roms/palette.nes");
+    let mut console = Console::new(Cart::from_bytes(rom).unwrap(), 44100);
+    console.enable_rewind(2, 3);
+    let mut frames = Vec::new();
+    for _ in 0..10 {
+        console.run_frame();
+        frames.push(console.capture_frame());
+    }
+    assert_eq!(console.frame(), 10);
+    // Snapshots were taken on frames 6, 8 and 10
+    for &frame in &[10, 8, 6] {
+        assert!(console.rewind_step());
+        assert_eq!(console.frame(), frame);
+        let expected = frames[frame as usize - 1].as_ref();
+        assert!(console.capture_frame().as_ref() == expected);
+    }
+    assert!(!console.rewind_step());
+    assert_eq!(console.frame(), 6);
+}
+
+#[test]
+fn reset_keeps_ram_unlike_power_cycle() {
+    let program = [
+        0xA9, 0x42, // LDA #$42
+        0x85, 0x10, // STA $10
+        0x4C, 0x04, 0x80, // JMP $8004
+    ];
+    let mut console = make_console(&program, 0, 0);
+    console.enable_trace_ring(1);
+    for _ in 0..3 {
+        console.step(&mut NullDevice, &mut NullDevice).unwrap();
+    }
+    assert_eq!(console.read(0x10), 0x42);
+    console.poke(0x10, 0x24);
+    console.reset();
+    assert_eq!(console.read(0x10), 0x24);
+    // The game starts again from the reset vector
+    console.step(&mut NullDevice, &mut NullDevice).unwrap();
+    assert_eq!(console.trace_dump()[0].pc, 0x8000);
+    console.power_cycle();
+    assert_eq!(console.read(0x10), 0);
+    console.step(&mut NullDevice, &mut NullDevice).unwrap();
+    assert_eq!(console.trace_dump()[0].pc, 0x8000);
+}
+
+#[test]
+fn power_cycle_resets_mapper() {
+    // A UxROM cart with its first bank selected, running from the last one
+    let mut buffer = make_ines(Mirroring::Horizontal, true, false, 2, 4, 1);
+    for bank in 0..4 {
+        buffer[16 + bank * 0x4000 + 0x100] = bank as u8;
+    }
+    buffer[16 + 0xC000..16 + 0xC003].copy_from_slice(&[0x4C, 0x00, 0xC0]);
+    buffer[16 + 0xFFFC..16 + 0xFFFE].copy_from_slice(&0xC000u16.to_le_bytes());
+    let mut console = Console::new(Cart::from_bytes(&buffer).unwrap(), 44100);
+    // ROM at $8000 is 0xFF, which avoids bus conflicts
+    console.poke(0x8000, 2);
+    console.poke(0x6000, 0x42);
+    assert_eq!(console.peek(0x8100), 2);
+    console.power_cycle();
+    assert_eq!(console.peek(0x8100), 0);
+    assert_eq!(console.peek(0x6000), 0x42);
+}
+
+#[test]
+fn read_range_dumps_memory() {
+    let mut console = make_console(&[0x4C, 0x00, 0x80], 0, 0);
+    for address in 0..0x100 {
+        console.poke(address, (address as u8).wrapping_mul(3));
+    }
+    let pattern: Vec<u8> = (0..=255u8).map(|i| i.wrapping_mul(3)).collect();
+    assert_eq!(console.read_range(0x0000, 0x100), pattern);
+    // RAM is mirrored, and the range wraps around to the zero page
+    assert_eq!(console.read_range(0x1800, 0x100), pattern);
+    assert_eq!(console.read_range(0xFFFE, 4)[2..], pattern[..2]);
+    assert_eq!(console.read_range(0x8000, 3), [0x4C, 0x00, 0x80]);
+    // Reading registers doesn't disturb them
+    console.poke(0x2006, 0x3F);
+    console.read_range(0x2000, 8);
+    console.poke(0x2006, 0x00);
+    console.poke(0x2007, 0x21);
+    console.poke(0x2006, 0x3F);
+    console.poke(0x2006, 0x00);
+    assert_eq!(console.read(0x2007) & 0x3F, 0x21);
+}
+
+#[test]
+fn peek_leaves_registers_alone() {
+    let mut console = make_console(&[0x4C, 0x00, 0x80], 0, 0);
+    // The first frame is cut short, since the PPU starts right before vblank
+    for _ in 0..3 {
+        console.step_frame(&mut NullDevice, &mut NullDevice);
+    }
+    for _ in 0..100_000 {
+        if console.peek(0x2002) & 0x80 != 0 {
+            break;
+        }
+        console.step(&mut NullDevice, &mut NullDevice).unwrap();
+    }
+    // Both vblank and the frame interrupt stay set until the CPU reads them
+    assert_eq!(console.peek(0x2002) & 0x80, 0x80);
+    assert_eq!(console.peek(0x4015) & 0x40, 0x40);
+    assert_eq!(console.peek(0x4015) & 0x40, 0x40);
+    assert_eq!(console.read(0x2002) & 0x80, 0x80);
+    assert_eq!(console.peek(0x2002) & 0x80, 0);
+    assert_eq!(console.read(0x4015) & 0x40, 0x40);
+    assert_eq!(console.peek(0x4015) & 0x40, 0);
+    // PPUDATA returns the buffered value, without moving to the next address
+    console.poke(0x2006, 0x20);
+    console.poke(0x2006, 0x00);
+    console.poke(0x2007, 0x55);
+    console.poke(0x2007, 0x66);
+    console.poke(0x2006, 0x20);
+    console.poke(0x2006, 0x00);
+    console.read(0x2007);
+    assert_eq!(console.peek(0x2007), 0x55);
+    assert_eq!(console.peek(0x2007), 0x55);
+    assert_eq!(console.read(0x2007), 0x55);
+    assert_eq!(console.read(0x2007), 0x66);
+}
+
+// Makes a cart looping forever at $8000, to build consoles with options
+fn looping_cart() -> Cart {
+    let mut buffer = make_ines(Mirroring::Horizontal, false, false, 0, 1, 1);
+    buffer[16..19].copy_from_slice(&[0x4C, 0x00, 0x80]);
+    buffer[16 + 0x3FFC..16 + 0x3FFE].copy_from_slice(&0x8000u16.to_le_bytes());
+    Cart::from_bytes(&buffer).unwrap()
+}
+
+#[test]
+fn null_audio_keeps_apu_running() {
+    // Samples are thrown away, but the APU still raises its frame interrupt
+    let mut console = Console::new(looping_cart(), 44100);
+    for _ in 0..3 {
+        console.step_frame(&mut NullAudio, &mut NullDevice);
+    }
+    assert_eq!(console.read(0x4015) & 0x40, 0x40);
+    // Without audio, the APU isn't stepped at all
+    let mut console = ConsoleBuilder::new(looping_cart(), 44100)
+        .audio(false)
+        .build();
+    for _ in 0..3 {
+        console.step_frame(&mut NullAudio, &mut NullDevice);
+    }
+    assert_eq!(console.read(0x4015) & 0x40, 0);
+}
+
+// Runs 60 frames worth of time, returning the cycles, samples, and frames shown
+fn run_second(console: &mut Console) -> (i32, usize, usize) {
+    let mut audio = BufferedAudio::default();
+    let mut video = FrameCounter::default();
+    let cycles = (0..60)
+        .map(|_| console.step_frame_exact(&mut audio, &mut video))
+        .sum();
+    (cycles, audio.samples.len(), video.0)
+}
+
+#[test]
+fn speed_scales_emulated_time() {
+    let mut console = make_console(&[0x4C, 0x00, 0x80], 0, 0);
+    let (cycles, samples, frames) = run_second(&mut console);
+    assert!((1_786_000..1_787_000).contains(&cycles));
+    assert!((44_000..44_200).contains(&samples));
+    assert!((59..=61).contains(&frames));
+    // At half speed, every frame is shown, and a second of audio comes out
+    console.set_speed(0.5);
+    let (cycles, samples, frames) = run_second(&mut console);
+    assert!((893_000..894_000).contains(&cycles));
+    assert!((44_000..44_200).contains(&samples));
+    assert!((29..=31).contains(&frames));
+    // At twice the speed, only one frame is shown per step
+    console.set_speed(2.0);
+    let (cycles, samples, frames) = run_second(&mut console);
+    assert!((3_573_000..3_574_000).contains(&cycles));
+    assert!((44_000..44_200).contains(&samples));
+    assert!((59..=61).contains(&frames));
+    // The speed is kept within bounds
+    console.set_speed(100.0);
+    let cycles = console.step_frame_exact(&mut NullDevice, &mut NullDevice);
+    assert!((238_200..238_300).contains(&cycles));
+}
+
+#[test]
+fn ram_fill_survives_reset_until_power_cycle() {
+    let console = ConsoleBuilder::new(looping_cart(), 44100)
+        .ram_fill(RamFill::Ones)
+        .build();
+    assert!(console.read_range(0, 0x800).iter().all(|&b| b == 0xFF));
+    let mut console = ConsoleBuilder::new(looping_cart(), 44100)
+        .ram_fill(RamFill::Striped)
+        .build();
+    let striped = [0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF];
+    assert_eq!(console.read_range(0x7F8, 8), striped);
+    console.poke(0x0004, 0x42);
+    console.reset();
+    assert_eq!(
+        console.read_range(0, 8),
+        [0, 0, 0, 0, 0x42, 0xFF, 0xFF, 0xFF]
+    );
+    console.power_cycle();
+    assert_eq!(console.read_range(0, 8), striped);
+}
+
+#[test]
+fn accurate_power_up_fills_ppu_memory() {
+    // Reads the first entry of palette ram, then the first byte of OAM
+    let first_bytes = |mut console: Console| {
+        console.poke(0x2006, 0x3F);
+        console.poke(0x2006, 0x00);
+        let palette = console.read(0x2007);
+        console.poke(0x2003, 0);
+        (palette, console.peek(0x2004))
+    };
+    let console = ConsoleBuilder::new(looping_cart(), 44100).build();
+    assert_eq!(first_bytes(console), (0x00, 0x00));
+    let console = ConsoleBuilder::new(looping_cart(), 44100)
+        .accurate_power_up(true)
+        .build();
+    assert_eq!(first_bytes(console), (0x09, 0xFF));
+}
+
+#[test]
+fn accuracy_changes_sprites_at_power_up() {
+    // With OAM full of zeros, every sprite is at the top of the screen,
+    // while 0xFF puts them all below it
+    let console = ConsoleBuilder::new(looping_cart(), 44100)
+        .accuracy(Accuracy::Fast)
+        .build();
+    assert_eq!(console.sprites_on_scanline(1).len(), 64);
+    let console = ConsoleBuilder::new(looping_cart(), 44100)
+        .accuracy(Accuracy::Accurate)
+        .build();
+    assert!(console.sprites_on_scanline(1).is_empty());
+}
+
+#[test]
+fn sprites_on_scanline_past_eight() {
+    let mut console = make_console(&[0x4C, 0x00, 0x80], 0, 0);
+    // 10 sprites at Y 50, then one at Y 51, the rest being off screen
+    console.poke(0x2003, 0);
+    for sprite in 0..64 {
+        let y = match sprite {
+            0..=9 => 50,
+            10 => 51,
+            _ => 0xFF,
+        };
+        for byte in [y, sprite, 0, sprite * 4] {
+            console.poke(0x2004, byte);
+        }
+    }
+    let sprites = console.sprites_on_scanline(51);
+    assert_eq!(sprites.len(), 10);
+    assert!(sprites[..8].iter().all(|sprite| sprite.drawn));
+    assert!(!sprites[8].drawn && !sprites[9].drawn);
+    assert_eq!(sprites[9].index, 9);
+    assert_eq!(sprites[9].x, 36);
+    // The next line has the sprite below them too, and shows their second row
+    let sprites = console.sprites_on_scanline(52);
+    assert_eq!(sprites.len(), 11);
+    assert_eq!(sprites[10].row, 0);
+    assert_eq!(sprites[0].row, 1);
+    assert!(console.sprites_on_scanline(50).is_empty());
+}
+
+#[test]
+fn game_config_applies_by_hash() {
+    let cart = looping_cart();
+    let config = GameConfig {
+        region: Some(Region::PAL),
+        palette: Some(Palette::from_colors([0xFF12_3456; 64])),
+        ..GameConfig::default()
+    };
+    let mut configs = BTreeMap::new();
+    configs.insert(cart.hash, config);
+    // Another game doesn't get the config
+    configs.insert(cart.hash ^ 1, GameConfig::default());
+    let mut console = ConsoleBuilder::new(cart, 44100)
+        .game_configs(configs.clone())
+        .build();
+    assert_eq!(console.region(), Region::PAL);
+    // Show the background, which is all backdrop
+    console.poke(0x2001, 0x08);
+    console.step_frame(&mut NullDevice, &mut NullDevice);
+    console.step_frame(&mut NullDevice, &mut NullDevice);
+    assert_eq!(console.capture_frame().as_ref()[0], 0xFF12_3456);
+    // The builder is overridden only for the game in the table
+    let mut buffer = make_ines(Mirroring::Horizontal, false, false, 0, 1, 1);
+    buffer[16] = 0x42;
+    let other = Cart::from_bytes(&buffer).unwrap();
+    let console = ConsoleBuilder::new(other, 44100)
+        .game_configs(configs)
+        .build();
+    assert_eq!(console.region(), Region::NTSC);
+}
+
+#[test]
+fn ppu_position_follows_cpu_cycles() {
+    let mut console = make_console(&[0x4C, 0x00, 0x80], 0, 0);
+    // The PPU starts at the last dot of the last visible scanline
+    assert_eq!(console.ppu_position(), (240, 340));
+    // Each JMP takes 3 cycles, so 9 dots
+    console.step(&mut NullDevice, &mut NullDevice).unwrap();
+    assert_eq!(console.ppu_position(), (241, 8));
+    for _ in 1..100 {
+        console.step(&mut NullDevice, &mut NullDevice).unwrap();
+    }
+    assert_eq!(console.ppu_position(), (243, 217));
+}
+
+#[test]
+fn load_cart_swaps_games() {
+    let program = [
+        0xA9, 0x11, // LDA #$11
+        0x85, 0x00, // STA $00
+        0x4C, 0x04, 0x80, // JMP $8004
+    ];
+    let mut console = make_console(&program, 0, 0);
+    for _ in 0..2 {
+        console.step_frame(&mut NullDevice, &mut NullDevice);
+    }
+    assert_eq!(console.peek(0x0000), 0x11);
+    // The next game uses UxROM, starting from its last bank
+    let mut rom = make_ines(Mirroring::Horizontal, false, false, 2, 2, 0);
+    let last_bank = 16 + 0x4000;
+    rom[last_bank..last_bank + 7].copy_from_slice(&[0xA9, 0x22, 0x85, 0x01, 0x4C, 0x04, 0xC0]);
+    rom[last_bank + 0x3FFC..last_bank + 0x3FFE].copy_from_slice(&0xC000u16.to_le_bytes());
+    // An invalid ROM leaves the current game running
+    assert!(console.load_cart(&rom[..8]).is_err());
+    assert_eq!(console.peek(0x0000), 0x11);
+    // Holding reset doesn't carry over to the next game
+    console.set_reset_button(true);
+    console.load_cart(&rom).unwrap();
+    assert_eq!(console.frame(), 0);
+    assert_eq!(console.registers().pc, 0xC000);
+    assert_eq!(console.peek(0x0000), 0);
+    for _ in 0..2 {
+        console.step_frame(&mut NullDevice, &mut NullDevice);
+    }
+    assert_eq!(console.peek(0x0001), 0x22);
+    assert_eq!(console.peek(0x0000), 0);
+}
+
+#[test]
+fn frame_skip_sends_fewer_frames() {
+    let mut console = Console::new(looping_cart(), 44100);
+    let mut reference = Console::new(looping_cart(), 44100);
+    console.set_frame_skip(1);
+    let mut audio = BufferedAudio::default();
+    let mut reference_audio = BufferedAudio::default();
+    let mut video = FrameCounter::default();
+    for _ in 0..20 {
+        console.step_frame(&mut audio, &mut video);
+        reference.step_frame(&mut reference_audio, &mut NullDevice);
+    }
+    assert_eq!(video.0, 10);
+    // Audio isn't skipped
+    assert_eq!(audio.samples, reference_audio.samples);
+    console.set_frame_skip(0);
+    for _ in 0..20 {
+        console.step_frame(&mut audio, &mut video);
+    }
+    assert_eq!(video.0, 30);
+}
+
+#[test]
+fn pause_sends_silence_without_catching_up() {
+    let mut console = Console::new(looping_cart(), 44100);
+    console.step_micros(&mut NullAudio, &mut NullDevice, 100_000);
+    let frame = console.frame();
+    let registers = console.registers();
+    console.pause();
+    assert!(console.is_paused());
+    let mut audio = BufferedAudio::default();
+    console.step_micros(&mut audio, &mut NullDevice, 1_000_000);
+    assert_eq!(console.step_frame_exact(&mut audio, &mut NullDevice), 0);
+    assert_eq!(console.frame(), frame);
+    assert_eq!(console.registers(), registers);
+    assert_eq!(audio.samples.len(), 44100 + 733);
+    assert!(audio.samples.iter().all(|&sample| sample == 0.0));
+    // The second spent paused isn't run once resumed
+    console.resume();
+    console.step_micros(&mut NullAudio, &mut NullDevice, 100_000);
+    assert!(console.frame() - frame <= 7);
+}
+
+#[test]
+fn pause_holds_last_sample() {
+    let mut console = Console::new(looping_cart(), 44100);
+    // Without filters, the DMC level is sent as is
+    console.set_audio_filter(None);
+    console.poke(0x4011, 64);
+    let mut audio = BufferedAudio::default();
+    console.step_micros(&mut audio, &mut NullDevice, 10_000);
+    let last = *audio.samples.last().unwrap();
+    assert!(last > 0.0);
+    console.pause();
+    let mut audio = BufferedAudio::default();
+    console.step_micros(&mut audio, &mut NullDevice, 10_000);
+    assert_eq!(audio.samples.len(), 441);
+    assert!(audio.samples.iter().all(|&sample| sample == last));
+}
@@ -0,0 +1,61 @@
+use alloc::vec::Vec;
+
+use super::make_bus;
+use crate::controller::{ButtonState, KeyBindings};
+
+#[test]
+fn remapped_keys_build_button_state() {
+    let bindings = KeyBindings {
+        up: 'i',
+        b: 'z',
+        ..KeyBindings::default()
+    };
+    let held = ['i', 'z', 'h', 'w'];
+    let buttons = bindings.button_state(|key| held.contains(key));
+    let expected = ButtonState {
+        up: true,
+        b: true,
+        start: true,
+        ..ButtonState::default()
+    };
+    assert_eq!(buttons, expected);
+}
+
+#[test]
+fn stick_maps_to_eight_directions() {
+    assert_eq!(
+        ButtonState::from_stick(0.1, -0.1, 0.2),
+        ButtonState::default()
+    );
+    let up = ButtonState::from_stick(0.2, 0.9, 0.2);
+    assert!(up.up && !up.left && !up.right && !up.down);
+    let down_left = ButtonState::from_stick(-0.6, -0.6, 0.2);
+    assert!(down_left.down && down_left.left && !down_left.up && !down_left.right);
+    let right = ButtonState::from_stick(1.0, 0.0, 0.2);
+    assert!(right.right && !right.up && !right.down);
+}
+
+#[test]
+fn strobe_then_read_nine_times() {
+    let mut m = make_bus();
+    m.controller1.set_buttons(ButtonState {
+        a: true,
+        start: true,
+        left: true,
+        ..ButtonState::default()
+    });
+    // While the strobe is held, A is returned every time
+    m.cpu_write(0x4016, 1);
+    for _ in 0..3 {
+        assert_eq!(m.cpu_read(0x4016) & 1, 1);
+    }
+    m.cpu_write(0x4016, 0);
+    // Buttons changed after the strobe wait for the next one
+    m.controller1.set_buttons(ButtonState::default());
+    let bits: Vec<u8> = (0..9).map(|_| m.cpu_read(0x4016) & 1).collect();
+    // A, B, Select, Start, Up, Down, Left, Right, then 1s
+    assert_eq!(bits, [1, 0, 0, 1, 0, 0, 1, 0, 1]);
+    m.cpu_write(0x4016, 1);
+    m.cpu_write(0x4016, 0);
+    assert_eq!(m.cpu_read(0x4016) & 1, 0);
+}
@@ -0,0 +1,348 @@
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use super::{make_console, make_ines, NullDevice};
+use crate::cart::Mirroring;
+use crate::console::{BreakReason, Console};
+use crate::cpu::{disassemble, disassemble_rom, disassembly_listing, Registers};
+use crate::memory::WatchHit;
+
+// CLI, then SEI, and finally loop forever, with an RTI for the handlers
+const PROGRAM: [u8; 6] = [0x58, 0x78, 0x4C, 0x02, 0x80, 0x40];
+const HANDLER: u16 = 0x8005;
+
+// Steps a single instruction, returning its address and the flags before it
+fn step(console: &mut Console) -> (u16, u8) {
+    console.step(&mut NullDevice, &mut NullDevice).unwrap();
+    let entry = *console.trace_dump().last().unwrap();
+    (entry.pc, entry.p)
+}
+
+#[test]
+fn triggered_irq_runs_handler() {
+    let mut console = make_console(&PROGRAM, 0, HANDLER);
+    console.enable_trace_ring(1);
+    assert_eq!(step(&mut console).0, 0x8000);
+    console.trigger_irq();
+    let (pc, p) = step(&mut console);
+    assert_eq!(pc, HANDLER);
+    assert_ne!(p & 0x04, 0);
+    // The RTI returns to the instruction after CLI
+    assert_eq!(step(&mut console).0, 0x8001);
+}
+
+#[test]
+fn triggered_irq_respects_interrupt_disable() {
+    let mut console = make_console(&PROGRAM, HANDLER, HANDLER);
+    console.enable_trace_ring(1);
+    step(&mut console);
+    step(&mut console);
+    console.trigger_irq();
+    assert_eq!(step(&mut console).0, 0x8002);
+    // NMIs can't be disabled
+    console.trigger_nmi();
+    assert_eq!(step(&mut console).0, HANDLER);
+    assert_eq!(step(&mut console).0, 0x8002);
+}
+
+#[test]
+fn irq_leaves_pending_nmi_alone() {
+    let mut console = make_console(&PROGRAM, HANDLER, 0x8002);
+    console.enable_trace_ring(1);
+    step(&mut console);
+    console.trigger_nmi();
+    console.trigger_irq();
+    assert_eq!(step(&mut console).0, HANDLER);
+}
+
+#[test]
+fn held_reset_button_stops_cpu() {
+    let mut console = make_console(&PROGRAM, 0, 0);
+    console.enable_trace_ring(1);
+    step(&mut console);
+    step(&mut console);
+    console.set_reset_button(true);
+    console.step_frame(&mut NullDevice, &mut NullDevice);
+    console.set_reset_button(true);
+    // Nothing ran after the second instruction
+    assert_eq!(console.trace_dump()[0].pc, 0x8001);
+    console.set_reset_button(false);
+    assert_eq!(step(&mut console).0, 0x8000);
+}
+
+// Runs the first instructions of a program, returning the console
+// and the cycles taken by each instruction
+fn run(program: &[u8], instructions: usize) -> (Console, Vec<i32>) {
+    let mut console = make_console(program, 0, 0);
+    let cycles = (0..instructions)
+        .map(|_| console.step(&mut NullDevice, &mut NullDevice).unwrap())
+        .collect();
+    (console, cycles)
+}
+
+#[test]
+fn indirect_jump_wraps_at_end_of_memory() {
+    let program = [
+        0x6C, 0xFF, 0xFF, // JMP ($FFFF)
+    ];
+    // The low byte comes from $FFFF, the high byte of the IRQ vector,
+    // and the high byte from $FF00, in the same page
+    let mut console = make_console(&program, 0, 0x8000);
+    console.step(&mut NullDevice, &mut NullDevice).unwrap();
+    assert_eq!(console.registers().pc, 0x0280);
+}
+
+#[test]
+fn lax_and_sax() {
+    let (mut console, _) = run(
+        &[
+            0xA9, 0x33, // LDA #$33
+            0x85, 0x10, // STA $10
+            0xA9, 0x00, // LDA #$00
+            0xA7, 0x10, // LAX $10
+            0xA9, 0x0F, // LDA #$0F
+            0x87, 0x11, // SAX $11
+        ],
+        6,
+    );
+    assert_eq!(console.read(0x11), 0x03);
+}
+
+#[test]
+fn read_modify_write_combinations() {
+    let (mut console, _) = run(
+        &[
+            0xA9, 0x05, // LDA #$05
+            0x85, 0x10, // STA $10
+            0xC7, 0x10, // DCP $10, leaving 4 and setting carry
+            0xE7, 0x10, // ISC $10, leaving 5 and A = 5 - 5
+            0x85, 0x11, // STA $11
+            0xA9, 0x01, // LDA #$01
+            0x07, 0x10, // SLO $10, leaving 10 and A = 1 | 10
+            0x85, 0x12, // STA $12
+        ],
+        8,
+    );
+    assert_eq!(console.read(0x10), 10);
+    assert_eq!(console.read(0x11), 0);
+    assert_eq!(console.read(0x12), 11);
+}
+
+#[test]
+fn unofficial_nops_skip_operands() {
+    let (_, cycles) = run(
+        &[
+            0x1A, // NOP
+            0x80, 0xFF, // NOP #$FF
+            0x04, 0x10, // NOP $10
+            0x0C, 0x00, 0x02, // NOP $0200
+            0xA2, 0x01, // LDX #$01
+            0x1C, 0x00, 0x02, // NOP $0200,X
+            0x1C, 0xFF, 0x02, // NOP $02FF,X, crossing a page
+        ],
+        7,
+    );
+    assert_eq!(cycles, [2, 2, 3, 4, 2, 4, 5]);
+}
+
+#[test]
+fn disassemble_handcrafted_prg() {
+    let prg = [
+        0xA9, 0x33, // LDA #$33
+        0x9D, 0x00, 0x02, // STA $0200,X
+        0x0A, // ASL A
+        0xB1, 0x10, // LDA ($10),Y
+        0xD0, 0xF6, // BNE $8000
+        0x6C, 0xFC, 0xFF, // JMP ($FFFC)
+        0xA7, 0x10, // LAX $10
+        0x4C, 0x00, // A JMP cut short, listed as raw bytes
+    ];
+    let instructions: Vec<(u16, String)> = disassemble(&prg);
+    let expected = [
+        (0x8000, "LDA #$33"),
+        (0x8002, "STA $0200,X"),
+        (0x8005, "ASL A"),
+        (0x8006, "LDA ($10),Y"),
+        (0x8008, "BNE $8000"),
+        (0x800A, "JMP ($FFFC)"),
+        (0x800D, "LAX $10"),
+        (0x800F, ".byte $4C"),
+        (0x8010, ".byte $00"),
+    ];
+    assert_eq!(instructions.len(), expected.len());
+    for ((address, text), (expected_address, expected_text)) in instructions.iter().zip(&expected) {
+        assert_eq!(*address, *expected_address);
+        assert_eq!(text, expected_text);
+    }
+    assert!(disassembly_listing(&prg[..2]) == "$8000  LDA #$33\n");
+}
+
+#[test]
+fn disassemble_rom_skips_header() {
+    let mut rom = make_ines(Mirroring::Horizontal, false, false, 0, 1, 1);
+    rom[16..18].copy_from_slice(&[0xA9, 0x33]);
+    let listing = disassemble_rom(&rom).unwrap();
+    assert!(listing.starts_with("$8000  LDA #$33\n"));
+    assert!(disassemble_rom(&rom[..8]).is_err());
+}
+
+#[test]
+fn step_until_breakpoint_stops_before_instruction() {
+    let mut console = make_console(&PROGRAM, 0, 0);
+    assert_eq!(
+        console.step_until_breakpoint(&mut NullDevice, &mut NullDevice, 1_000_000),
+        None
+    );
+    console.add_breakpoint(0x8002);
+    console.add_breakpoint(0x8001);
+    console.enable_trace_ring(1);
+    let hit = console.step_until_breakpoint(&mut NullDevice, &mut NullDevice, 1_000_000);
+    assert_eq!(hit, Some(BreakReason::Breakpoint(0x8001)));
+    assert_eq!(console.trace_dump()[0].pc, 0x8000);
+    // Continuing runs past the breakpoint, then loops back to the JMP
+    console.remove_breakpoint(0x8001);
+    for _ in 0..2 {
+        let hit = console.step_until_breakpoint(&mut NullDevice, &mut NullDevice, 1_000_000);
+        assert_eq!(hit, Some(BreakReason::Breakpoint(0x8002)));
+    }
+}
+
+#[test]
+fn step_until_breakpoint_skips_stalls_and_reset() {
+    let program = [
+        0xA9, 0x02, // LDA #$02
+        0x8D, 0x14, 0x40, // STA $4014
+        0xEA, // NOP
+        0xEA, // NOP
+        0x4C, 0x07, 0x80, // JMP $8007
+    ];
+    let mut console = make_console(&program, 0, 0);
+    console.add_breakpoint(0x8005);
+    console.add_breakpoint(0x8006);
+    let hit = console.step_until_breakpoint(&mut NullDevice, &mut NullDevice, 1_000_000);
+    assert_eq!(hit, Some(BreakReason::Breakpoint(0x8005)));
+    // The OAM DMA stall doesn't stop at the same breakpoint again
+    let hit = console.step_until_breakpoint(&mut NullDevice, &mut NullDevice, 1_000_000);
+    assert_eq!(hit, Some(BreakReason::Breakpoint(0x8006)));
+    // The JMP loop never reaches a breakpoint
+    let hit = console.step_until_breakpoint(&mut NullDevice, &mut NullDevice, 1000);
+    assert_eq!(hit, Some(BreakReason::CyclesElapsed));
+    // Neither does holding reset, even with a breakpoint at the reset vector
+    console.add_breakpoint(0x8000);
+    console.set_reset_button(true);
+    let hit = console.step_until_breakpoint(&mut NullDevice, &mut NullDevice, 1000);
+    assert_eq!(hit, Some(BreakReason::CyclesElapsed));
+    console.set_reset_button(false);
+    let hit = console.step_until_breakpoint(&mut NullDevice, &mut NullDevice, 1000);
+    assert_eq!(hit, Some(BreakReason::Breakpoint(0x8005)));
+}
+
+#[test]
+fn watches_report_access() {
+    let program = [
+        0xA9, 0x07, // LDA #$07
+        0x85, 0x10, // STA $10
+        0xAD, 0x10, 0x08, // LDA $0810
+        0x4C, 0x07, 0x80, // JMP $8007
+    ];
+    let mut console = make_console(&program, 0, 0);
+    console.add_watch(0x0010, true, true);
+    let hit = console.step_until_breakpoint(&mut NullDevice, &mut NullDevice, 1_000_000);
+    let write = WatchHit {
+        pc: 0x8002,
+        address: 0x0010,
+        value: 0x07,
+        write: true,
+    };
+    assert_eq!(hit, Some(BreakReason::Watch(write)));
+    // The read goes through a mirror of the same address
+    let hit = console.step_until_breakpoint(&mut NullDevice, &mut NullDevice, 1_000_000);
+    let read = WatchHit {
+        pc: 0x8004,
+        address: 0x0810,
+        write: false,
+        ..write
+    };
+    assert_eq!(hit, Some(BreakReason::Watch(read)));
+    console.add_watch(0x0010, false, false);
+    assert_eq!(
+        console.step_until_breakpoint(&mut NullDevice, &mut NullDevice, 1_000_000),
+        None
+    );
+}
+
+#[test]
+fn registers_after_each_instruction() {
+    let program = [
+        0xA9, 0x05, // LDA #$05
+        0xAA, // TAX
+        0xCA, // DEX
+        0xA0, 0x80, // LDY #$80
+        0x48, // PHA
+    ];
+    let mut console = make_console(&program, 0, 0);
+    let start = console.registers();
+    assert_eq!(start.pc, 0x8000);
+    let expected = [
+        (0x8002, 0x05, 0x00, 0x00, 0x24, 0xFD, 2),
+        (0x8003, 0x05, 0x05, 0x00, 0x24, 0xFD, 2),
+        (0x8004, 0x05, 0x04, 0x00, 0x24, 0xFD, 2),
+        (0x8006, 0x05, 0x04, 0x80, 0xA4, 0xFD, 2),
+        (0x8007, 0x05, 0x04, 0x80, 0xA4, 0xFC, 3),
+    ];
+    for (pc, a, x, y, p, sp, cycles) in expected {
+        let result = console.step_detailed(&mut NullDevice, &mut NullDevice);
+        assert_eq!(result.cpu_cycles, cycles);
+        assert_eq!(result.ppu_dots, 3 * cycles);
+        let registers = Registers { pc, a, x, y, p, sp };
+        assert_eq!(console.registers(), registers);
+    }
+    assert_eq!(console.read(0x01FD), 0x05);
+}
+
+#[test]
+fn registers_decode_flags() {
+    let program = [
+        0xA9, 0x00, // LDA #$00
+        0x38, // SEC
+        0xA9, 0x80, // LDA #$80
+        0x69, 0x80, // ADC #$80
+    ];
+    let (console, _) = run(&program, 2);
+    let registers = console.registers();
+    assert!(registers.zero() && registers.carry() && registers.interrupt_disable());
+    assert!(!registers.negative() && !registers.overflow() && !registers.decimal());
+    let (console, _) = run(&program, 4);
+    let registers = console.registers();
+    // 0x80 + 0x80 + 1 overflows, leaving 1 with a carry
+    assert!(registers.carry() && registers.overflow());
+    assert!(!registers.zero() && !registers.negative());
+    let text = registers.to_string();
+    assert_eq!(text, "A:01 X:00 Y:00 P:65 SP:FD PC:8007");
+}
+
+// Runs a program ending with a write to $4014, returning the cycles of that write
+fn oam_dma_cycles(program: &[u8]) -> i32 {
+    let mut console = make_console(program, 0, 0);
+    for i in 0..256 {
+        console.poke(0x0200 + i, i as u8);
+    }
+    let steps = program.len() / 2;
+    for _ in 1..steps {
+        console.step(&mut NullDevice, &mut NullDevice).unwrap();
+    }
+    let cycles = console.step(&mut NullDevice, &mut NullDevice).unwrap();
+    let sprite = console.sprites()[8];
+    assert_eq!((sprite.y, sprite.tile, sprite.x), (0x20, 0x21, 0x23));
+    cycles
+}
+
+#[test]
+fn oam_dma_halts_cpu() {
+    // LDA #$02, then STA $4014
+    let even = oam_dma_cycles(&[0xA9, 0x02, 0x8D, 0x14, 0x40]);
+    assert_eq!(even, 4 + 513);
+    // LDA $00 takes 3 cycles, so the DMA waits an extra cycle to align
+    let odd = oam_dma_cycles(&[0xA5, 0x00, 0xA9, 0x02, 0x8D, 0x14, 0x40]);
+    assert_eq!(odd, 4 + 514);
+}
@@ -0,0 +1,42 @@
+extern crate std;
+
+use std::thread;
+
+use super::{make_console, NullDevice};
+use crate::frames::frame_exchange;
+use crate::ports::{test_pattern, PixelBuffer, TestPattern, VideoDevice};
+
+#[test]
+fn consumer_takes_latest_frame() {
+    let (mut producer, mut consumer) = frame_exchange();
+    let mut out = PixelBuffer::default();
+    assert!(!consumer.take(&mut out));
+    producer.blit_pixels(&test_pattern(TestPattern::ColorBars));
+    producer.blit_pixels(&test_pattern(TestPattern::Gradient));
+    assert!(consumer.take(&mut out));
+    assert_eq!(out.as_ref(), test_pattern(TestPattern::Gradient).as_ref());
+    // Nothing new was blitted, so the frame is left alone
+    assert!(!consumer.take(&mut out));
+    producer.blit_pixels(&test_pattern(TestPattern::PaletteGrid));
+    assert!(consumer.take(&mut out));
+    assert_eq!(
+        out.as_ref(),
+        test_pattern(TestPattern::PaletteGrid).as_ref()
+    );
+}
+
+#[test]
+fn console_runs_on_its_own_thread() {
+    let mut console = make_console(&[0x4C, 0x00, 0x80], 0, 0);
+    let (mut producer, mut consumer) = frame_exchange();
+    let emulation = thread::spawn(move || {
+        for _ in 0..3 {
+            console.step_frame(&mut NullDevice, &mut producer);
+        }
+        console
+    });
+    let console = emulation.join().unwrap();
+    let mut out = PixelBuffer::default();
+    assert!(consumer.take(&mut out));
+    assert_eq!(out.as_ref(), console.capture_frame().as_ref());
+}
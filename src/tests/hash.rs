@@ -0,0 +1,41 @@
+use crate::hash::{adler32, crc32, md5, sha1};
+
+#[test]
+fn crc32_check_value() {
+    assert_eq!(crc32(b""), 0);
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+}
+
+#[test]
+fn adler32_check_value() {
+    assert_eq!(adler32(b""), 1);
+    assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+}
+
+#[test]
+fn md5_check_value() {
+    let empty = [
+        0xD4, 0x1D, 0x8C, 0xD9, 0x8F, 0x00, 0xB2, 0x04, 0xE9, 0x80, 0x09, 0x98, 0xEC, 0xF8, 0x42,
+        0x7E,
+    ];
+    assert_eq!(md5(b""), empty);
+    let fox = [
+        0x9E, 0x10, 0x7D, 0x9D, 0x37, 0x2B, 0xB6, 0x82, 0x6B, 0xD8, 0x1D, 0x35, 0x42, 0xA4, 0x19,
+        0xD6,
+    ];
+    assert_eq!(md5(b"The quick brown fox jumps over the lazy dog"), fox);
+}
+
+#[test]
+fn sha1_check_value() {
+    let empty = [
+        0xDA, 0x39, 0xA3, 0xEE, 0x5E, 0x6B, 0x4B, 0x0D, 0x32, 0x55, 0xBF, 0xEF, 0x95, 0x60, 0x18,
+        0x90, 0xAF, 0xD8, 0x07, 0x09,
+    ];
+    assert_eq!(sha1(b""), empty);
+    let fox = [
+        0x2F, 0xD4, 0xE1, 0xC6, 0x7A, 0x2D, 0x28, 0xFC, 0xED, 0x84, 0x9E, 0xE1, 0xBB, 0x76, 0xE7,
+        0x39, 0x1B, 0x93, 0xEB, 0x12,
+    ];
+    assert_eq!(sha1(b"The quick brown fox jumps over the lazy dog"), fox);
+}
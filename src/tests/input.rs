@@ -0,0 +1,123 @@
+use alloc::vec::Vec;
+
+use super::{make_console, NullDevice};
+use crate::console::Console;
+use crate::controller::ButtonState;
+use crate::input::{FrameInput, InputScript, ScriptError, MAX_RANGE};
+
+// Returns whether or not A is held on the controller read at an address
+fn a_held_on(console: &mut Console, address: u16) -> bool {
+    console.poke(0x4016, 1);
+    console.poke(0x4016, 0);
+    console.read(address) & 1 != 0
+}
+
+// Returns whether or not A is held on the first controller
+fn a_held(console: &mut Console) -> bool {
+    a_held_on(console, 0x4016)
+}
+
+#[test]
+fn script_holds_buttons_over_ranges() {
+    let text = "# comment\n\nframe2 START\nframe4-6 right\nframe5-6 A\n";
+    let script = InputScript::parse(text).unwrap();
+    let inputs: Vec<_> = script.inputs().collect();
+    let frames: Vec<u64> = inputs.iter().map(|(frame, _)| *frame).collect();
+    assert_eq!(frames, [2, 3, 4, 5, 6, 7]);
+    assert!(inputs[0].1.player1.start);
+    // The frame after each range releases everything
+    assert_eq!(inputs[1].1.player1, Default::default());
+    assert!(inputs[2].1.player1.right && !inputs[2].1.player1.a);
+    assert!(inputs[3].1.player1.right && inputs[3].1.player1.a);
+    assert_eq!(inputs[5].1.player1, Default::default());
+}
+
+#[test]
+fn script_errors() {
+    let parse = |text| InputScript::parse(text);
+    assert_eq!(parse("frame1 A\nframe B"), Err(ScriptError::BadFrame(2)));
+    assert_eq!(parse("120 A"), Err(ScriptError::BadFrame(1)));
+    assert_eq!(parse("frame1-x A"), Err(ScriptError::BadFrame(1)));
+    assert_eq!(parse("frame1 JUMP"), Err(ScriptError::UnknownButton(1)));
+    // Ranges have to go forward
+    assert_eq!(parse("frame10-5 A"), Err(ScriptError::BadFrame(1)));
+    // Huge ranges are rejected instead of filling memory
+    let huge = "frame0-18446744073709551615 A";
+    assert_eq!(parse(huge), Err(ScriptError::BadFrame(1)));
+    let huge = "frame0-18446744073709551614 A";
+    assert_eq!(parse(huge), Err(ScriptError::RangeTooLong(1)));
+    let too_long = format!("frame0-{} A", MAX_RANGE);
+    assert_eq!(parse(&too_long), Err(ScriptError::RangeTooLong(1)));
+    let last = "frame18446744073709551614 A";
+    assert!(InputScript::parse(last).is_ok());
+    assert_eq!(
+        parse("frame18446744073709551615 A"),
+        Err(ScriptError::BadFrame(1))
+    );
+}
+
+#[test]
+fn queued_inputs_apply_on_their_frame() {
+    let mut console = make_console(&[0x4C, 0x00, 0x80], 0, 0);
+    let a = FrameInput {
+        player1: ButtonState {
+            a: true,
+            ..ButtonState::default()
+        },
+        ..FrameInput::default()
+    };
+    let start = console.frame();
+    console.queue_input(start + 2, a);
+    console.queue_input(start + 3, FrameInput::default());
+    console.step_frame(&mut NullDevice, &mut NullDevice);
+    assert!(!a_held(&mut console));
+    console.step_frame(&mut NullDevice, &mut NullDevice);
+    assert!(a_held(&mut console));
+    console.step_frame(&mut NullDevice, &mut NullDevice);
+    assert!(!a_held(&mut console));
+    // Inputs are kept to be played again after rolling back
+    let state = console.save_state();
+    console.queue_input(start + 4, a);
+    console.step_frame(&mut NullDevice, &mut NullDevice);
+    assert!(a_held(&mut console));
+    console.load_state(&state).unwrap();
+    console.step_frame(&mut NullDevice, &mut NullDevice);
+    assert!(a_held(&mut console));
+    // Until they're discarded
+    console.discard_inputs_before(start + 5);
+    console.update_controller(ButtonState::default());
+    console.load_state(&state).unwrap();
+    console.step_frame(&mut NullDevice, &mut NullDevice);
+    assert!(!a_held(&mut console));
+}
+
+#[test]
+fn queued_inputs_cover_both_players() {
+    let mut console = make_console(&[0x4C, 0x00, 0x80], 0, 0);
+    let a = ButtonState {
+        a: true,
+        ..ButtonState::default()
+    };
+    let start = console.frame();
+    let second = FrameInput {
+        player2: a,
+        ..FrameInput::default()
+    };
+    let both = FrameInput {
+        player1: a,
+        player2: a,
+        ..FrameInput::default()
+    };
+    console.queue_input(start + 1, second);
+    console.queue_input(start + 2, both);
+    console.queue_input(start + 3, FrameInput::default());
+    console.step_frame(&mut NullDevice, &mut NullDevice);
+    assert!(!a_held(&mut console));
+    assert!(a_held_on(&mut console, 0x4017));
+    console.step_frame(&mut NullDevice, &mut NullDevice);
+    assert!(a_held(&mut console));
+    assert!(a_held_on(&mut console, 0x4017));
+    console.step_frame(&mut NullDevice, &mut NullDevice);
+    assert!(!a_held(&mut console));
+    assert!(!a_held_on(&mut console, 0x4017));
+}
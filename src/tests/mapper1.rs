@@ -0,0 +1,130 @@
+use super::make_ines;
+use crate::cart::{Cart, Mirroring};
+use crate::memory::{MemoryBus, PowerOn};
+use crate::state::{StateError, StateReader, StateWriter};
+
+// Makes a bus with an MMC1 cart, with each 16KB PRG bank starting with its index
+fn make_mmc1_bus() -> MemoryBus {
+    let mut buffer = make_ines(Mirroring::Vertical, false, false, 1, 4, 1);
+    for bank in 0..4 {
+        buffer[16 + bank * 0x4000] = bank as u8;
+    }
+    let cart = Cart::from_bytes(&buffer).unwrap();
+    MemoryBus::with_cart(cart, PowerOn::default())
+}
+
+// Writes a value through the serial port, one bit per instruction
+fn write_serial(m: &mut MemoryBus, address: u16, value: u8) {
+    for bit in 0..5 {
+        m.cpu_write(address, value >> bit);
+        m.step_mapper();
+    }
+}
+
+#[test]
+fn mmc1_prg_banking() {
+    let mut m = make_mmc1_bus();
+    write_serial(&mut m, 0xE000, 2);
+    assert_eq!(m.cpu_read(0x8000), 2);
+    assert_eq!(m.cpu_read(0xC000), 3);
+}
+
+#[test]
+fn mmc1_ignores_consecutive_writes() {
+    let mut m = make_mmc1_bus();
+    // Only the first of these writes is taken into account
+    m.cpu_write(0xE000, 1);
+    m.cpu_write(0xE000, 1);
+    m.step_mapper();
+    for _ in 0..4 {
+        m.cpu_write(0xE000, 0);
+        m.step_mapper();
+    }
+    assert_eq!(m.cpu_read(0x8000), 1);
+}
+
+#[test]
+fn mmc1_reset_keeps_mirroring() {
+    let mut m = make_mmc1_bus();
+    write_serial(&mut m, 0x8000, 0b0_0011);
+    assert_eq!(m.mapper.mirroring_mode(), Mirroring::Horizontal);
+    m.cpu_write(0x8000, 0x80);
+    m.step_mapper();
+    assert_eq!(m.mapper.mirroring_mode(), Mirroring::Horizontal);
+}
+
+#[test]
+fn mmc1_512k_prg_uses_chr_bit() {
+    // SUROM has 512KB of PRG, and CHR RAM
+    let mut buffer = make_ines(Mirroring::Vertical, false, false, 1, 32, 0);
+    for bank in 0..32 {
+        buffer[16 + bank * 0x4000] = bank as u8;
+    }
+    let cart = Cart::from_bytes(&buffer).unwrap();
+    let mut m = MemoryBus::with_cart(cart, PowerOn::default());
+    // The last bank of the first half is fixed at first
+    assert_eq!(m.cpu_read(0xC000), 15);
+    write_serial(&mut m, 0xE000, 2);
+    assert_eq!(m.cpu_read(0x8000), 2);
+    // Bit 4 of the CHR register switches to the upper half
+    write_serial(&mut m, 0xA000, 0x10);
+    assert_eq!(m.cpu_read(0x8000), 18);
+    assert_eq!(m.cpu_read(0xC000), 31);
+    write_serial(&mut m, 0xA000, 0);
+    assert_eq!(m.cpu_read(0xC000), 15);
+}
+
+#[test]
+fn mmc1_384k_prg_stays_in_bounds() {
+    let mut buffer = make_ines(Mirroring::Vertical, false, false, 1, 24, 0);
+    for bank in 0..24 {
+        buffer[16 + bank * 0x4000] = bank as u8;
+    }
+    let cart = Cart::from_bytes(&buffer).unwrap();
+    let mut m = MemoryBus::with_cart(cart, PowerOn::default());
+    write_serial(&mut m, 0xE000, 2);
+    // Without a full upper half, the CHR bit doesn't switch halves
+    write_serial(&mut m, 0xA000, 0x10);
+    assert_eq!(m.cpu_read(0x8000), 2);
+    assert_eq!(m.cpu_read(0xC000), 15);
+    // Saved states can't select an upper half either
+    let mut w = StateWriter::new(b"TEST");
+    m.mapper.save(&mut w);
+    let mut saved = w.finish();
+    let outer = saved.len() - 9;
+    assert_eq!(saved[outer], 0);
+    saved[outer] = 16;
+    let mut r = StateReader::new(&saved, b"TEST").unwrap();
+    assert_eq!(m.mapper.load(&mut r), Err(StateError::InvalidValue));
+}
+
+#[test]
+fn mmc1_shift_count_is_validated() {
+    let mut m = make_mmc1_bus();
+    m.cpu_write(0x8000, 1);
+    let mut w = StateWriter::new(b"TEST");
+    m.mapper.save(&mut w);
+    let mut saved = w.finish();
+    // The count is followed by the upper bank flag
+    let count = saved.len() - 2;
+    assert_eq!(saved[count], 1);
+    saved[count] = 4;
+    let mut r = StateReader::new(&saved, b"TEST").unwrap();
+    assert_eq!(m.mapper.load(&mut r), Ok(()));
+    saved[count] = 5;
+    let mut r = StateReader::new(&saved, b"TEST").unwrap();
+    assert_eq!(m.mapper.load(&mut r), Err(StateError::InvalidValue));
+}
+
+#[test]
+fn mmc1_prg_ram_disable() {
+    let mut m = make_mmc1_bus();
+    m.cpu_write(0x6000, 0x42);
+    assert_eq!(m.cpu_read(0x6000), 0x42);
+    // Bit 4 of the PRG register disables RAM, ignoring writes
+    write_serial(&mut m, 0xE000, 0x10);
+    m.cpu_write(0x6000, 0x24);
+    assert_ne!(m.cpu_read(0x6000), 0x42);
+    write_serial(&mut m, 0xE000, 0);
+    assert_eq!(m.cpu_read(0x6000), 0x42);
+}
@@ -0,0 +1,45 @@
+use super::make_ines;
+use crate::cart::{Cart, Mirroring};
+use crate::memory::{MemoryBus, PowerOn};
+
+#[test]
+fn uxrom_prg_banking() {
+    let mut buffer = make_ines(Mirroring::Vertical, false, false, 2, 4, 0);
+    for bank in 0..4 {
+        buffer[16 + bank * 0x4000] = bank as u8;
+    }
+    let cart = Cart::from_bytes(&buffer).unwrap();
+    let mut m = MemoryBus::with_cart(cart, PowerOn::default());
+    assert_eq!(m.cpu_read(0x8000), 0);
+    assert_eq!(m.cpu_read(0xC000), 3);
+    // The ROM byte written over is 0x02, which keeps the bank as is
+    m.cpu_write(0xC001, 2);
+    assert_eq!(m.cpu_read(0x8000), 2);
+    assert_eq!(m.cpu_read(0xC000), 3);
+    // Without CHR ROM, the cart has CHR RAM
+    m.mapper.write(0x10, 0x42);
+    assert_eq!(m.mapper.read(0x10), 0x42);
+}
+
+#[test]
+fn uxrom_bus_conflicts() {
+    let mut buffer = make_ines(Mirroring::Vertical, false, false, 2, 4, 0);
+    for bank in 0..4 {
+        buffer[16 + bank * 0x4000] = bank as u8;
+    }
+    let mut cart = Cart::from_bytes(&buffer).unwrap();
+    assert!(cart.bus_conflicts);
+    let mut m = MemoryBus::with_cart(cart, PowerOn::default());
+    // The byte at $C001 is 0x02, so writing 3 there selects bank 2
+    m.cpu_write(0xC001, 3);
+    assert_eq!(m.cpu_read(0x8000), 2);
+    // The byte at $C000 is 3, letting any bank through
+    m.cpu_write(0xC000, 1);
+    assert_eq!(m.cpu_read(0x8000), 1);
+    // Without conflicts, the value written is used as is
+    cart = Cart::from_bytes(&buffer).unwrap();
+    cart.bus_conflicts = false;
+    let mut m = MemoryBus::with_cart(cart, PowerOn::default());
+    m.cpu_write(0xC001, 3);
+    assert_eq!(m.cpu_read(0x8000), 3);
+}
@@ -0,0 +1,62 @@
+use super::{make_ines, NullDevice};
+use crate::cart::{Cart, Mirroring};
+use crate::console::Console;
+use crate::memory::{MemoryBus, PowerOn};
+use crate::ports::BufferedAudio;
+
+// Makes a bus with a VRC6b cart, with each 8KB PRG bank starting with its index
+fn make_vrc6b_bus() -> MemoryBus {
+    let mut buffer = make_ines(Mirroring::Vertical, false, false, 26, 4, 1);
+    for bank in 0..8 {
+        buffer[16 + bank * 0x2000] = bank as u8;
+    }
+    let cart = Cart::from_bytes(&buffer).unwrap();
+    MemoryBus::with_cart(cart, PowerOn::default())
+}
+
+#[test]
+fn vrc6_prg_banking() {
+    let mut m = make_vrc6b_bus();
+    m.cpu_write(0x8000, 1);
+    m.cpu_write(0xC000, 5);
+    assert_eq!(m.cpu_read(0x8000), 2);
+    assert_eq!(m.cpu_read(0xA000), 3);
+    assert_eq!(m.cpu_read(0xC000), 5);
+    assert_eq!(m.cpu_read(0xE000), 7);
+}
+
+#[test]
+fn vrc6b_irq_with_swapped_lines() {
+    let mut m = make_vrc6b_bus();
+    m.cpu_write(0xF000, 0xFE);
+    // The control and acknowledge registers are swapped on VRC6b
+    m.cpu_write(0xF002, 0b110);
+    m.step_mapper();
+    assert!(!m.mapper.irq());
+    m.step_mapper();
+    assert!(m.mapper.irq());
+    m.cpu_write(0xF001, 0);
+    assert!(!m.mapper.irq());
+}
+
+#[test]
+fn vrc6_audio_reaches_mixer() {
+    // Loop forever in the fixed bank at $E000
+    let mut buffer = make_ines(Mirroring::Vertical, false, false, 24, 4, 1);
+    buffer[16 + 0xE000..16 + 0xE003].copy_from_slice(&[0x4C, 0x00, 0xE0]);
+    buffer[16 + 0xFFFC..16 + 0xFFFE].copy_from_slice(&0xE000u16.to_le_bytes());
+    let last_sample = |pulse: bool| {
+        let mut console = Console::new(Cart::from_bytes(&buffer).unwrap(), 44100);
+        console.set_audio_filter(None);
+        if pulse {
+            // Hold the first pulse at full volume, ignoring its duty
+            console.poke(0x9000, 0x8F);
+            console.poke(0x9002, 0x80);
+        }
+        let mut audio = BufferedAudio::default();
+        console.step_frame_exact(&mut audio, &mut NullDevice);
+        *audio.samples.last().unwrap()
+    };
+    assert_eq!(last_sample(false), 0.0);
+    assert!(last_sample(true) > 0.0);
+}
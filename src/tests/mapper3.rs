@@ -0,0 +1,19 @@
+use super::make_ines;
+use crate::cart::{Cart, Mirroring};
+use crate::memory::{MemoryBus, PowerOn};
+
+#[test]
+fn cnrom_chr_banking() {
+    let mut buffer = make_ines(Mirroring::Vertical, false, false, 3, 2, 4);
+    for bank in 0..4 {
+        buffer[16 + 0x8000 + bank * 0x2000] = bank as u8;
+    }
+    let cart = Cart::from_bytes(&buffer).unwrap();
+    let mut m = MemoryBus::with_cart(cart, PowerOn::default());
+    assert_eq!(m.mapper.read(0), 0);
+    m.cpu_write(0x8000, 3);
+    assert_eq!(m.mapper.read(0), 3);
+    // CHR ROM can't be written to
+    m.mapper.write(0, 0x42);
+    assert_eq!(m.mapper.read(0), 3);
+}
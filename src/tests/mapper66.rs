@@ -0,0 +1,37 @@
+use super::make_ines;
+use crate::cart::{Cart, Mirroring};
+use crate::memory::{MemoryBus, PowerOn};
+
+// Makes a bus with 4 PRG and CHR banks, each starting with its number
+fn make_banked_bus(mapper: u8) -> MemoryBus {
+    let mut buffer = make_ines(Mirroring::Vertical, false, false, mapper, 8, 4);
+    for bank in 0..4 {
+        let prg = 16 + bank * 0x8000;
+        buffer[prg] = bank as u8;
+        buffer[prg + 0x7FFF] = 0xFF;
+        buffer[16 + 0x20000 + bank * 0x2000] = 0x10 + bank as u8;
+    }
+    let cart = Cart::from_bytes(&buffer).unwrap();
+    MemoryBus::with_cart(cart, PowerOn::default())
+}
+
+#[test]
+fn gxrom_banking() {
+    let mut m = make_banked_bus(66);
+    assert_eq!((m.mapper.read(0x8000), m.mapper.read(0)), (0, 0x10));
+    m.cpu_write(0xFFFF, 0x21);
+    assert_eq!((m.mapper.read(0x8000), m.mapper.read(0)), (2, 0x11));
+    // The value is ANDed with the byte of ROM at the same address
+    m.cpu_write(0x8001, 0x33);
+    assert_eq!((m.mapper.read(0x8000), m.mapper.read(0)), (0, 0x12));
+}
+
+#[test]
+fn color_dreams_banking() {
+    let mut m = make_banked_bus(11);
+    assert_eq!((m.mapper.read(0x8000), m.mapper.read(0)), (0, 0x10));
+    m.cpu_write(0x8001, 0x31);
+    assert_eq!((m.mapper.read(0x8000), m.mapper.read(0)), (1, 0x13));
+    m.cpu_write(0xFFFF, 0x12);
+    assert_eq!((m.mapper.read(0x8000), m.mapper.read(0)), (2, 0x11));
+}
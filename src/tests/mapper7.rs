@@ -0,0 +1,37 @@
+use super::make_ines;
+use crate::cart::{Cart, Mirroring};
+use crate::memory::{MemoryBus, PowerOn};
+
+// Reads the first byte of the nametable at $2000 through PPUDATA
+fn read_nametable(m: &mut MemoryBus) -> u8 {
+    m.cpu_write(0x2006, 0x20);
+    m.cpu_write(0x2006, 0x00);
+    m.cpu_read(0x2007);
+    m.cpu_read(0x2007)
+}
+
+#[test]
+fn axrom_banking_and_mirroring() {
+    let mut buffer = make_ines(Mirroring::Vertical, false, false, 7, 8, 0);
+    for bank in 0..4 {
+        buffer[16 + bank * 0x8000] = bank as u8;
+    }
+    let cart = Cart::from_bytes(&buffer).unwrap();
+    let mut m = MemoryBus::with_cart(cart, PowerOn::default());
+    assert_eq!(m.mapper.read(0x8000), 0);
+    m.cpu_write(0x8000, 2);
+    assert_eq!(m.mapper.read(0x8000), 2);
+    assert_eq!(m.mapper.mirroring_mode(), Mirroring::SingleLower);
+    // Every nametable points to the first one
+    m.cpu_write(0x2006, 0x2C);
+    m.cpu_write(0x2006, 0x00);
+    m.cpu_write(0x2007, 0x42);
+    assert_eq!(read_nametable(&mut m), 0x42);
+    // Bit 4 switches to the second one, keeping the bank
+    m.cpu_write(0x8000, 0x12);
+    assert_eq!(m.mapper.read(0x8000), 2);
+    assert_eq!(m.mapper.mirroring_mode(), Mirroring::SingleUpper);
+    assert_ne!(read_nametable(&mut m), 0x42);
+    m.cpu_write(0x8000, 0x02);
+    assert_eq!(read_nametable(&mut m), 0x42);
+}
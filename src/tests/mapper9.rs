@@ -0,0 +1,65 @@
+use super::{make_ines, NullDevice};
+use crate::cart::{Cart, Mirroring};
+use crate::memory::{MemoryBus, PowerOn};
+use crate::ppu::PPU;
+
+// Makes an MMC2 cart with 128KB of PRG and CHR, where the first byte of
+// each 8KB PRG bank and 4KB CHR bank holds its number
+fn make_mmc2() -> MemoryBus {
+    let mut buffer = make_ines(Mirroring::Vertical, false, false, 9, 8, 16);
+    for bank in 0..16 {
+        buffer[16 + bank * 0x2000] = bank as u8;
+    }
+    let chr_start = 16 + 8 * 0x4000;
+    for bank in 0..32 {
+        buffer[chr_start + bank * 0x1000] = bank as u8;
+    }
+    let cart = Cart::from_bytes(&buffer).unwrap();
+    MemoryBus::with_cart(cart, PowerOn::default())
+}
+
+#[test]
+fn mmc2_prg_banks() {
+    let mut m = make_mmc2();
+    m.cpu_write(0xA000, 3);
+    assert_eq!(m.mapper.read(0x8000), 3);
+    assert_eq!(m.mapper.read(0xA000), 13);
+    assert_eq!(m.mapper.read(0xC000), 14);
+    assert_eq!(m.mapper.read(0xE000), 15);
+    m.cpu_write(0xF000, 1);
+    assert_eq!(m.mapper.mirroring_mode(), Mirroring::Horizontal);
+}
+
+#[test]
+fn mmc2_latches_follow_fetched_tiles() {
+    let mut m = make_mmc2();
+    m.cpu_write(0xB000, 4);
+    m.cpu_write(0xC000, 5);
+    m.cpu_write(0xD000, 6);
+    m.cpu_write(0xE000, 7);
+    // Both latches start on $FE
+    assert_eq!(m.mapper.read(0x0000), 5);
+    assert_eq!(m.mapper.read(0x1000), 7);
+    m.mapper.pattern_fetched(0x0FD8);
+    assert_eq!(m.mapper.read(0x0000), 4);
+    // The first table only reacts to the first row of the tiles
+    m.mapper.pattern_fetched(0x0FE9);
+    assert_eq!(m.mapper.read(0x0000), 4);
+    m.mapper.pattern_fetched(0x1FDB);
+    assert_eq!(m.mapper.read(0x1000), 6);
+    m.mapper.pattern_fetched(0x1FEF);
+    assert_eq!(m.mapper.read(0x1000), 7);
+    // Drawing tile $FD on the first line sets the latch of its table
+    let mut ppu = PPU::new(&mut m);
+    m.cpu_write(0x2006, 0x20);
+    m.cpu_write(0x2006, 0x00);
+    m.cpu_write(0x2007, 0xFD);
+    m.cpu_write(0x2006, 0x00);
+    m.cpu_write(0x2006, 0x00);
+    m.mapper.pattern_fetched(0x0FE8);
+    m.cpu_write(0x2001, 0b0000_1000);
+    while ppu.position() != (1, 0) {
+        ppu.step(&mut m, &mut NullDevice);
+    }
+    assert_eq!(m.mapper.read(0x0000), 4);
+}
@@ -0,0 +1,104 @@
+use alloc::vec::Vec;
+
+use super::{make_bus, make_ines};
+use crate::cart::{Cart, Mirroring};
+use crate::controller::ButtonState;
+use crate::memory::{MemoryBus, PowerOn};
+use crate::state::StateWriter;
+
+// Returns the contents of ram and the PPU state after powering on
+fn power_on_memory(seed: Option<u64>) -> (Vec<u8>, Vec<u8>) {
+    let buffer = make_ines(Mirroring::Horizontal, false, false, 0, 1, 1);
+    let cart = Cart::from_bytes(&buffer).unwrap();
+    let power_on = PowerOn {
+        seed,
+        ..PowerOn::default()
+    };
+    let mut m = MemoryBus::with_cart(cart, power_on);
+    let ram = (0..0x800).map(|address| m.cpu_read(address)).collect();
+    let mut w = StateWriter::new(b"TEST");
+    m.ppu.save(&mut w);
+    (ram, w.finish())
+}
+
+#[test]
+fn seeded_power_on_is_reproducible() {
+    let first = power_on_memory(Some(1234));
+    assert_eq!(first, power_on_memory(Some(1234)));
+    assert_ne!(first, power_on_memory(Some(4321)));
+    assert_ne!(first, power_on_memory(None));
+}
+
+#[test]
+fn microphone_is_bit_2_of_4016() {
+    let buffer = make_ines(Mirroring::Horizontal, false, false, 0, 1, 1);
+    let cart = Cart::from_bytes(&buffer).unwrap();
+    let mut m = MemoryBus::with_cart(cart, PowerOn::default());
+    assert_eq!(m.cpu_read(0x4016) & 0b100, 0);
+    m.microphone = true;
+    assert_eq!(m.cpu_read(0x4016) & 0b100, 0b100);
+}
+
+#[test]
+fn second_controller_is_read_through_4017() {
+    let mut m = make_bus();
+    let player2 = ButtonState {
+        a: true,
+        start: true,
+        left: true,
+        ..ButtonState::default()
+    };
+    m.controller2.set_buttons(player2);
+    m.cpu_write(0x4016, 1);
+    m.cpu_write(0x4016, 0);
+    let bits: Vec<u8> = (0..8).map(|_| m.cpu_read(0x4017) & 1).collect();
+    assert_eq!(bits, [1, 0, 0, 1, 0, 0, 1, 0]);
+    // The first controller has nothing held
+    assert_eq!(m.cpu_read(0x4016) & 1, 0);
+}
+
+#[test]
+fn unmapped_reads_return_open_bus() {
+    let mut m = make_bus();
+    m.cpu_write(0x0010, 0xA5);
+    assert_eq!(m.cpu_read(0x5000), 0xA5);
+    // Only the top bits of PPUSTATUS come from the PPU
+    m.cpu_write(0x2000, 0x1F);
+    assert_eq!(m.cpu_read(0x2002) & 0x1F, 0x1F);
+    // Write only registers read back the bus as well
+    m.cpu_write(0x0010, 0x3C);
+    assert_eq!(m.cpu_read(0x4000), 0x3C);
+    assert_eq!(m.cpu_read(0x2005), 0x3C);
+}
+
+#[test]
+fn dmc_fetch_after_controller_read_loses_a_bit() {
+    let mut m = make_bus();
+    let buttons = ButtonState {
+        a: true,
+        select: true,
+        ..ButtonState::default()
+    };
+    m.controller1.set_buttons(buttons);
+    m.cpu_write(0x4016, 1);
+    m.cpu_write(0x4016, 0);
+    assert_eq!(m.cpu_read(0x4016) & 1, 1);
+    // The read is repeated while the CPU is halted, so B is skipped
+    m.on_last_access = true;
+    m.dmc_fetch(0xC000);
+    assert_eq!(m.cpu_read(0x4016) & 1, 1);
+    // Fetches after other accesses leave the controller alone
+    m.cpu_write(0x4016, 1);
+    m.cpu_write(0x4016, 0);
+    m.dmc_fetch(0xC000);
+    assert_eq!(m.cpu_read(0x4016) & 1, 1);
+    m.cpu_write(0x0000, 0);
+    m.dmc_fetch(0xC000);
+    assert_eq!(m.cpu_read(0x4016) & 1, 0);
+    // So do fetches on an earlier cycle than the controller read
+    m.on_last_access = false;
+    m.dmc_fetch(0xC000);
+    assert_eq!(m.cpu_read(0x4016) & 1, 1);
+    m.dmc_fetch(0xC000);
+    assert_eq!(m.cpu_read(0x4016) & 1, 0);
+}
@@ -1 +1,113 @@
+mod apu;
 mod cart;
+mod cheats;
+mod console;
+mod controller;
+mod cpu;
+mod frames;
+mod hash;
+mod input;
+mod mapper1;
+mod mapper2;
+mod mapper24;
+mod mapper3;
+mod mapper66;
+mod mapper7;
+mod mapper9;
+mod memory;
+mod movie;
+mod nsf;
+mod palette;
+mod png;
+mod ports;
+mod ppu;
+mod ring;
+mod state;
+
+use alloc::vec::Vec;
+use core::iter::repeat_n;
+
+use crate::cart::{Cart, Mirroring};
+use crate::console::Console;
+use crate::memory::{MemoryBus, PowerOn};
+use crate::ports::{AudioDevice, PixelBuffer, VideoDevice};
+
+// Makes an ines file with anything filling the PRG and CHR
+// 0xFF is used as a marker file for the beginning of PRG and CHR
+fn make_ines(
+    mirroring: Mirroring,
+    has_battery: bool,
+    trainer: bool,
+    mapper: u8,
+    prg_chunks: usize,
+    chr_chunks: usize,
+) -> Vec<u8> {
+    let trainer_offset = if trainer { 512 } else { 0 };
+    let mut buffer = {
+        let rom_size = 0x4000 * prg_chunks + 0x2000 * chr_chunks;
+        Vec::with_capacity(16 + trainer_offset + rom_size)
+    };
+    let mut flag6 = 0;
+    if mirroring.is_vertical() {
+        flag6 |= 0b1;
+    }
+    if mirroring == Mirroring::FourScreen {
+        flag6 |= 0b1000;
+    }
+    if has_battery {
+        flag6 |= 0b10;
+    }
+    if trainer {
+        flag6 |= 0b100;
+    }
+    flag6 |= (mapper & 0x0F) << 4;
+    let flag7 = mapper & 0xF0;
+    buffer.push(0x4E);
+    buffer.push(0x45);
+    buffer.push(0x53);
+    buffer.push(0x1A);
+    buffer.push(prg_chunks as u8);
+    buffer.push(chr_chunks as u8);
+    buffer.push(flag6);
+    buffer.push(flag7);
+    buffer.extend(repeat_n(0, 8));
+    buffer.extend(repeat_n(0x1, trainer_offset));
+    buffer.push(0xFF);
+    buffer.extend(repeat_n(0x2, prg_chunks * 0x4000 - 1));
+    // Carts without CHR ROM use CHR RAM instead
+    if chr_chunks > 0 {
+        buffer.push(0xFF);
+        buffer.extend(repeat_n(0x3, chr_chunks * 0x2000 - 1));
+    }
+    buffer
+}
+
+// Makes a memory bus with a simple cart plugged in
+fn make_bus() -> MemoryBus {
+    let buffer = make_ines(Mirroring::Horizontal, false, false, 0, 1, 1);
+    let cart = Cart::from_bytes(&buffer).unwrap();
+    MemoryBus::with_cart(cart, PowerOn::default())
+}
+
+// Makes a console running a program placed at 0x8000, which is also where
+// the reset vector points. The NMI and IRQ vectors point to the addresses given.
+fn make_console(program: &[u8], nmi: u16, irq: u16) -> Console {
+    let mut buffer = make_ines(Mirroring::Horizontal, false, false, 0, 1, 1);
+    let prg = &mut buffer[16..16 + 0x4000];
+    prg[..program.len()].copy_from_slice(program);
+    prg[0x3FFA..0x3FFC].copy_from_slice(&nmi.to_le_bytes());
+    prg[0x3FFC..0x3FFE].copy_from_slice(&0x8000u16.to_le_bytes());
+    prg[0x3FFE..].copy_from_slice(&irq.to_le_bytes());
+    Console::new(Cart::from_bytes(&buffer).unwrap(), 44100)
+}
+
+// A device ignoring both audio and video
+struct NullDevice;
+
+impl AudioDevice for NullDevice {
+    fn push_sample(&mut self, _sample: f32) {}
+}
+
+impl VideoDevice for NullDevice {
+    fn blit_pixels(&mut self, _pixels: &PixelBuffer) {}
+}
@@ -0,0 +1,112 @@
+use super::{make_console, NullDevice};
+use crate::console::Console;
+use crate::controller::ButtonState;
+use crate::movie::{Movie, MovieError};
+
+const MOVIE: &str = "version 3
+emuVersion 22020
+palFlag 0
+romFilename test
+port0 1
+port1 1
+port2 0
+|0|.......A|........||
+|0|R.......|...U....||
+|2|........|........||
+";
+
+// Reads the buttons held on a controller, with A as the lowest bit
+fn read_buttons(console: &mut Console, address: u16) -> u8 {
+    console.poke(0x4016, 1);
+    console.poke(0x4016, 0);
+    (0..8).fold(0, |buttons, i| buttons | ((console.read(address) & 1) << i))
+}
+
+#[test]
+fn fm2_frames_are_parsed() {
+    let movie = Movie::parse(MOVIE).unwrap();
+    assert_eq!(movie.checksum(), None);
+    assert!(!movie.pal());
+    let frames = movie.frames();
+    assert_eq!(frames.len(), 3);
+    assert!(frames[0].player1.a && !frames[0].player1.right);
+    assert!(frames[1].player1.right && frames[1].player2.up);
+    assert!(frames[2].power && !frames[2].reset);
+    // The checksum of an empty ROM
+    let header = "romChecksum base64:1B2M2Y8AsgTpgAmY7PhCfg==\n";
+    let movie = Movie::parse(header).unwrap();
+    assert_eq!(movie.checksum().unwrap()[..4], [0xD4, 0x1D, 0x8C, 0xD9]);
+    let bad = "romChecksum base64:@@\n";
+    assert_eq!(Movie::parse(bad), Err(MovieError::BadChecksum(1)));
+    assert_eq!(Movie::parse("|0|ABC|||"), Err(MovieError::BadInput(1)));
+}
+
+#[test]
+fn movie_drives_controllers() {
+    let mut console = make_console(&[0x4C, 0x00, 0x80], 0, 0);
+    let wrong = Movie::parse("romChecksum base64:1B2M2Y8AsgTpgAmY7PhCfg==").unwrap();
+    assert_eq!(console.play_movie(&wrong), Err(MovieError::WrongRom));
+    console.play_movie(&Movie::parse(MOVIE).unwrap()).unwrap();
+    assert_eq!(read_buttons(&mut console, 0x4016), 0x01);
+    // Live input is ignored while the movie plays
+    console.update_controller(Default::default());
+    console.step_frame(&mut NullDevice, &mut NullDevice);
+    assert_eq!(read_buttons(&mut console, 0x4016), 0x80);
+    assert_eq!(read_buttons(&mut console, 0x4017), 0x10);
+    // The last frame power cycles the console, clearing ram
+    console.poke(0x0010, 0x42);
+    console.step_frame(&mut NullDevice, &mut NullDevice);
+    assert_eq!(console.read(0x0010), 0);
+    assert!(console.movie_playing());
+    console.step_frame(&mut NullDevice, &mut NullDevice);
+    assert!(!console.movie_playing());
+}
+
+#[test]
+fn recorded_movie_plays_back() {
+    let mut console = make_console(&[0x4C, 0x00, 0x80], 0, 0);
+    console.start_recording();
+    let a = ButtonState {
+        a: true,
+        ..ButtonState::default()
+    };
+    let right = ButtonState {
+        right: true,
+        ..ButtonState::default()
+    };
+    console.update_controller(a);
+    console.step_frame(&mut NullDevice, &mut NullDevice);
+    console.update_controller(right);
+    console.update_controller_p2(a);
+    console.step_frame(&mut NullDevice, &mut NullDevice);
+    console.reset();
+    console.update_controller(ButtonState::default());
+    console.step_frame(&mut NullDevice, &mut NullDevice);
+    let movie = console.stop_recording();
+    assert_eq!(movie.frames().len(), 3);
+    assert!(movie.frames()[2].reset);
+    // Writing the movie out keeps everything
+    let text = movie.to_fm2();
+    assert!(text.contains("|0|R.......|.......A||\n"));
+    assert_eq!(Movie::parse(&text), Ok(movie.clone()));
+    console.play_movie(&movie).unwrap();
+    assert_eq!(read_buttons(&mut console, 0x4016), 0x01);
+    console.step_frame(&mut NullDevice, &mut NullDevice);
+    assert_eq!(read_buttons(&mut console, 0x4016), 0x80);
+    assert_eq!(read_buttons(&mut console, 0x4017), 0x01);
+}
+
+#[test]
+fn loading_state_before_movie_stops_it() {
+    let mut console = make_console(&[0x4C, 0x00, 0x80], 0, 0);
+    console.step_frame(&mut NullDevice, &mut NullDevice);
+    let state = console.save_state();
+    for _ in 0..2 {
+        console.step_frame(&mut NullDevice, &mut NullDevice);
+    }
+    console.play_movie(&Movie::parse(MOVIE).unwrap()).unwrap();
+    console.step_frame(&mut NullDevice, &mut NullDevice);
+    console.load_state(&state).unwrap();
+    console.step_frame(&mut NullDevice, &mut NullDevice);
+    assert!(!console.movie_playing());
+}
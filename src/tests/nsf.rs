@@ -0,0 +1,67 @@
+use alloc::vec::Vec;
+
+use super::NullDevice;
+use crate::console::Console;
+use crate::nsf::{Nsf, NsfError};
+use crate::region::Region;
+
+// Makes an NSF loaded at $8000, where INIT stores the song number at $02
+// and counts its calls at $00, while PLAY counts its calls at $01
+fn make_nsf(songs: u8, starting_song: u8, pal: bool) -> Vec<u8> {
+    let mut buffer = b"NESM\x1A\x01".to_vec();
+    buffer.extend([songs, starting_song, 0x00, 0x80, 0x00, 0x80, 0x05, 0x80]);
+    let mut title = b"Test".to_vec();
+    title.resize(0x60, 0);
+    buffer.extend(title);
+    buffer.extend([0x1B, 0x41]);
+    buffer.extend([0; 8]);
+    buffer.extend([0x20, 0x4E, u8::from(pal), 0, 0, 0, 0, 0]);
+    // INIT: STA $02, INC $00, RTS; PLAY: INC $01, RTS
+    buffer.extend([0x85, 0x02, 0xE6, 0x00, 0x60, 0xE6, 0x01, 0x60]);
+    buffer
+}
+
+#[test]
+fn nsf_header_is_parsed() {
+    let nsf = Nsf::from_bytes(&make_nsf(3, 2, true)).unwrap();
+    assert_eq!(nsf.title, "Test");
+    assert_eq!(nsf.song_count, 3);
+    assert_eq!(nsf.starting_song, 2);
+    assert_eq!(nsf.init_address, 0x8000);
+    assert_eq!(nsf.play_address, 0x8005);
+    assert_eq!(nsf.ntsc_speed, 16667);
+    assert_eq!(nsf.bankswitch, None);
+    assert_eq!(nsf.region(), Region::PAL);
+    assert_eq!(nsf.data().len(), 8);
+    let result = Nsf::from_bytes(&make_nsf(3, 2, true)[..0x40]);
+    assert_eq!(result, Err(NsfError::UnrecognisedFormat));
+    assert_eq!(
+        Nsf::from_bytes(&make_nsf(0, 1, false)),
+        Err(NsfError::NoSongs)
+    );
+}
+
+#[test]
+fn nsf_calls_init_then_play() {
+    let nsf = Nsf::from_bytes(&make_nsf(3, 2, false)).unwrap();
+    let mut console = Console::from_nsf(&nsf, 44100);
+    assert_eq!(console.track(), Some(1));
+    assert_eq!(console.track_count(), Some(3));
+    // PLAY is called every 16667 micro seconds, from the header
+    console.step_micros(&mut NullDevice, &mut NullDevice, 1_000_000);
+    assert_eq!(console.peek(0x00), 1);
+    assert_eq!(console.peek(0x01), 59);
+    assert_eq!(console.peek(0x02), 1);
+    // Each frame ends as PLAY gets called, so the last call hasn't run yet
+    for _ in 0..10 {
+        console.step_frame(&mut NullDevice, &mut NullDevice);
+    }
+    assert_eq!(console.peek(0x00), 1);
+    assert_eq!(console.peek(0x01), 68);
+    console.play_track(5);
+    assert_eq!(console.track(), Some(2));
+    console.step_frame(&mut NullDevice, &mut NullDevice);
+    assert_eq!(console.peek(0x00), 1);
+    assert_eq!(console.peek(0x01), 0);
+    assert_eq!(console.peek(0x02), 2);
+}
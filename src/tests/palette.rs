@@ -0,0 +1,40 @@
+use alloc::vec::Vec;
+
+use super::make_bus;
+use crate::palette::{Palette, PaletteError};
+
+// Makes a palette file where each color has its own index, and emphasis, as red
+fn make_pal(emphases: usize) -> Vec<u8> {
+    (0..emphases * 64)
+        .flat_map(|i| [(i % 64) as u8, (i / 64) as u8, 0])
+        .collect()
+}
+
+#[test]
+fn pal_files_are_read() {
+    assert_eq!(
+        Palette::from_pal(&[0; 10]),
+        Err(PaletteError::BadLength(10))
+    );
+    let mut m = make_bus();
+    m.ppu.colors = Palette::from_pal(&make_pal(1)).unwrap();
+    m.cpu_write(0x2006, 0x3F);
+    m.cpu_write(0x2006, 0x00);
+    m.cpu_write(0x2007, 0x21);
+    assert_eq!(m.ppu.pixel_color(0), 0xFF21_0000);
+    // Without colors for each emphasis, colors are darkened
+    m.cpu_write(0x2001, 0b0100_0000);
+    assert_eq!(m.ppu.pixel_color(0), 0xFF18_0000);
+}
+
+#[test]
+fn pal_files_with_emphasis() {
+    let mut m = make_bus();
+    m.ppu.colors = Palette::from_pal(&make_pal(8)).unwrap();
+    m.cpu_write(0x2006, 0x3F);
+    m.cpu_write(0x2006, 0x00);
+    m.cpu_write(0x2007, 0x21);
+    // Green and blue emphasis
+    m.cpu_write(0x2001, 0b1100_0000);
+    assert_eq!(m.ppu.pixel_color(0), 0xFF21_0600);
+}
@@ -0,0 +1,71 @@
+use alloc::vec::Vec;
+
+use crate::hash::crc32;
+use crate::png::encode_png;
+use crate::ports::{Overscan, PixelBuffer, NES_HEIGHT, NES_WIDTH};
+
+// Reads the chunks of a PNG image, checking their checksums
+fn read_chunks(png: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    assert_eq!(png[..8], [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A]);
+    let mut chunks = Vec::new();
+    let mut rest = &png[8..];
+    while !rest.is_empty() {
+        let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+        let crc = u32::from_be_bytes(rest[8 + len..12 + len].try_into().unwrap());
+        assert_eq!(crc32(&rest[4..8 + len]), crc);
+        chunks.push((rest[4..8].try_into().unwrap(), &rest[8..8 + len]));
+        rest = &rest[12 + len..];
+    }
+    chunks
+}
+
+// Reads the data out of a zlib stream made of stored blocks
+fn inflate_stored(stream: &[u8]) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut rest = &stream[2..];
+    loop {
+        let last = rest[0] & 1 != 0;
+        assert_eq!(rest[0] & 6, 0);
+        let len = u16::from_le_bytes([rest[1], rest[2]]) as usize;
+        data.extend_from_slice(&rest[5..5 + len]);
+        rest = &rest[5 + len..];
+        if last {
+            return data;
+        }
+    }
+}
+
+#[test]
+fn solid_frame_decodes_back() {
+    let mut pixels = PixelBuffer::default();
+    for y in 0..NES_HEIGHT {
+        for x in 0..NES_WIDTH {
+            pixels.write(x, y, 0xFF12_3456);
+        }
+    }
+    let top_bottom = Overscan {
+        left: 0,
+        right: 0,
+        ..Overscan::default()
+    };
+    let cases = [
+        (Overscan::none(), 256, 240),
+        (top_bottom, 256, 224),
+        (Overscan::default(), 240, 224),
+    ];
+    for (overscan, width, height) in cases {
+        let png = encode_png(&pixels, overscan);
+        let chunks = read_chunks(&png);
+        let kinds: Vec<&[u8; 4]> = chunks.iter().map(|(kind, _)| kind).collect();
+        assert_eq!(kinds, [b"IHDR", b"IDAT", b"IEND"]);
+        let header = chunks[0].1;
+        assert_eq!(header[..4], (width as u32).to_be_bytes());
+        assert_eq!(header[4..8], (height as u32).to_be_bytes());
+        let image = inflate_stored(chunks[1].1);
+        assert_eq!(image.len(), height * (width * 3 + 1));
+        for row in image.chunks(width * 3 + 1) {
+            assert_eq!(row[0], 0);
+            assert!(row[1..].chunks(3).all(|rgb| rgb == [0x12, 0x34, 0x56]));
+        }
+    }
+}
@@ -0,0 +1,30 @@
+use alloc::vec::Vec;
+
+use crate::ports::{test_pattern, Overscan, TestPattern, NES_HEIGHT, NES_WIDTH};
+
+#[test]
+fn overscan_crops_edges() {
+    let frame = test_pattern(TestPattern::Gradient);
+    let pixels = frame.as_ref();
+    let mut out = Vec::new();
+    let overscan = Overscan::default();
+    assert_eq!((overscan.width(), overscan.height()), (240, 224));
+    overscan.crop_into(&frame, &mut out);
+    assert_eq!(out.len(), 240 * 224);
+    assert_eq!(out[0], pixels[8 * NES_WIDTH + 8]);
+    assert_eq!(out[239], pixels[8 * NES_WIDTH + 247]);
+    assert_eq!(out[240 * 223], pixels[231 * NES_WIDTH + 8]);
+    // Margins can differ on each edge, and the buffer is replaced
+    let overscan = Overscan {
+        top: 16,
+        bottom: 0,
+        left: 0,
+        right: 4,
+    };
+    overscan.crop_into(&frame, &mut out);
+    assert_eq!(out.len(), 252 * 224);
+    assert_eq!(out[252], pixels[17 * NES_WIDTH]);
+    Overscan::none().crop_into(&frame, &mut out);
+    assert_eq!(out, pixels);
+    assert_eq!(out.len(), NES_WIDTH * NES_HEIGHT);
+}
@@ -0,0 +1,538 @@
+use alloc::vec::Vec;
+
+use super::{make_bus, make_ines, NullDevice};
+use crate::cart::{Cart, Mirroring};
+use crate::controller::Zapper;
+use crate::memory::{MemoryBus, PowerOn};
+use crate::ports::{PixelBuffer, VideoDevice, NAMETABLES_HEIGHT, NAMETABLES_WIDTH, NES_WIDTH};
+use crate::ppu::{SpriteInfo, PPU};
+
+#[test]
+fn grayscale_then_emphasis() {
+    let mut m = make_bus();
+    // Set the universal background color to a bright red
+    m.cpu_write(0x2006, 0x3F);
+    m.cpu_write(0x2006, 0x00);
+    m.cpu_write(0x2007, 0x26);
+    // Enable grayscale along with blue emphasis
+    m.cpu_write(0x2001, 0b1000_0001);
+    // Grayscale turns 0x26 into 0x20, then red and green get darkened
+    assert_eq!(m.ppu.pixel_color(0), 0xFFBE_BEFF);
+}
+
+#[test]
+fn emphasis_darkens_other_channels() {
+    let mut m = make_bus();
+    // White, with every channel at full brightness
+    m.cpu_write(0x2006, 0x3F);
+    m.cpu_write(0x2006, 0x00);
+    m.cpu_write(0x2007, 0x30);
+    assert_eq!(m.ppu.pixel_color(0), 0xFFFF_FFFF);
+    // Blue emphasis keeps blue, darkening red and green
+    m.cpu_write(0x2001, 0b1000_0000);
+    assert_eq!(m.ppu.pixel_color(0), 0xFFBE_BEFF);
+    // Red and green emphasis darken every channel
+    m.cpu_write(0x2001, 0b0110_0000);
+    assert_eq!(m.ppu.pixel_color(0), 0xFFBE_BEBE);
+    // PAL swaps red and green
+    m.cpu_write(0x2001, 0b0010_0000);
+    assert_eq!(m.ppu.pixel_color(0), 0xFFFF_BEBE);
+    m.ppu.swap_emphasis = true;
+    assert_eq!(m.ppu.pixel_color(0), 0xFFBE_FFBE);
+}
+
+#[test]
+fn address_write_in_hblank_scrolls_next_line() {
+    let mut m = make_bus();
+    let mut ppu = PPU::new(&mut m);
+    m.cpu_write(0x2001, 0b0000_1000);
+    while ppu.position() != (100, 300) {
+        ppu.step(&mut m, &mut NullDevice);
+    }
+    // Coarse Y 5, coarse X 0, and fine Y 2 from bit 5 of the first write
+    m.cpu_write(0x2006, 0x20);
+    m.cpu_write(0x2006, 0xA0);
+    assert_eq!(m.ppu.v, 0x20A0);
+    // The next line prefetches two tiles, starting from the new address
+    while ppu.position() != (101, 0) {
+        ppu.step(&mut m, &mut NullDevice);
+    }
+    assert_eq!(m.ppu.v, 0x20A2);
+}
+
+#[test]
+fn data_address_wraps_at_15_bits() {
+    let mut m = make_bus();
+    // Increment by 32, starting at the last palette entry
+    m.cpu_write(0x2000, 0b0000_0100);
+    m.cpu_write(0x2006, 0x3F);
+    m.cpu_write(0x2006, 0xFF);
+    for _ in 0..0x800 {
+        m.cpu_read(0x2007);
+    }
+    // 0x10000 was added, going around the 15 bits twice
+    assert_eq!(m.ppu.v, 0x3FFF);
+}
+
+#[test]
+fn control_selects_both_nametable_bits() {
+    let mut m = make_bus();
+    m.cpu_write(0x2000, 0x03);
+    assert_eq!(m.ppu.flg_nametable, 3);
+    m.cpu_write(0x2000, 0x01);
+    assert_eq!(m.ppu.flg_nametable, 1);
+}
+
+#[test]
+fn nametables_follow_mirroring() {
+    // CHR RAM, so tiles can be written through PPUDATA
+    let buffer = make_ines(Mirroring::Horizontal, false, false, 0, 1, 0);
+    let cart = Cart::from_bytes(&buffer).unwrap();
+    let mut m = MemoryBus::with_cart(cart, PowerOn::default());
+    let ppu = PPU::new(&mut m);
+    // Tile 1 uses color 1 for every pixel
+    m.cpu_write(0x2006, 0x00);
+    m.cpu_write(0x2006, 0x10);
+    for _ in 0..8 {
+        m.cpu_write(0x2007, 0xFF);
+    }
+    // Place it in the top left corner of the first nametable
+    m.cpu_write(0x2006, 0x20);
+    m.cpu_write(0x2006, 0x00);
+    m.cpu_write(0x2007, 0x01);
+    m.cpu_write(0x2006, 0x3F);
+    m.cpu_write(0x2006, 0x00);
+    m.cpu_write(0x2007, 0x0F);
+    m.cpu_write(0x2007, 0x16);
+    let mut pixels = vec![0; NAMETABLES_WIDTH * NAMETABLES_HEIGHT];
+    ppu.render_nametables(&m, &mut pixels);
+    let tile = m.ppu.pixel_color(1);
+    let background = m.ppu.pixel_color(0);
+    assert_eq!(pixels[0], tile);
+    assert_eq!(pixels[7 * NAMETABLES_WIDTH + 7], tile);
+    assert_eq!(pixels[8], background);
+    // The second nametable mirrors the first, but not the third
+    assert_eq!(pixels[256], tile);
+    assert_eq!(pixels[240 * NAMETABLES_WIDTH], background);
+}
+
+#[test]
+fn pattern_tables_side_by_side() {
+    let buffer = make_ines(Mirroring::Horizontal, false, false, 0, 1, 0);
+    let cart = Cart::from_bytes(&buffer).unwrap();
+    let mut m = MemoryBus::with_cart(cart, PowerOn::default());
+    let ppu = PPU::new(&mut m);
+    // Tile 0x11 of the second table uses color 3 on its first row
+    m.cpu_write(0x2006, 0x11);
+    m.cpu_write(0x2006, 0x10);
+    m.cpu_write(0x2007, 0xFF);
+    m.cpu_write(0x2006, 0x11);
+    m.cpu_write(0x2006, 0x18);
+    m.cpu_write(0x2007, 0xFF);
+    m.cpu_write(0x2006, 0x3F);
+    m.cpu_write(0x2006, 0x0B);
+    m.cpu_write(0x2007, 0x2A);
+    let mut pixels = PixelBuffer::default();
+    ppu.render_pattern_tables(&m, 2, &mut pixels);
+    let pixels = pixels.as_ref();
+    let tile = m.ppu.pixel_color(0x0B);
+    assert_eq!(pixels[8 * NES_WIDTH + 136], tile);
+    assert_eq!(pixels[8 * NES_WIDTH + 143], tile);
+    assert_eq!(pixels[9 * NES_WIDTH + 136], m.ppu.pixel_color(0));
+    // Nothing is drawn below the tables
+    assert_eq!(pixels[128 * NES_WIDTH], 0);
+}
+
+#[test]
+fn sprites_decode_oam() {
+    let mut m = make_bus();
+    m.ppu.oam.0[..8].copy_from_slice(&[0x10, 0x42, 0b1010_0011, 0x80, 0xEF, 0x01, 0x40, 0x00]);
+    let sprites = m.ppu.sprites();
+    let first = SpriteInfo {
+        y: 0x10,
+        tile: 0x42,
+        palette: 3,
+        behind_background: true,
+        flip_horizontal: false,
+        flip_vertical: true,
+        x: 0x80,
+        height: 8,
+    };
+    assert_eq!(sprites[0], first);
+    assert!(sprites[1].flip_horizontal && !sprites[1].flip_vertical);
+    assert_eq!(sprites[1].palette, 0);
+    // Sprites show up on the 8 lines after their Y position
+    assert!(!sprites[0].on_scanline(0x10));
+    assert!(sprites[0].on_scanline(0x11));
+    assert!(sprites[0].on_scanline(0x18));
+    assert!(!sprites[0].on_scanline(0x19));
+    // Tall sprites cover 16 lines
+    m.cpu_write(0x2000, 0x20);
+    assert!(m.ppu.sprites()[0].on_scanline(0x20));
+}
+
+#[test]
+fn four_screen_tables_are_separate() {
+    let buffer = make_ines(Mirroring::FourScreen, false, false, 0, 1, 1);
+    let cart = Cart::from_bytes(&buffer).unwrap();
+    assert_eq!(cart.mirroring, Mirroring::FourScreen);
+    let mut m = MemoryBus::with_cart(cart, PowerOn::default());
+    for table in 0..4 {
+        m.cpu_write(0x2006, 0x20 + table * 4);
+        m.cpu_write(0x2006, 0x00);
+        m.cpu_write(0x2007, 0xA0 + table);
+    }
+    for table in 0..4 {
+        m.cpu_write(0x2006, 0x20 + table * 4);
+        m.cpu_write(0x2006, 0x00);
+        // The first read only fills the buffer
+        m.cpu_read(0x2007);
+        assert_eq!(m.cpu_read(0x2007), 0xA0 + table);
+    }
+}
+
+#[test]
+fn chr_ram_written_through_ppudata() {
+    for chr_chunks in 0..2 {
+        let buffer = make_ines(Mirroring::Horizontal, false, false, 2, 1, chr_chunks);
+        let cart = Cart::from_bytes(&buffer).unwrap();
+        let mut m = MemoryBus::with_cart(cart, PowerOn::default());
+        m.cpu_write(0x2006, 0x01);
+        m.cpu_write(0x2006, 0x20);
+        let pattern = [0x18, 0x3C, 0x7E, 0xFF];
+        for &byte in &pattern {
+            m.cpu_write(0x2007, byte);
+        }
+        m.cpu_write(0x2006, 0x01);
+        m.cpu_write(0x2006, 0x20);
+        m.cpu_read(0x2007);
+        let read: Vec<u8> = (0..4).map(|_| m.cpu_read(0x2007)).collect();
+        if chr_chunks == 0 {
+            assert_eq!(read, pattern);
+        } else {
+            // CHR ROM keeps its contents
+            assert_eq!(read, [0x03; 4]);
+        }
+    }
+}
+
+#[test]
+fn zapper_senses_bright_pixels() {
+    let mut m = make_bus();
+    let mut ppu = PPU::new(&mut m);
+    // Make the background white, and show it
+    m.cpu_write(0x2006, 0x3F);
+    m.cpu_write(0x2006, 0x00);
+    for _ in 0..4 {
+        m.cpu_write(0x2007, 0x30);
+    }
+    m.cpu_write(0x2001, 0b0000_1010);
+    m.zapper = Some(Zapper::new(128, 100, true));
+    while ppu.position() != (100, 0) {
+        ppu.step(&mut m, &mut NullDevice);
+    }
+    // The trigger is pulled, but nothing was seen yet
+    assert_eq!(m.cpu_read(0x4017) & 0x18, 0x18);
+    while ppu.position() != (110, 0) {
+        ppu.step(&mut m, &mut NullDevice);
+    }
+    assert_eq!(m.cpu_read(0x4017) & 0x18, 0x10);
+    // The light fades after a few scanlines
+    while ppu.position() != (130, 0) {
+        ppu.step(&mut m, &mut NullDevice);
+    }
+    assert_eq!(m.cpu_read(0x4017) & 0x18, 0x18);
+}
+
+// Runs a frame with sprite 0 at some position, returning where the hit flag
+// was first set, if it was.
+fn sprite_zero_hit(tile: u8, x: u8, mask: u8) -> Option<(i32, i32)> {
+    let mut m = make_bus();
+    let mut ppu = PPU::new(&mut m);
+    // CHR is filled with 0x03 after the first byte, so every tile but the
+    // first row of tile 0 has its two rightmost pixels opaque
+    m.ppu.oam.0 = [0xFF; 256];
+    m.ppu.oam.0[..4].copy_from_slice(&[99, tile, 0, x]);
+    m.cpu_write(0x2001, mask);
+    // Skip to the start of the next frame, where the flag is cleared
+    while ppu.position() != (0, 0) {
+        ppu.step(&mut m, &mut NullDevice);
+    }
+    while ppu.position().0 < 240 {
+        ppu.step(&mut m, &mut NullDevice);
+        if m.cpu_read(0x2002) & 0x40 != 0 {
+            return Some(ppu.position());
+        }
+    }
+    None
+}
+
+#[test]
+fn sprite_zero_hit_timing() {
+    // The first row of the sprite is opaque, and hits the background at x 6
+    assert_eq!(sprite_zero_hit(0, 0, 0b0001_1110), Some((100, 7)));
+    assert_eq!(sprite_zero_hit(1, 0, 0b0001_1110), Some((100, 7)));
+    // Each mask for the leftmost column prevents the hit
+    assert_eq!(sprite_zero_hit(1, 0, 0b0001_1100), None);
+    assert_eq!(sprite_zero_hit(1, 0, 0b0001_1010), None);
+    assert_eq!(sprite_zero_hit(1, 8, 0b0001_1010), Some((100, 15)));
+    // Hits only happen with both layers shown
+    assert_eq!(sprite_zero_hit(1, 8, 0b0000_1110), None);
+    assert_eq!(sprite_zero_hit(1, 8, 0b0001_0110), None);
+    // Nothing is hit at x 255
+    assert_eq!(sprite_zero_hit(1, 248, 0b0001_1110), Some((100, 255)));
+    assert_eq!(sprite_zero_hit(1, 249, 0b0001_1110), None);
+}
+
+// Runs the first 60 lines of a frame with 8 sprites at Y 50, followed by
+// some other sprites, returning whether sprite overflow was flagged
+fn sprite_overflow(others: &[u8], bug: bool) -> bool {
+    let mut m = make_bus();
+    let mut ppu = PPU::new(&mut m);
+    ppu.set_overflow_bug(bug);
+    m.ppu.oam.0 = [0xFF; 256];
+    for sprite in 0..8 {
+        m.ppu.oam.0[sprite * 4] = 50;
+    }
+    m.ppu.oam.0[32..32 + others.len()].copy_from_slice(others);
+    m.cpu_write(0x2001, 0b0001_0000);
+    while ppu.position() != (0, 0) {
+        ppu.step(&mut m, &mut NullDevice);
+    }
+    while ppu.position().0 < 60 {
+        ppu.step(&mut m, &mut NullDevice);
+    }
+    m.cpu_read(0x2002) & 0x20 != 0
+}
+
+#[test]
+fn sprite_overflow_hardware_bug() {
+    // After the 9th sprite, the tile of the 10th is compared as a Y position
+    let false_positive = [0xF0, 0, 0, 0, 0xF0, 50, 0, 0];
+    assert!(!sprite_overflow(&false_positive, false));
+    assert!(sprite_overflow(&false_positive, true));
+    // The same goes for the 10th sprite being in range, with its tile out of it
+    let missed = [0xF0, 0, 0, 0, 50, 0, 0, 0];
+    assert!(sprite_overflow(&missed, false));
+    assert!(!sprite_overflow(&missed, true));
+    // A 9th sprite right after the first 8 is always found
+    assert!(sprite_overflow(&[50], true));
+}
+
+// Returns the number of dots in each of the next frames
+fn frame_lengths(m: &mut MemoryBus, ppu: &mut PPU, count: usize) -> Vec<u32> {
+    while ppu.position() != (0, 0) {
+        ppu.step(m, &mut NullDevice);
+    }
+    let mut lengths = Vec::new();
+    for _ in 0..count {
+        let mut dots = 1;
+        ppu.step(m, &mut NullDevice);
+        while ppu.position() != (0, 0) {
+            ppu.step(m, &mut NullDevice);
+            dots += 1;
+        }
+        lengths.push(dots);
+    }
+    lengths
+}
+
+#[test]
+fn odd_frames_skip_a_dot_while_rendering() {
+    let mut m = make_bus();
+    let mut ppu = PPU::new(&mut m);
+    let full = 341 * 262;
+    assert_eq!(frame_lengths(&mut m, &mut ppu, 4), [full; 4]);
+    m.cpu_write(0x2001, 0x08);
+    let lengths = frame_lengths(&mut m, &mut ppu, 4);
+    assert!(
+        lengths == [full, full - 1, full, full - 1] || lengths == [full - 1, full, full - 1, full]
+    );
+    // The parity keeps flipping while rendering is off
+    m.cpu_write(0x2001, 0x00);
+    assert_eq!(frame_lengths(&mut m, &mut ppu, 1), [full]);
+    m.cpu_write(0x2001, 0x10);
+    assert_eq!(frame_lengths(&mut m, &mut ppu, 2), [lengths[3], lengths[2]]);
+}
+
+#[test]
+fn index_buffer_holds_palette_indices() {
+    let mut m = make_bus();
+    let mut ppu = PPU::new(&mut m);
+    m.cpu_write(0x2006, 0x3F);
+    m.cpu_write(0x2006, 0x00);
+    for color in [0x0F, 0x16, 0x00, 0x2A] {
+        m.cpu_write(0x2007, color);
+    }
+    // Scroll back to the top left of the first nametable
+    m.cpu_write(0x2006, 0x00);
+    m.cpu_write(0x2006, 0x00);
+    m.cpu_write(0x2001, 0b0000_1010);
+    // The first row of tile 0 has colors 1 and 3, the others 0 and 3
+    let frame = |m: &mut MemoryBus, ppu: &mut PPU| {
+        while ppu.position() != (0, 0) {
+            ppu.step(m, &mut NullDevice);
+        }
+        while ppu.position() != (241, 2) {
+            ppu.step(m, &mut NullDevice);
+        }
+    };
+    frame(&mut m, &mut ppu);
+    let indices = ppu.index_buffer();
+    assert_eq!(indices[0], 0x16);
+    assert_eq!(indices[6], 0x2A);
+    assert_eq!(indices[NES_WIDTH], 0x0F);
+    assert_eq!(indices[NES_WIDTH + 7], 0x2A);
+    // Grayscale keeps only the brightness of each index
+    m.cpu_write(0x2001, 0b0000_1011);
+    frame(&mut m, &mut ppu);
+    let indices = ppu.index_buffer();
+    assert_eq!(indices[0], 0x10);
+    assert_eq!(indices[6], 0x20);
+    assert_eq!(indices[NES_WIDTH], 0x00);
+}
+
+// Keeps the line number and the 7th pixel of each scanline as it completes,
+// along with the same pixel of each line once the frame is blitted
+#[derive(Default)]
+struct ScanlineRecorder {
+    lines: Vec<(u16, u32)>,
+    blitted: Vec<u32>,
+}
+
+impl VideoDevice for ScanlineRecorder {
+    fn blit_pixels(&mut self, pixels: &PixelBuffer) {
+        let rows = pixels.as_ref().chunks(NES_WIDTH);
+        self.blitted = rows.map(|row| row[6]).collect();
+    }
+
+    fn scanline_complete(&mut self, line: u16, pixels: &[u32]) {
+        assert_eq!(pixels.len(), NES_WIDTH);
+        self.lines.push((line, pixels[6]));
+    }
+}
+
+#[test]
+fn flipped_tall_sprite_rows() {
+    let buffer = make_ines(Mirroring::Horizontal, false, false, 0, 1, 0);
+    let cart = Cart::from_bytes(&buffer).unwrap();
+    let mut m = MemoryBus::with_cart(cart, PowerOn::default());
+    let mut ppu = PPU::new(&mut m);
+    // Each row of tiles 2 and 3 draws its number in binary, with color 1
+    // on tile 2, and color 3 on tile 3
+    m.cpu_write(0x2006, 0x00);
+    m.cpu_write(0x2006, 0x20);
+    for tile in [2, 3] {
+        for row in 0..8 {
+            m.cpu_write(0x2007, row);
+        }
+        for row in 0..8 {
+            m.cpu_write(0x2007, if tile == 3 { row } else { 0 });
+        }
+    }
+    m.cpu_write(0x2006, 0x3F);
+    m.cpu_write(0x2006, 0x00);
+    m.cpu_write(0x2007, 0x0F);
+    m.cpu_write(0x2006, 0x3F);
+    m.cpu_write(0x2006, 0x11);
+    for color in [0x11, 0x12, 0x13] {
+        m.cpu_write(0x2007, color);
+    }
+    m.cpu_write(0x2006, 0x00);
+    m.cpu_write(0x2006, 0x00);
+    m.ppu.oam.0 = [0xFF; 256];
+    m.ppu.oam.0[..4].copy_from_slice(&[40, 2, 0x80, 16]);
+    m.cpu_write(0x2000, 0x20);
+    m.cpu_write(0x2001, 0b0001_0100);
+    while ppu.position() != (0, 0) {
+        ppu.step(&mut m, &mut NullDevice);
+    }
+    while ppu.position() != (241, 2) {
+        ppu.step(&mut m, &mut NullDevice);
+    }
+    let indices = ppu.index_buffer();
+    let line = |y: usize| indices[y * NES_WIDTH + 16..y * NES_WIDTH + 24].to_vec();
+    // Flipped vertically, the sprite starts with the last row of tile 3
+    let row = |tile: u8, row: u8| -> Vec<u8> {
+        (0..8)
+            .map(|x| match (row >> (7 - x)) & 1 {
+                0 => 0x0F,
+                _ if tile == 3 => 0x13,
+                _ => 0x11,
+            })
+            .collect()
+    };
+    let blank = [0x0F; 8].to_vec();
+    assert_eq!(line(40), blank);
+    for sprite_row in 0..16 {
+        let flipped = 15 - sprite_row;
+        let expected = row(2 + flipped / 8, flipped % 8);
+        assert_eq!(line(41 + usize::from(sprite_row)), expected);
+    }
+    assert_eq!(line(57), blank);
+}
+
+#[test]
+fn scanlines_complete_before_blit() {
+    let mut m = make_bus();
+    let mut ppu = PPU::new(&mut m);
+    m.cpu_write(0x2006, 0x3F);
+    m.cpu_write(0x2006, 0x03);
+    m.cpu_write(0x2007, 0x2A);
+    m.cpu_write(0x2006, 0x00);
+    m.cpu_write(0x2006, 0x00);
+    m.cpu_write(0x2001, 0b0000_1010);
+    while ppu.position() != (0, 0) {
+        ppu.step(&mut m, &mut NullDevice);
+    }
+    let mut video = ScanlineRecorder::default();
+    // Turn on grayscale halfway through the frame
+    while ppu.position() != (120, 0) {
+        ppu.step(&mut m, &mut video);
+    }
+    m.cpu_write(0x2001, 0b0000_1011);
+    while ppu.position() != (241, 2) {
+        ppu.step(&mut m, &mut video);
+    }
+    assert_eq!(video.lines.len(), 240);
+    let color = m.ppu.index_color(0x2A);
+    let gray = m.ppu.index_color(0x20);
+    for (i, &(line, pixel)) in video.lines.iter().enumerate() {
+        assert_eq!(usize::from(line), i);
+        assert_eq!(pixel, if i < 120 { color } else { gray });
+        assert_eq!(video.blitted[i], pixel);
+    }
+}
+
+// Reads PPUSTATUS once the PPU reaches a dot of scanline 241, with NMIs
+// enabled, returning whether vblank was seen, whether the NMI happened,
+// and whether the flag was set again by the end of the next dots.
+fn read_status_near_vblank(dot: i32) -> (bool, bool, bool) {
+    let mut m = make_bus();
+    let mut ppu = PPU::new(&mut m);
+    while ppu.position() != (0, 0) {
+        ppu.step(&mut m, &mut NullDevice);
+    }
+    m.cpu_write(0x2000, 0x80);
+    while ppu.position() != (241, dot) {
+        ppu.step(&mut m, &mut NullDevice);
+    }
+    let vblank = m.cpu_read(0x2002) & 0x80 != 0;
+    while ppu.position() != (241, 20) {
+        ppu.step(&mut m, &mut NullDevice);
+    }
+    let nmi = m.cpu.nmi_pending();
+    (vblank, nmi, m.cpu_read(0x2002) & 0x80 != 0)
+}
+
+#[test]
+fn reading_status_near_vblank_suppresses_nmi() {
+    // A dot early, the flag is never set for this frame
+    assert_eq!(read_status_near_vblank(0), (false, false, false));
+    // On the dot the flag is set, and the next one, the NMI is suppressed
+    assert_eq!(read_status_near_vblank(1), (true, false, false));
+    assert_eq!(read_status_near_vblank(2), (true, false, false));
+    // Later reads don't prevent the NMI
+    assert_eq!(read_status_near_vblank(3), (true, true, false));
+    assert_eq!(read_status_near_vblank(10), (true, true, false));
+}
@@ -0,0 +1,20 @@
+use crate::ports::AudioDevice;
+use crate::ring::audio_ring;
+
+#[test]
+fn audio_ring_drops_and_underruns() {
+    let (mut producer, mut consumer) = audio_ring(2);
+    producer.push_sample(0.25);
+    producer.push_sample(0.5);
+    // The ring is full, so this gets dropped
+    producer.push_sample(0.75);
+    assert_eq!(consumer.len(), 2);
+    assert_eq!(consumer.pop(), 0.25);
+    assert_eq!(consumer.pop(), 0.5);
+    assert_eq!(consumer.underruns(), 0);
+    assert_eq!(consumer.pop(), 0.0);
+    assert_eq!(consumer.underruns(), 1);
+    producer.push_sample(1.0);
+    assert_eq!(consumer.pop(), 1.0);
+    assert!(consumer.is_empty());
+}
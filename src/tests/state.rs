@@ -0,0 +1,158 @@
+use alloc::vec::Vec;
+
+use super::{make_bus, make_console, NullDevice};
+use crate::apu::{APUState, APU};
+use crate::cart::Cart;
+use crate::console::Console;
+use crate::ports::PixelBuffer;
+use crate::ppu::{PPUState, PPU};
+use crate::state::{StateError, StateReader, StateWriter};
+
+#[test]
+fn ppu_state_round_trip() {
+    let mut m = make_bus();
+    m.cpu_write(0x2000, 0b1010_0001);
+    m.cpu_write(0x2001, 0b0001_1110);
+    m.cpu_write(0x2006, 0x3F);
+    m.cpu_write(0x2006, 0x01);
+    m.cpu_write(0x2007, 0x2A);
+    m.ppu.oam.0[10] = 0x42;
+    let mut w = StateWriter::new(b"TEST");
+    m.ppu.save(&mut w);
+    let saved = w.finish();
+
+    let mut r = StateReader::new(&saved, b"TEST").unwrap();
+    let loaded = PPUState::load(&mut r).unwrap();
+    let mut w = StateWriter::new(b"TEST");
+    loaded.save(&mut w);
+    assert_eq!(w.finish(), saved);
+    assert_eq!(loaded.oam.0[10], 0x42);
+}
+
+#[test]
+fn state_errors() {
+    let mut w = StateWriter::new(b"TEST");
+    PPUState::new().save(&mut w);
+    let saved = w.finish();
+    assert_eq!(
+        StateReader::new(&saved, b"ELSE").err(),
+        Some(StateError::BadHeader)
+    );
+    let mut r = StateReader::new(&saved[..100], b"TEST").unwrap();
+    assert_eq!(
+        PPUState::load(&mut r).err(),
+        Some(StateError::UnexpectedEnd)
+    );
+}
+
+#[test]
+fn ppu_position_is_validated() {
+    let mut m = make_bus();
+    let mut ppu = PPU::new(&mut m);
+    let mut w = StateWriter::new(b"TEST");
+    ppu.save(&mut w);
+    let saved = w.finish();
+    // The cycle, then the scanline, follow the header and version
+    for (offset, value) in [(5, 341), (5, -1), (9, 262), (9, -1)] {
+        let mut corrupted = saved.clone();
+        corrupted[offset..offset + 4].copy_from_slice(&i32::to_le_bytes(value));
+        let mut r = StateReader::new(&corrupted, b"TEST").unwrap();
+        assert_eq!(ppu.load(&mut r), Err(StateError::InvalidValue));
+    }
+    let mut r = StateReader::new(&saved, b"TEST").unwrap();
+    assert_eq!(ppu.load(&mut r), Ok(()));
+}
+
+#[test]
+fn ppu_flags_are_masked() {
+    let mut w = StateWriter::new(b"TEST");
+    PPUState::new().save(&mut w);
+    let mut saved = w.finish();
+    // w, x and the NMI delay, then 2 booleans and the 16 flags, end the
+    // state along with the OAM address and the read buffer
+    let flags = saved.len() - 18;
+    saved[flags - 5..flags - 2].fill(0xFF);
+    saved[flags..flags + 16].fill(0xFF);
+    let mut r = StateReader::new(&saved, b"TEST").unwrap();
+    let loaded = PPUState::load(&mut r).unwrap();
+    let mut w = StateWriter::new(b"TEST");
+    loaded.save(&mut w);
+    let resaved = w.finish();
+    assert_eq!(resaved[flags - 5..flags - 2], [1, 7, 2]);
+    assert_eq!(resaved[flags], 3);
+    assert!(resaved[flags + 1..flags + 16].iter().all(|&f| f == 1));
+}
+
+#[test]
+fn apu_state_is_sanitized() {
+    let mut w = StateWriter::new(b"TEST");
+    APUState::new().save(&mut w);
+    let saved = w.finish();
+    let mut corrupted = saved.clone();
+    // Enable the first square and the triangle, with out of range steps
+    corrupted[5] = 1;
+    corrupted[7] = 10;
+    corrupted[12] = 9;
+    corrupted[13] = 200;
+    corrupted[49] = 1;
+    corrupted[51] = 10;
+    corrupted[56] = 40;
+    corrupted[58] = 10;
+    let mut m = make_bus();
+    let mut r = StateReader::new(&corrupted, b"TEST").unwrap();
+    assert_eq!(m.apu.load(&mut r), Ok(()));
+    let mut apu = APU::new(44100);
+    for _ in 0..1000 {
+        apu.step(&mut m, &mut NullDevice);
+    }
+    // The frame counter only has 4 and 5 step modes
+    let len = saved.len();
+    corrupted[len - 4] = 6;
+    let mut r = StateReader::new(&corrupted, b"TEST").unwrap();
+    assert_eq!(m.apu.load(&mut r), Err(StateError::InvalidValue));
+}
+
+// Steps 10 frames, returning the last one along with the state at the end
+fn run_frames(console: &mut Console) -> (PixelBuffer, Vec<u8>) {
+    for _ in 0..10 {
+        console.step_frame(&mut NullDevice, &mut NullDevice);
+    }
+    (console.capture_frame(), console.save_state())
+}
+
+#[test]
+fn console_state_round_trip() {
+    let rom = include_bytes!("../../test_roms/palette.nes");
+    let mut console = Console::new(Cart::from_bytes(rom).unwrap(), 44100);
+    console.step_frame(&mut NullDevice, &mut NullDevice);
+    for _ in 0..1000 {
+        console.step(&mut NullDevice, &mut NullDevice).unwrap();
+    }
+    let saved = console.save_state();
+    let (frame, end) = run_frames(&mut console);
+
+    console.load_state(&saved).unwrap();
+    assert_eq!(console.save_state(), saved);
+    let (replayed, replayed_end) = run_frames(&mut console);
+    assert_eq!(frame.as_ref(), replayed.as_ref());
+    assert_eq!(end, replayed_end);
+}
+
+#[test]
+fn console_state_errors() {
+    let rom = include_bytes!("../../test_roms/palette.nes");
+    let mut console = Console::new(Cart::from_bytes(rom).unwrap(), 44100);
+    let saved = console.save_state();
+    let mut other = make_console(&[0xEA], 0x8000, 0x8000);
+    assert_eq!(other.load_state(&saved), Err(StateError::WrongCart));
+    assert_eq!(
+        console.load_state(&console.save_ppu_state()),
+        Err(StateError::BadHeader)
+    );
+    let before = console.save_state();
+    assert_eq!(
+        console.load_state(&saved[..saved.len() - 1]),
+        Err(StateError::UnexpectedEnd)
+    );
+    assert_eq!(console.save_state(), before);
+}